@@ -43,18 +43,70 @@ fn btf() {
 
 fn link() {
     for link in query::LinkInfoIter::default() {
-        let link_type_str = match link.info {
-            query::LinkTypeInfo::RawTracepoint(_) => "raw_tracepoint",
-            query::LinkTypeInfo::Tracing(_) => "tracing",
-            query::LinkTypeInfo::Cgroup(_) => "cgroup",
-            query::LinkTypeInfo::Iter => "iter",
-            query::LinkTypeInfo::NetNs(_) => "netns",
-            query::LinkTypeInfo::Unknown => "unknown",
+        let (link_type_str, target) = match link.info {
+            query::LinkTypeInfo::RawTracepoint(tp) => ("raw_tracepoint", tp.name),
+            query::LinkTypeInfo::Tracing(tracing) => (
+                "tracing",
+                format!(
+                    "attach_type={} target_obj_id={} target_btf_id={}",
+                    tracing.attach_type, tracing.target_obj_id, tracing.target_btf_id
+                ),
+            ),
+            query::LinkTypeInfo::Cgroup(cgroup) => (
+                "cgroup",
+                format!(
+                    "cgroup_id={} attach_type={}",
+                    cgroup.cgroup_id, cgroup.attach_type
+                ),
+            ),
+            query::LinkTypeInfo::Iter => ("iter", String::new()),
+            query::LinkTypeInfo::NetNs(netns) => (
+                "netns",
+                format!("ino={} attach_type={}", netns.ino, netns.attach_type),
+            ),
+            query::LinkTypeInfo::Xdp(xdp) => ("xdp", format!("ifindex={}", xdp.ifindex)),
+            query::LinkTypeInfo::PerfEvent => ("perf_event", String::new()),
+            query::LinkTypeInfo::KprobeMulti(kprobe_multi) => {
+                ("kprobe_multi", format!("count={}", kprobe_multi.count))
+            }
+            query::LinkTypeInfo::StructOps(struct_ops) => {
+                ("struct_ops", format!("map_id={}", struct_ops.map_id))
+            }
+            query::LinkTypeInfo::Netfilter(netfilter) => (
+                "netfilter",
+                format!(
+                    "pf={} hooknum={} priority={}",
+                    netfilter.protocol_family, netfilter.hooknum, netfilter.priority
+                ),
+            ),
+            query::LinkTypeInfo::Tcx(tcx) => (
+                "tcx",
+                format!("ifindex={} attach_type={}", tcx.ifindex, tcx.attach_type),
+            ),
+            query::LinkTypeInfo::UprobeMulti(uprobe_multi) => (
+                "uprobe_multi",
+                format!("count={} pid={}", uprobe_multi.count, uprobe_multi.pid),
+            ),
+            query::LinkTypeInfo::Netkit(netkit) => (
+                "netkit",
+                format!(
+                    "ifindex={} attach_type={}",
+                    netkit.ifindex, netkit.attach_type
+                ),
+            ),
+            query::LinkTypeInfo::SockMap(sockmap) => (
+                "sockmap",
+                format!(
+                    "map_id={} attach_type={}",
+                    sockmap.map_id, sockmap.attach_type
+                ),
+            ),
+            query::LinkTypeInfo::Unknown => ("unknown", String::new()),
         };
 
         println!(
-            "id={:4} prog_id={:4} type={}",
-            link.id, link.prog_id, link_type_str
+            "id={:4} prog_id={:4} type={:<14} {}",
+            link.id, link.prog_id, link_type_str, target
         );
     }
 }
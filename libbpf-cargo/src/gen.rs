@@ -46,7 +46,7 @@ macro_rules! gen_bpf_object_iter {
             type Item = *mut $iter_ty;
 
             fn next(&mut self) -> Option<Self::Item> {
-                self.last = unsafe { $next_fn(self.last, self.obj) };
+                self.last = unsafe { $next_fn(self.obj, self.last) };
 
                 if self.last.is_null() {
                     None
@@ -58,11 +58,15 @@ macro_rules! gen_bpf_object_iter {
     };
 }
 
-gen_bpf_object_iter!(MapIter, libbpf_sys::bpf_map, libbpf_sys::bpf_map__next);
+gen_bpf_object_iter!(
+    MapIter,
+    libbpf_sys::bpf_map,
+    libbpf_sys::bpf_object__next_map
+);
 gen_bpf_object_iter!(
     ProgIter,
     libbpf_sys::bpf_program,
-    libbpf_sys::bpf_program__next
+    libbpf_sys::bpf_object__next_program
 );
 
 /// Run `rustfmt` over `s` and return result
@@ -153,8 +157,7 @@ fn get_prog_name(prog: *const libbpf_sys::bpf_program) -> Result<String> {
 }
 
 fn map_is_mmapable(map: *const libbpf_sys::bpf_map) -> bool {
-    let def = unsafe { libbpf_sys::bpf_map__def(map) };
-    (unsafe { (*def).map_flags } & libbpf_sys::BPF_F_MMAPABLE) > 0
+    (unsafe { libbpf_sys::bpf_map__map_flags(map) } & libbpf_sys::BPF_F_MMAPABLE) > 0
 }
 
 fn map_is_datasec(map: *const libbpf_sys::bpf_map) -> bool {
@@ -166,10 +169,9 @@ fn map_is_datasec(map: *const libbpf_sys::bpf_map) -> bool {
 
 fn map_is_readonly(map: *const libbpf_sys::bpf_map) -> bool {
     assert!(map_is_mmapable(map));
-    let def = unsafe { libbpf_sys::bpf_map__def(map) };
 
     // BPF_F_RDONLY_PROG means readonly from prog side
-    (unsafe { (*def).map_flags } & libbpf_sys::BPF_F_RDONLY_PROG) > 0
+    (unsafe { libbpf_sys::bpf_map__map_flags(map) } & libbpf_sys::BPF_F_RDONLY_PROG) > 0
 }
 
 fn gen_skel_c_skel_constructor(
@@ -555,6 +557,36 @@ fn gen_skel_link_defs(
 
     writeln!(skel, "}}")?;
 
+    write!(
+        skel,
+        r#"
+        impl {}Links {{
+            /// Keep the programs attached when the links are dropped, see
+            /// [`libbpf_rs::Link::disconnect()`].
+            pub fn disconnect(&mut self) {{
+        "#,
+        obj_name
+    )?;
+
+    for prog in ProgIter::new(object) {
+        write!(
+            skel,
+            r#"if let Some(link) = &mut self.{} {{
+                link.disconnect();
+            }}
+            "#,
+            get_prog_name(prog)?
+        )?;
+    }
+
+    write!(
+        skel,
+        r#"
+            }}
+        }}
+        "#,
+    )?;
+
     Ok(())
 }
 
@@ -738,7 +770,8 @@ fn gen_skel_contents(_debug: bool, raw_obj_name: &str, obj_file_path: &Path) ->
                     return Err(libbpf_rs::Error::System(-ret));
                 }}
 
-                let obj = unsafe {{ libbpf_rs::Object::from_ptr(self.obj.take_ptr())? }};
+                let (ptr, log_bufs) = self.obj.take_ptr_and_log_bufs();
+                let obj = unsafe {{ libbpf_rs::Object::from_ptr_with_log_bufs(ptr, log_bufs)? }};
 
                 Ok({name}Skel {{
                     obj,
@@ -463,6 +463,12 @@ fn test_skeleton_basic() {
             let _open_map_mut = open_skel.maps_mut().mymap();
             let _open_prog_mut = open_skel.progs_mut().this_is_my_prog();
 
+            open_skel
+                .progs_mut()
+                .this_is_my_prog()
+                .set_log_buf_size(4096)
+                .expect("failed to set log buffer");
+
             let mut skel = open_skel
                 .load()
                 .expect("failed to load skel");
@@ -470,12 +476,17 @@ fn test_skeleton_basic() {
             // Check that we can grab handles to loaded maps/progs
             let _map = skel.maps().mymap();
             let _prog = skel.progs().this_is_my_prog();
+            // The log buffer is carried over from the open skeleton
+            assert!(skel.progs().this_is_my_prog().log_buf().is_some());
             let _map_mut = skel.maps_mut().mymap();
             let _prog_mut = skel.progs_mut().this_is_my_prog();
 
             // Check that attach() is generated
             skel.attach().expect("failed to attach progs");
 
+            // Check that disconnect() is generated
+            skel.links.disconnect();
+
             // Check that Option<Link> field is generated
             let _mylink = skel.links.this_is_my_prog.unwrap();
         }}
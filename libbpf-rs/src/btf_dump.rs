@@ -0,0 +1,97 @@
+use core::ffi::c_void;
+use std::mem;
+use std::os::raw::c_char;
+use std::ptr;
+
+use crate::*;
+
+/// Renders raw data as text according to BTF type information, using libbpf's `btf_dump`.
+pub(crate) struct BtfDumper {
+    btf: *mut libbpf_sys::btf,
+    dump: *mut libbpf_sys::btf_dump,
+    // Boxed so its address stays stable while libbpf holds on to it
+    #[allow(clippy::box_collection)]
+    out: Box<String>,
+}
+
+impl BtfDumper {
+    /// Load the BTF object with id `btf_id` from the kernel.
+    pub(crate) fn from_btf_id(btf_id: u32) -> Result<Self> {
+        let btf = unsafe { libbpf_sys::btf__load_from_kernel_by_id(btf_id) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(btf as *const _) };
+        if err != 0 {
+            return Err(Error::System(-err as i32));
+        }
+
+        let mut out = Box::new(String::new());
+        let opts = libbpf_sys::btf_dump_opts {
+            sz: mem::size_of::<libbpf_sys::btf_dump_opts>() as libbpf_sys::size_t,
+        };
+        let dump = unsafe {
+            libbpf_sys::btf_dump__new(
+                btf,
+                Some(Self::printf_cb),
+                &mut *out as *mut String as *mut c_void,
+                &opts,
+            )
+        };
+        let err = unsafe { libbpf_sys::libbpf_get_error(dump as *const _) };
+        if err != 0 {
+            unsafe { libbpf_sys::btf__free(btf) };
+            return Err(Error::System(-err as i32));
+        }
+
+        Ok(BtfDumper { btf, dump, out })
+    }
+
+    /// Render `data` as the type with id `type_id`.
+    pub(crate) fn dump(&mut self, type_id: u32, data: &[u8]) -> Result<String> {
+        let opts = libbpf_sys::btf_dump_type_data_opts {
+            sz: mem::size_of::<libbpf_sys::btf_dump_type_data_opts>() as libbpf_sys::size_t,
+            indent_str: ptr::null(),
+            indent_level: 0,
+            compact: false,
+            skip_names: false,
+            emit_zeroes: false,
+            emit_strings: false,
+        };
+
+        self.out.clear();
+        let ret = unsafe {
+            libbpf_sys::btf_dump__dump_type_data(
+                self.dump,
+                type_id,
+                data.as_ptr() as *const c_void,
+                data.len() as libbpf_sys::size_t,
+                &opts,
+            )
+        };
+        if ret < 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret));
+        }
+
+        Ok(mem::take(&mut *self.out))
+    }
+
+    unsafe extern "C" fn printf_cb(
+        ctx: *mut c_void,
+        fmtstr: *const c_char,
+        va_list: *mut libbpf_sys::__va_list_tag,
+    ) {
+        let out = &mut *(ctx as *mut String);
+        match vsprintf::vsprintf(fmtstr, va_list) {
+            Ok(s) => out.push_str(&s),
+            Err(_) => out.push('?'),
+        }
+    }
+}
+
+impl Drop for BtfDumper {
+    fn drop(&mut self) {
+        unsafe {
+            libbpf_sys::btf_dump__free(self.dump);
+            libbpf_sys::btf__free(self.btf);
+        }
+    }
+}
@@ -1,5 +1,6 @@
-use nix::{errno, libc, unistd};
+use nix::{errno, libc};
 use std::io;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
 
 use crate::*;
 
@@ -10,23 +11,26 @@ use crate::*;
 /// Methods require working with raw bytes. You may find libraries such as
 /// [`plain`](https://crates.io/crates/plain) helpful.
 pub struct Iter {
-    fd: i32,
+    fd: OwnedFd,
 }
 
 impl Iter {
     pub fn new(link: &Link) -> Result<Self> {
-        let link_fd = link.get_fd();
-        let fd = unsafe { libbpf_sys::bpf_iter_create(link_fd) };
+        let link_fd = link.get_fd()?;
+        let fd = unsafe { libbpf_sys::bpf_iter_create(link_fd.as_raw_fd()) };
         if fd < 0 {
             return Err(Error::System(errno::errno()));
         }
-        Ok(Self { fd })
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
     }
 }
 
 impl io::Read for Iter {
     fn read(&mut self, buf: &mut [u8]) -> std::result::Result<usize, std::io::Error> {
-        let bytes_read = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut _, buf.len()) };
+        let bytes_read =
+            unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr() as *mut _, buf.len()) };
         if bytes_read < 0 {
             return Err(std::io::Error::last_os_error());
         }
@@ -34,8 +38,63 @@ impl io::Read for Iter {
     }
 }
 
-impl Drop for Iter {
-    fn drop(&mut self) {
-        let _ = unistd::close(self.fd);
+impl AsFd for Iter {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+/// Order in which a cgroup iterator visits cgroups. Maps to `enum bpf_cgroup_iter_order` in
+/// kernel uapi.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CgroupIterOrder {
+    /// Only the cgroup itself.
+    #[default]
+    SelfOnly = libbpf_sys::BPF_CGROUP_ITER_SELF_ONLY,
+    /// The cgroup and its descendants, parents before children.
+    DescendantsPre = libbpf_sys::BPF_CGROUP_ITER_DESCENDANTS_PRE,
+    /// The cgroup and its descendants, children before parents.
+    DescendantsPost = libbpf_sys::BPF_CGROUP_ITER_DESCENDANTS_POST,
+    /// The cgroup and its ancestors, up to the root.
+    AncestorsUp = libbpf_sys::BPF_CGROUP_ITER_ANCESTORS_UP,
+}
+
+/// Options for [`Program::attach_iter()`], restricting what the iterator visits. Maps to
+/// `union bpf_iter_link_info` in kernel uapi.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum IterOpts<'fd> {
+    /// Visit everything, e.g. all tasks for `SEC("iter/task")`.
+    #[default]
+    All,
+    /// Visit the elements of the map `fd`. Required for `SEC("iter/bpf_map_elem")` and
+    /// `SEC("iter/bpf_sk_storage_map")`.
+    Map(BorrowedFd<'fd>),
+    /// Visit the cgroup `fd` and its relatives in `order`, for `SEC("iter/cgroup")`.
+    Cgroup {
+        fd: BorrowedFd<'fd>,
+        order: CgroupIterOrder,
+    },
+    /// Visit only the thread `tid`, for task iterators. Requires Linux 6.1.
+    Thread(u32),
+    /// Visit only the threads of the process `pid`, for task iterators. Requires Linux 6.1.
+    Process(u32),
+}
+
+impl IterOpts<'_> {
+    pub(crate) fn to_link_info(self) -> Option<libbpf_sys::bpf_iter_link_info> {
+        let mut info = libbpf_sys::bpf_iter_link_info::default();
+        match self {
+            IterOpts::All => return None,
+            IterOpts::Map(fd) => info.map.map_fd = fd.as_raw_fd() as u32,
+            IterOpts::Cgroup { fd, order } => {
+                info.cgroup.cgroup_fd = fd.as_raw_fd() as u32;
+                info.cgroup.order = order as u32;
+            }
+            IterOpts::Thread(tid) => info.task.tid = tid,
+            IterOpts::Process(pid) => info.task.pid = pid,
+        }
+
+        Some(info)
     }
 }
@@ -66,30 +66,59 @@
 //!
 //! [See example here](https://github.com/libbpf/libbpf-rs/tree/master/examples/runqslower).
 
+mod btf_dump;
 mod error;
 mod iter;
 mod link;
 mod map;
 mod object;
 mod perf_buffer;
+#[cfg(feature = "tokio")]
+mod perf_buffer_stream;
 mod print;
 mod program;
 pub mod query;
 mod ringbuf;
+#[cfg(feature = "tokio")]
+mod ringbuf_stream;
 /// Used for skeleton -- an end user may not consider this API stable
 #[doc(hidden)]
 pub mod skeleton;
+mod stack_trace;
+mod tc;
+mod typed_map;
+mod usdt;
+mod user_ringbuf;
 mod util;
 
 pub use libbpf_sys;
 
 pub use crate::error::{Error, Result};
-pub use crate::iter::Iter;
+pub use crate::iter::{CgroupIterOrder, Iter, IterOpts};
 pub use crate::link::Link;
-pub use crate::map::{Map, MapFlags, MapType, OpenMap};
+pub use crate::map::{
+    lpm_trie_key, Map, MapArena, MapBatchCursor, MapBatchElemFlags, MapBatchOpts, MapCreateOpts,
+    MapFlags, MapHandle, MapIter, MapKeyIter, MapMmap, MapMmapMut, MapType, OpenMap,
+};
 pub use crate::object::{Object, ObjectBuilder, OpenObject};
-pub use crate::perf_buffer::{PerfBuffer, PerfBufferBuilder};
+pub use crate::perf_buffer::{
+    PerfBuffer, PerfBufferBuilder, PerfBufferStats, SendPerfBuffer, TypedPerfBuffer,
+};
+#[cfg(feature = "tokio")]
+pub use crate::perf_buffer_stream::{PerfBufferEvent, PerfBufferStream};
 pub use crate::print::{get_print, set_print, PrintCallback, PrintLevel};
-pub use crate::program::{OpenProgram, Program, ProgramAttachType, ProgramType};
+pub use crate::program::{
+    AttachAnchor, AttachOrder, CgroupAttachFlags, CgroupAttachOpts, KprobeMultiOpts, KprobeOpts,
+    NetfilterFlags, NetkitOpts, OpenProgram, PerfEventOpts, PerfSample, ProbeAttachMode, Program,
+    ProgramAttachType, ProgramHandle, ProgramType, RawTracepointOpts, TcxOpts, TestRunFlags,
+    TestRunOpts, TestRunOutput, TracepointOpts, UprobeMultiOpts, UprobeOpts, UsdtOpts, XdpFlags,
+};
 pub use crate::ringbuf::{RingBuffer, RingBufferBuilder};
-pub use crate::util::num_possible_cpus;
+#[cfg(feature = "tokio")]
+pub use crate::ringbuf_stream::{RingBufferSample, RingBufferStream};
+pub use crate::stack_trace::{KernelSymbolizer, Symbol, Symbolizer};
+pub use crate::tc::{TcAttachPoint, TcHook, TcHookBuilder};
+pub use crate::typed_map::TypedMap;
+pub use crate::usdt::{usdt_probes, UsdtArg, UsdtProbe};
+pub use crate::user_ringbuf::{UserRingBuffer, UserRingBufferSample};
+pub use crate::util::{num_possible_cpus, online_cpus};
@@ -1,5 +1,12 @@
-use nix::errno;
-use std::path::Path;
+use core::ffi::c_void;
+use nix::{errno, libc};
+use std::ffi::{CStr, OsStr};
+use std::fs;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
 
 use crate::*;
 
@@ -8,12 +15,68 @@ use crate::*;
 /// This struct is used to model ownership. The underlying program will be detached
 /// when this object is dropped if nothing else is holding a reference count.
 pub struct Link {
+    /// Null for links created without libbpf, which are owned by `fd` instead
     ptr: *mut libbpf_sys::bpf_link,
+    fd: Option<OwnedFd>,
+    /// Where a link created without libbpf is pinned, if anywhere
+    pin_path: Option<PathBuf>,
+    /// kretprobe event in tracefs created for the link, removed again once it is detached
+    kretprobe_event: Option<String>,
 }
 
 impl Link {
     pub(crate) fn new(ptr: *mut libbpf_sys::bpf_link) -> Self {
-        Link { ptr }
+        Link {
+            ptr,
+            fd: None,
+            pin_path: None,
+            kretprobe_event: None,
+        }
+    }
+
+    /// Takes ownership of the BPF link `fd`, e.g. as returned by `bpf_link_create()`.
+    pub(crate) fn from_fd(fd: OwnedFd) -> Self {
+        Link {
+            ptr: ptr::null_mut(),
+            fd: Some(fd),
+            pin_path: None,
+            kretprobe_event: None,
+        }
+    }
+
+    /// Remove the kretprobe event `event` when the link is dropped.
+    pub(crate) fn with_kretprobe_event(mut self, event: String) -> Self {
+        self.kretprobe_event = Some(event);
+        self
+    }
+
+    /// Open a link pinned to bpffs at `path`, e.g. by another process, to update or unpin it.
+    ///
+    /// Dropping the returned link only closes it. The attachment stays in place for as long as
+    /// it is pinned.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path_c = util::path_to_cstring(path)?;
+
+        let ptr = unsafe { libbpf_sys::bpf_link__open(path_c.as_ptr()) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Open the link with the given `id`, as listed by e.g. [`query::LinkInfoIter`], to pin,
+    /// update or detach it.
+    ///
+    /// Like with [`Link::open()`], dropping the returned link only closes it.
+    pub fn from_id(id: u32) -> Result<Self> {
+        let fd = unsafe { libbpf_sys::bpf_link_get_fd_by_id(id) };
+        if fd < 0 {
+            return Err(Error::System(errno::errno()));
+        }
+
+        Ok(Link::from_fd(unsafe { OwnedFd::from_raw_fd(fd) }))
     }
 
     /// Takes ownership from pointer.
@@ -27,7 +90,54 @@ impl Link {
 
     /// Replace the underlying prog with `prog`.
     pub fn update_prog(&mut self, prog: Program) -> Result<()> {
-        let ret = unsafe { libbpf_sys::bpf_link__update_program(self.ptr, prog.ptr) };
+        self.update(&prog)
+    }
+
+    /// Atomically replace the attached program with `prog`, e.g. a [`Program`] or a
+    /// [`ProgramHandle`], so that there is no window in which neither program runs.
+    ///
+    /// `prog` must be of the same type and attach type as the program it replaces.
+    pub fn update<P: AsFd>(&mut self, prog: &P) -> Result<()> {
+        let ret = unsafe {
+            libbpf_sys::bpf_link_update(
+                self.get_fd()?.as_raw_fd(),
+                prog.as_fd().as_raw_fd(),
+                ptr::null(),
+            )
+        };
+        if ret != 0 {
+            Err(Error::System(errno::errno()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Replace the struct_ops map of a link created by [`Map::attach_struct_ops()`] with `map`.
+    ///
+    /// Both maps must implement the same struct_ops type, and `map` must have been created with
+    /// `BPF_F_LINK`, which libbpf does for maps in a `.struct_ops.link` section.
+    pub fn update_map(&mut self, map: &Map) -> Result<()> {
+        if map.ptr.is_null() || self.ptr.is_null() {
+            return Err(Error::InvalidInput(
+                "struct_ops maps can only be updated as part of an object".to_string(),
+            ));
+        }
+
+        let ret = unsafe { libbpf_sys::bpf_link__update_map(self.ptr, map.ptr) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            Err(Error::System(-ret))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Detach the program right away, without waiting for the link to be dropped and even if
+    /// it is pinned or otherwise shared. Requires Linux 5.9.
+    ///
+    /// The link stays around in a defunct state until dropped, but can no longer be updated.
+    pub fn detach(&self) -> Result<()> {
+        let ret = unsafe { libbpf_sys::bpf_link_detach(self.get_fd()?.as_raw_fd()) };
         if ret != 0 {
             Err(Error::System(errno::errno()))
         } else {
@@ -44,16 +154,43 @@ impl Link {
     /// additional steps (like pinning BPF program in BPF FS) necessary to ensure
     /// exit of userspace program doesn't trigger automatic detachment and clean up
     /// inside the kernel.
+    ///
+    /// The file descriptor of the link is leaked rather than closed. As the kernel closes it
+    /// when the process exits, which detaches the program, attachments that are to survive the
+    /// process still have to be pinned with [`Link::pin()`].
     pub fn disconnect(&mut self) {
+        // The probe has to stay around for as long as the program is attached to it
+        self.kretprobe_event = None;
+
+        if self.ptr.is_null() {
+            // Keep the link alive by leaking a reference to it, like libbpf leaks its fd
+            if let Some(Ok(fd)) = self.fd.as_ref().map(|fd| fd.try_clone()) {
+                let _ = fd.into_raw_fd();
+            }
+            return;
+        }
+
         unsafe { libbpf_sys::bpf_link__disconnect(self.ptr) }
     }
 
     /// [Pin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
     /// this link to bpffs.
+    ///
+    /// A pinned link keeps the program attached after the `Link` is dropped and the process
+    /// exits, until it is unpinned. Other processes can pick it up from the pin.
     pub fn pin<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let path_c = util::path_to_cstring(path)?;
+        let path_c = util::path_to_cstring(path.as_ref())?;
         let path_ptr = path_c.as_ptr();
 
+        if self.ptr.is_null() {
+            let ret = unsafe { libbpf_sys::bpf_obj_pin(self.get_fd()?.as_raw_fd(), path_ptr) };
+            if ret != 0 {
+                return Err(Error::System(errno::errno()));
+            }
+            self.pin_path = Some(path.as_ref().to_path_buf());
+            return Ok(());
+        }
+
         let ret = unsafe { libbpf_sys::bpf_link__pin(self.ptr, path_ptr) };
         if ret != 0 {
             // Error code is returned negative, flip to positive to match errno
@@ -63,9 +200,33 @@ impl Link {
         }
     }
 
+    /// Returns the path the link is pinned at with [`Link::pin()`], if any.
+    pub fn pin_path(&self) -> Option<PathBuf> {
+        if self.ptr.is_null() {
+            return self.pin_path.clone();
+        }
+
+        let path = unsafe { libbpf_sys::bpf_link__pin_path(self.ptr) };
+        if path.is_null() {
+            return None;
+        }
+
+        let path = unsafe { CStr::from_ptr(path) };
+        Some(PathBuf::from(OsStr::from_bytes(path.to_bytes())))
+    }
+
     /// [Unpin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
     /// from bpffs
     pub fn unpin(&mut self) -> Result<()> {
+        if self.ptr.is_null() {
+            let path = self
+                .pin_path
+                .take()
+                .ok_or_else(|| Error::InvalidInput("link is not pinned".to_string()))?;
+            return fs::remove_file(&path)
+                .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)));
+        }
+
         let ret = unsafe { libbpf_sys::bpf_link__unpin(self.ptr) };
         if ret != 0 {
             // Error code is returned negative, flip to positive to match errno
@@ -75,14 +236,75 @@ impl Link {
         }
     }
 
+    /// Returns information about the link, like what it is attached to, as reported by the
+    /// kernel.
+    pub fn info(&self) -> Result<query::LinkInfo> {
+        // Padding bytes need to be zero as well, see `query::gen_info_impl`
+        let mut info: libbpf_sys::bpf_link_info = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libbpf_sys::bpf_link_info>() as u32;
+
+        let fd = self.get_fd()?.as_raw_fd();
+        let ret = unsafe {
+            libbpf_sys::bpf_obj_get_info_by_fd(
+                fd,
+                &mut info as *mut libbpf_sys::bpf_link_info as *mut c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(Error::System(errno::errno()));
+        }
+
+        query::LinkInfo::from_uapi(fd, info)
+            .ok_or_else(|| Error::Internal("failed to query link info".to_string()))
+    }
+
+    /// Turn the link into its file descriptor, e.g. to pass it to another process over a unix
+    /// socket. The program stays attached for as long as the file descriptor is open.
+    pub fn into_fd(mut self) -> Result<OwnedFd> {
+        if let Some(fd) = self.fd.take() {
+            return Ok(fd);
+        }
+
+        let fd = self
+            .get_fd()?
+            .try_clone_to_owned()
+            .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)))?;
+        // Keep libbpf from detaching the program when destroying the link on drop
+        unsafe { libbpf_sys::bpf_link__disconnect(self.ptr) };
+        self.kretprobe_event = None;
+        Ok(fd)
+    }
+
     /// Returns the file descriptor of the link.
-    pub fn get_fd(&self) -> i32 {
-        unsafe { libbpf_sys::bpf_link__fd(self.ptr) }
+    ///
+    /// Not all links have one, e.g. USDT links consist of several links internally.
+    pub fn get_fd(&self) -> Result<BorrowedFd<'_>> {
+        if let Some(fd) = &self.fd {
+            return Ok(fd.as_fd());
+        }
+
+        let fd = unsafe { libbpf_sys::bpf_link__fd(self.ptr) };
+        if fd < 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-fd));
+        }
+
+        // The fd is owned by the link and valid for as long as it exists
+        Ok(unsafe { BorrowedFd::borrow_raw(fd) })
     }
 }
 
 impl Drop for Link {
     fn drop(&mut self) {
-        let _ = unsafe { libbpf_sys::bpf_link__destroy(self.ptr) };
+        // Links created without libbpf are detached by closing `fd`
+        if !self.ptr.is_null() {
+            let _ = unsafe { libbpf_sys::bpf_link__destroy(self.ptr) };
+        }
+
+        // Only possible once the perf event is closed along with the link
+        if let Some(event) = &self.kretprobe_event {
+            let _ = program::remove_kretprobe_event(event);
+        }
     }
 }
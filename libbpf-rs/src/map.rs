@@ -1,13 +1,24 @@
 use core::ffi::c_void;
 use std::convert::TryFrom;
-use std::path::Path;
+use std::ffi::{CStr, OsStr};
+use std::fs;
+use std::marker::PhantomData;
+use std::mem;
+use std::net::IpAddr;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
 use std::ptr;
+use std::slice;
 
 use bitflags::bitflags;
-use nix::{errno, unistd};
+use nix::{errno, libc};
 use num_enum::TryFromPrimitive;
+use plain::Plain;
 use strum_macros::Display;
 
+use crate::btf_dump::BtfDumper;
 use crate::*;
 
 /// Represents a parsed but not yet loaded BPF map.
@@ -25,6 +36,8 @@ impl OpenMap {
         OpenMap { ptr }
     }
 
+    /// Offload the map to the network device with index `idx`, for use by programs offloaded
+    /// to the same device with [`OpenProgram::set_ifindex()`].
     pub fn set_map_ifindex(&mut self, idx: u32) {
         unsafe { libbpf_sys::bpf_map__set_ifindex(self.ptr, idx) };
     }
@@ -46,6 +59,16 @@ impl OpenMap {
         Ok(())
     }
 
+    /// Returns the maximum number of entries the map will be created with.
+    pub fn max_entries(&self) -> u32 {
+        unsafe { libbpf_sys::bpf_map__max_entries(self.ptr) }
+    }
+
+    /// Set the maximum number of entries of the map before it is created, overriding the value
+    /// compiled into the BPF object.
+    ///
+    /// For [`MapType::RingBuf`] maps this is the size of the buffer in bytes. libbpf rounds it up
+    /// to a power of two multiple of the page size when the map is created.
     pub fn set_max_entries(&mut self, count: u32) -> Result<()> {
         let ret = unsafe { libbpf_sys::bpf_map__set_max_entries(self.ptr, count) };
 
@@ -57,8 +80,123 @@ impl OpenMap {
         Ok(())
     }
 
-    pub fn set_inner_map_fd(&mut self, inner: &Map) {
-        unsafe { libbpf_sys::bpf_map__set_inner_map_fd(self.ptr, inner.fd()) };
+    /// Returns the `BPF_F_*` flags the map will be created with.
+    pub fn map_flags(&self) -> u32 {
+        unsafe { libbpf_sys::bpf_map__map_flags(self.ptr) }
+    }
+
+    /// Set the `BPF_F_*` flags of the map before it is created, e.g. `BPF_F_NO_PREALLOC`,
+    /// overriding the flags compiled into the BPF object.
+    pub fn set_map_flags(&mut self, flags: u32) -> Result<()> {
+        let ret = unsafe { libbpf_sys::bpf_map__set_map_flags(self.ptr, flags) };
+
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the NUMA node the map will be allocated on.
+    pub fn numa_node(&self) -> u32 {
+        unsafe { libbpf_sys::bpf_map__numa_node(self.ptr) }
+    }
+
+    /// Allocate the map on NUMA node `numa_node` when it is created.
+    ///
+    /// This only takes effect if `BPF_F_NUMA_NODE` is part of the map flags, see
+    /// [`OpenMap::set_map_flags()`].
+    pub fn set_numa_node(&mut self, numa_node: u32) -> Result<()> {
+        let ret = unsafe { libbpf_sys::bpf_map__set_numa_node(self.ptr, numa_node) };
+
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the `map_extra` value of the map.
+    pub fn map_extra(&self) -> u64 {
+        unsafe { libbpf_sys::bpf_map__map_extra(self.ptr) }
+    }
+
+    /// Set the `map_extra` value of the map before it is created.
+    ///
+    /// The meaning depends on the map type. For a [`MapType::BloomFilter`] the lower 4 bits
+    /// are the number of hash functions to use. If unset, the kernel picks a default of 5.
+    pub fn set_map_extra(&mut self, map_extra: u64) -> Result<()> {
+        let ret = unsafe { libbpf_sys::bpf_map__set_map_extra(self.ptr, map_extra) };
+
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret));
+        }
+
+        Ok(())
+    }
+
+    /// Use `inner` as the prototype for the inner maps of a [`MapType::ArrayOfMaps`] or
+    /// [`MapType::HashOfMaps`] map.
+    ///
+    /// The kernel only needs the prototype to verify BPF programs accessing inner maps, so any
+    /// map with the same type, key size, value size and flags will do.
+    pub fn set_inner_map_fd(&mut self, inner: &Map) -> Result<()> {
+        let ret =
+            unsafe { libbpf_sys::bpf_map__set_inner_map_fd(self.ptr, inner.fd().as_raw_fd()) };
+
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the inner map prototype of a map-in-map declared with BTF, if any.
+    ///
+    /// libbpf creates the prototype itself from the `values` type in the BPF object. It can be
+    /// adjusted before load, e.g. with [`OpenMap::set_max_entries()`].
+    pub fn inner_map(&mut self) -> Option<OpenMap> {
+        let ptr = unsafe { libbpf_sys::bpf_map__inner_map(self.ptr) };
+        if ptr.is_null() {
+            None
+        } else {
+            Some(OpenMap::new(ptr))
+        }
+    }
+
+    /// Set the path the map is pinned at upon load.
+    ///
+    /// If a compatible map is already pinned at `path`, it is reused instead of creating a new
+    /// one. This overrides any path derived from [`ObjectBuilder::pin_root_path()`].
+    pub fn set_pin_path<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path_c = util::path_to_cstring(path)?;
+
+        let ret = unsafe { libbpf_sys::bpf_map__set_pin_path(self.ptr, path_c.as_ptr()) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret));
+        }
+
+        Ok(())
+    }
+
+    /// Reuse an existing map referred to by `fd` for `self` instead of creating a new one.
+    ///
+    /// The existing map must be compatible, i.e. have the same type, key size, value size,
+    /// max entries and flags. libbpf duplicates `fd`, so the caller keeps ownership of it.
+    pub fn reuse_fd(&mut self, fd: BorrowedFd<'_>) -> Result<()> {
+        let ret = unsafe { libbpf_sys::bpf_map__reuse_fd(self.ptr, fd.as_raw_fd()) };
+
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret));
+        }
+
+        Ok(())
     }
 
     /// Reuse an already-pinned map for `self`.
@@ -70,19 +208,72 @@ impl OpenMap {
             return Err(Error::System(errno::errno()));
         }
 
-        let ret = unsafe { libbpf_sys::bpf_map__reuse_fd(self.ptr, fd) };
+        // `fd` is closed when dropped, regardless of if `bpf_map__reuse_fd` succeeded or failed
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        self.reuse_fd(fd.as_fd())
+    }
+}
 
-        // Always close `fd` regardless of if `bpf_map__reuse_fd` succeeded or failed
-        //
-        // Ignore errors b/c can't really recover from failure
-        let _ = unistd::close(fd);
+/// Build a key for a [`MapType::LpmTrie`] map matching `prefix_len` leading bits of `addr`.
+///
+/// The key has the layout of `struct bpf_lpm_trie_key`: the prefix length in host byte order
+/// followed by the address in network byte order. The map's key size must therefore be 8 for
+/// IPv4 and 20 for IPv6 addresses.
+pub fn lpm_trie_key(addr: IpAddr, prefix_len: u32) -> Result<Vec<u8>> {
+    let (octets, max_prefix_len) = match addr {
+        IpAddr::V4(addr) => (addr.octets().to_vec(), 32),
+        IpAddr::V6(addr) => (addr.octets().to_vec(), 128),
+    };
+
+    if prefix_len > max_prefix_len {
+        return Err(Error::InvalidInput(format!(
+            "prefix length {} exceeds {} bits of {}",
+            prefix_len, max_prefix_len, addr
+        )));
+    }
 
-        if ret != 0 {
-            return Err(Error::System(-ret));
+    let mut key = prefix_len.to_ne_bytes().to_vec();
+    key.extend_from_slice(&octets);
+    Ok(key)
+}
+
+/// Kernel internal error code for unsupported operations, which leaks to userspace e.g. for
+/// batch operations on map types that do not implement them.
+const ENOTSUPP: i32 = 524;
+
+/// Return the size of one value including padding for interacting with per-cpu
+/// maps. The values are aligned to 8 bytes.
+fn percpu_aligned_value_size(val_size: usize) -> usize {
+    util::roundup(val_size, 8)
+}
+
+/// Split the raw buffer of a per-cpu lookup into one value per cpu, dropping the padding
+/// between them.
+fn split_percpu_values(raw: &[u8], val_size: usize) -> Vec<Vec<u8>> {
+    raw.chunks_exact(percpu_aligned_value_size(val_size))
+        .map(|chunk| chunk[..val_size].to_vec())
+        .collect()
+}
+
+/// Pack one value per cpu into the padded layout the kernel expects for a per-cpu update.
+fn pack_percpu_values(values: &[Vec<u8>], val_size: usize) -> Result<Vec<u8>> {
+    let aligned_val_size = percpu_aligned_value_size(val_size);
+    let mut value_buf = vec![0; values.len() * aligned_val_size];
+
+    for (i, val) in values.iter().enumerate() {
+        if val.len() != val_size {
+            return Err(Error::InvalidInput(format!(
+                "value size for cpu {} is {} != {}",
+                i,
+                val.len(),
+                val_size
+            )));
         }
 
-        Ok(())
+        value_buf[(i * aligned_val_size)..(i * aligned_val_size + val_size)].copy_from_slice(val);
     }
+
+    Ok(value_buf)
 }
 
 /// Represents a created map.
@@ -95,7 +286,7 @@ pub struct Map {
     ty: libbpf_sys::bpf_map_type,
     key_size: u32,
     value_size: u32,
-    ptr: *mut libbpf_sys::bpf_map,
+    pub(crate) ptr: *mut libbpf_sys::bpf_map,
 }
 
 impl Map {
@@ -122,8 +313,12 @@ impl Map {
     }
 
     /// Returns a file descriptor to the underlying map.
-    pub fn fd(&self) -> i32 {
-        self.fd
+    ///
+    /// The file descriptor is owned by `self`. Use [`BorrowedFd::try_clone_to_owned()`] or
+    /// [`MapHandle::try_from()`] to get one that outlives it.
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        // `fd` is valid for as long as the map exists
+        unsafe { BorrowedFd::borrow_raw(self.fd) }
     }
 
     pub fn map_type(&self) -> MapType {
@@ -143,18 +338,11 @@ impl Map {
         self.value_size
     }
 
-    /// Return the size of one value including padding for interacting with per-cpu
-    /// maps. The values are aligned to 8 bytes.
-    fn percpu_aligned_value_size(&self) -> usize {
-        let val_size = self.value_size() as usize;
-        return util::roundup(val_size, 8);
-    }
-
     /// Returns the size of the buffer needed for a lookup/update of a per-cpu map.
     fn percpu_buffer_size(&self) -> Result<usize> {
-        let aligned_val_size = self.percpu_aligned_value_size();
+        let aligned_val_size = percpu_aligned_value_size(self.value_size() as usize);
         let ncpu = util::num_possible_cpus()?;
-        return Ok(ncpu * aligned_val_size);
+        Ok(ncpu * aligned_val_size)
     }
 
     /// [Pin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
@@ -163,6 +351,16 @@ impl Map {
         let path_c = util::path_to_cstring(path)?;
         let path_ptr = path_c.as_ptr();
 
+        if self.ptr.is_null() {
+            // Not part of an object, so there is no libbpf state to keep track of the pin
+            let ret = unsafe { libbpf_sys::bpf_obj_pin(self.fd, path_ptr) };
+            return if ret != 0 {
+                Err(Error::System(errno::errno()))
+            } else {
+                Ok(())
+            };
+        }
+
         let ret = unsafe { libbpf_sys::bpf_map__pin(self.ptr, path_ptr) };
         if ret != 0 {
             // Error code is returned negative, flip to positive to match errno
@@ -172,9 +370,38 @@ impl Map {
         }
     }
 
+    /// Returns whether the map was pinned by libbpf, e.g. because of its pin path.
+    ///
+    /// Always `false` for a [`MapHandle`], as libbpf does not track those.
+    pub fn is_pinned(&self) -> bool {
+        !self.ptr.is_null() && unsafe { libbpf_sys::bpf_map__is_pinned(self.ptr) }
+    }
+
+    /// Returns the path the map is pinned at by libbpf, if any.
+    ///
+    /// Always `None` for a [`MapHandle`], as libbpf does not track those.
+    pub fn pin_path(&self) -> Option<PathBuf> {
+        if self.ptr.is_null() {
+            return None;
+        }
+
+        let path = unsafe { libbpf_sys::bpf_map__pin_path(self.ptr) };
+        if path.is_null() {
+            return None;
+        }
+
+        let path = unsafe { CStr::from_ptr(path) };
+        Some(PathBuf::from(OsStr::from_bytes(path.to_bytes())))
+    }
+
     /// [Unpin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
     /// from bpffs
     pub fn unpin<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        if self.ptr.is_null() {
+            return fs::remove_file(path.as_ref())
+                .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)));
+        }
+
         let path_c = util::path_to_cstring(path)?;
         let path_ptr = path_c.as_ptr();
 
@@ -187,12 +414,294 @@ impl Map {
         }
     }
 
+    /// Register the struct_ops implementation described by a [`MapType::StructOps`] map, e.g. a
+    /// TCP congestion control algorithm.
+    ///
+    /// The implementation is unregistered again when the returned [`Link`] is dropped, unless
+    /// it is disconnected or pinned. Use [`Link::update_map()`] to switch to another
+    /// implementation of the same type without unregistering in between.
+    ///
+    /// Only maps in a `SEC(".struct_ops.link")` section are attached with a kernel link, which
+    /// can be pinned and updated, and which keeps the implementation registered while any
+    /// process holds its fd. Maps in a `SEC(".struct_ops")` section are registered directly,
+    /// and the returned link only unregisters them on drop.
+    pub fn attach_struct_ops(&self) -> Result<Link> {
+        if self.map_type() != MapType::StructOps {
+            return Err(Error::InvalidInput(format!(
+                "attach_struct_ops() is only supported for struct_ops maps (type of the map is {})",
+                self.map_type()
+            )));
+        }
+
+        if self.ptr.is_null() {
+            return Err(Error::InvalidInput(
+                "struct_ops maps can only be attached as part of an object".to_string(),
+            ));
+        }
+
+        let ptr = unsafe { libbpf_sys::bpf_map__attach_struct_ops(self.ptr) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Freeze the map as read-only from user space.
+    ///
+    /// Entries from a frozen map can no longer be updated or deleted with the `bpf()` system
+    /// call. This operation is not reversible, and makes it possible to treat the contents of
+    /// the map as constant once userspace is done initializing it. BPF programs can still
+    /// modify the map unless it is also marked read-only for programs (e.g. `.rodata`).
+    pub fn freeze(&mut self) -> Result<()> {
+        let ret = unsafe { libbpf_sys::bpf_map_freeze(self.fd) };
+        if ret != 0 {
+            Err(Error::System(errno::errno()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Map the contents of this map into userspace for reading.
+    ///
+    /// Only [`MapType::Array`] maps created with the `BPF_F_MMAPABLE` flag can be mapped. Reading
+    /// through the mapping does not require any syscalls, which makes it a good fit for
+    /// high-rate counters. Use [`Map::mmap_mut()`] to also write through the mapping.
+    pub fn mmap(&self) -> Result<MapMmap<'_>> {
+        let raw = self.mmap_raw(libc::PROT_READ)?;
+        Ok(MapMmap {
+            raw,
+            _map: PhantomData,
+        })
+    }
+
+    /// Map the contents of this map into userspace for reading and writing.
+    ///
+    /// Same as [`Map::mmap()`], except that the returned mapping is also writable. This fails for
+    /// frozen maps.
+    pub fn mmap_mut(&mut self) -> Result<MapMmapMut<'_>> {
+        let raw = self.mmap_raw(libc::PROT_READ | libc::PROT_WRITE)?;
+        Ok(MapMmapMut {
+            raw,
+            _map: PhantomData,
+        })
+    }
+
+    /// Access the memory region of a [`MapType::Arena`] map.
+    ///
+    /// libbpf already maps the arenas of a loaded [`Object`], which is reused here. Arenas
+    /// created with [`MapHandle::create()`] are mapped upon calling this, at the address given
+    /// as `map_extra` if any. Such arenas must be created with `BPF_F_MMAPABLE`, and
+    /// `max_entries` is their size in pages. Mapping fails with `EEXIST` if something else,
+    /// e.g. the arena of another handle to the same map, already occupies that address.
+    pub fn arena(&mut self) -> Result<MapArena<'_>> {
+        if self.map_type() != MapType::Arena {
+            return Err(Error::InvalidInput(format!(
+                "arena() is only supported for arena maps (type of the map is {})",
+                self.map_type()
+            )));
+        }
+
+        if !self.ptr.is_null() {
+            let mut size: libbpf_sys::size_t = 0;
+            let ptr = unsafe { libbpf_sys::bpf_map__initial_value(self.ptr, &mut size) };
+            if ptr.is_null() {
+                return Err(Error::Internal("arena is not mapped".to_string()));
+            }
+
+            return Ok(MapArena {
+                ptr: ptr as *mut u8,
+                len: size as usize,
+                owned: false,
+                _map: PhantomData,
+            });
+        }
+
+        let info = self.raw_info()?;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let len = info.max_entries as usize * page_size;
+
+        // The kernel insists on the arena being mapped at its start address, like libbpf does.
+        // Never replace whatever is mapped there already, as that may still be in use.
+        let flags = if info.map_extra != 0 {
+            libc::MAP_SHARED | libc::MAP_FIXED_NOREPLACE
+        } else {
+            libc::MAP_SHARED
+        };
+        let ptr = unsafe {
+            libc::mmap(
+                info.map_extra as *mut c_void,
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                self.fd,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Error::System(errno::errno()));
+        }
+
+        // Kernels before 4.17 treat MAP_FIXED_NOREPLACE as a mere hint
+        if info.map_extra != 0 && ptr as u64 != info.map_extra {
+            unsafe { libc::munmap(ptr, len) };
+            return Err(Error::System(libc::EEXIST));
+        }
+
+        Ok(MapArena {
+            ptr: ptr as *mut u8,
+            len,
+            owned: true,
+            _map: PhantomData,
+        })
+    }
+
+    /// Internal function to mmap the value area of an mmapable array map.
+    fn mmap_raw(&self, prot: i32) -> Result<RawMapMmap> {
+        if self.map_type() != MapType::Array {
+            return Err(Error::InvalidInput(format!(
+                "only array maps can be mmaped (type of the map is {})",
+                self.map_type()
+            )));
+        }
+
+        let (map_flags, max_entries) = if self.ptr.is_null() {
+            let info = self.raw_info()?;
+            (info.map_flags, info.max_entries)
+        } else {
+            unsafe {
+                (
+                    libbpf_sys::bpf_map__map_flags(self.ptr),
+                    libbpf_sys::bpf_map__max_entries(self.ptr),
+                )
+            }
+        };
+        if map_flags & libbpf_sys::BPF_F_MMAPABLE == 0 {
+            return Err(Error::InvalidInput(
+                "map was not created with BPF_F_MMAPABLE".to_string(),
+            ));
+        }
+
+        let stride = percpu_aligned_value_size(self.value_size() as usize);
+        let len = stride * max_entries as usize;
+
+        // The kernel hands out whole pages, so the mapping must cover whole pages as well
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let mmap_len = util::roundup(len, page_size);
+
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                mmap_len,
+                prot,
+                libc::MAP_SHARED,
+                self.fd,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Error::System(errno::errno()));
+        }
+
+        Ok(RawMapMmap {
+            ptr: ptr as *mut u8,
+            len,
+            mmap_len,
+            stride,
+        })
+    }
+
+    /// Returns information about the map as seen by the kernel.
+    pub fn info(&self) -> Result<query::MapInfo> {
+        let info = self.raw_info()?;
+        query::MapInfo::from_uapi(self.fd, info)
+            .ok_or_else(|| Error::Internal("failed to parse map info".to_string()))
+    }
+
+    /// Render every entry of the map as text, using the BTF type information of its keys and
+    /// values, similar to `bpftool map dump`.
+    ///
+    /// Each entry is printed as a `key:` line followed by `value:` lines, one per CPU for per-cpu
+    /// maps. Keys or values without a BTF type are printed as hex bytes. Fails if the map has
+    /// no BTF information at all.
+    pub fn dump_pretty(&self) -> Result<String> {
+        let info = self.raw_info()?;
+        if info.btf_id == 0 {
+            return Err(Error::InvalidInput(format!(
+                "map {} has no BTF type information",
+                self.name()
+            )));
+        }
+
+        let mut dumper = BtfDumper::from_btf_id(info.btf_id)?;
+        let mut render = |type_id: u32, data: &[u8]| -> Result<String> {
+            if type_id == 0 {
+                Ok(data
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<Vec<_>>()
+                    .join(" "))
+            } else {
+                dumper.dump(type_id, data)
+            }
+        };
+
+        let mut out = String::new();
+        for key in self.keys() {
+            let values = if self.map_type().is_percpu() {
+                self.lookup_percpu(&key, MapFlags::ANY)?
+            } else {
+                self.lookup(&key, MapFlags::ANY)?.map(|v| vec![v])
+            };
+            // The entry may have been deleted in the meantime
+            let values = match values {
+                Some(values) => values,
+                None => continue,
+            };
+
+            out.push_str(&format!("key: {}\n", render(info.btf_key_type_id, &key)?));
+            for (cpu, value) in values.iter().enumerate() {
+                let value = render(info.btf_value_type_id, value)?;
+                if self.map_type().is_percpu() {
+                    out.push_str(&format!("value (CPU {}): {}\n", cpu, value));
+                } else {
+                    out.push_str(&format!("value: {}\n", value));
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Internal function to query the kernel's view of the map.
+    fn raw_info(&self) -> Result<libbpf_sys::bpf_map_info> {
+        // Padding bytes need to be zero as well, see `query::gen_info_impl`
+        let mut info: libbpf_sys::bpf_map_info = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libbpf_sys::bpf_map_info>() as u32;
+
+        let ret = unsafe {
+            libbpf_sys::bpf_obj_get_info_by_fd(
+                self.fd,
+                &mut info as *mut libbpf_sys::bpf_map_info as *mut c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(Error::System(errno::errno()));
+        }
+
+        Ok(info)
+    }
+
     /// Returns map value as `Vec` of `u8`.
     ///
     /// `key` must have exactly [`Map::key_size()`] elements.
     ///
     /// If the map is one of the per-cpu data structures, the function [`Map::lookup_percpu()`]
     /// must be used.
+    ///
+    /// See [`Map::lookup_locked()`] for values containing a `struct bpf_spin_lock`.
     pub fn lookup(&self, key: &[u8], flags: MapFlags) -> Result<Option<Vec<u8>>> {
         if self.map_type().is_percpu() {
             return Err(Error::InvalidInput(format!(
@@ -205,6 +714,78 @@ impl Map {
         self.lookup_raw(key, flags, out_size)
     }
 
+    /// Look up the inner map stored at `key` in a [`MapType::ArrayOfMaps`] or
+    /// [`MapType::HashOfMaps`] map.
+    ///
+    /// From userspace the value of a map-in-map is the id of the inner map. The returned
+    /// [`MapHandle`] holds its own file descriptor, so the inner map stays alive for as long as
+    /// the handle does, even if it is removed from the outer map in the meantime.
+    pub fn lookup_inner(&self, key: &[u8], flags: MapFlags) -> Result<Option<MapHandle>> {
+        match self.map_type() {
+            MapType::ArrayOfMaps | MapType::HashOfMaps => (),
+            ty => {
+                return Err(Error::InvalidInput(format!(
+                    "lookup_inner() is only supported for map-in-map types (type of the map is {})",
+                    ty,
+                )))
+            }
+        }
+
+        let id = match self.lookup(key, flags)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let id = util::ne_bytes_to_u64(&id)
+            .ok_or_else(|| Error::Internal(format!("unexpected inner map id size {}", id.len())))?;
+
+        MapHandle::from_map_id(id as u32).map(Some)
+    }
+
+    /// Look up the value of the longest prefix matching `addr` in a [`MapType::LpmTrie`] map.
+    ///
+    /// Entries are added with [`Map::update()`] and a key built by [`lpm_trie_key()`].
+    pub fn lookup_lpm(&self, addr: IpAddr, flags: MapFlags) -> Result<Option<Vec<u8>>> {
+        if self.map_type() != MapType::LpmTrie {
+            return Err(Error::InvalidInput(format!(
+                "lookup_lpm() is only supported for LPM trie maps (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
+        let prefix_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        self.lookup(&lpm_trie_key(addr, prefix_len)?, flags)
+    }
+
+    /// Look up the stack trace with id `stack_id` in a [`MapType::StackTrace`] map, as returned
+    /// by `bpf_get_stackid()`.
+    ///
+    /// The frames are ordered from the innermost to the outermost one. Use a [`Symbolizer`] to
+    /// resolve them to symbols.
+    pub fn lookup_stack(&self, stack_id: u32) -> Result<Option<Vec<u64>>> {
+        if self.map_type() != MapType::StackTrace {
+            return Err(Error::InvalidInput(format!(
+                "lookup_stack() is only supported for stack trace maps (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
+        let value = match self.lookup(&stack_id.to_ne_bytes(), MapFlags::ANY)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        // Unused trailing frames are zeroed
+        let frames = value
+            .chunks_exact(mem::size_of::<u64>())
+            .map(|frame| util::ne_bytes_to_u64(frame).unwrap())
+            .take_while(|addr| *addr != 0)
+            .collect();
+        Ok(Some(frames))
+    }
+
     /// Returns one value per cpu as `Vec` of `Vec` of `u8` for per per-cpu maps.
     ///
     /// For normal maps, [`Map::lookup()`] must be used.
@@ -217,19 +798,50 @@ impl Map {
         }
 
         let val_size = self.value_size() as usize;
-        let aligned_val_size = self.percpu_aligned_value_size();
         let out_size = self.percpu_buffer_size()?;
 
         let raw_res = self.lookup_raw(key, flags, out_size)?;
-        if let Some(raw_vals) = raw_res {
-            let mut out = Vec::new();
-            for chunk in raw_vals.chunks_exact(aligned_val_size) {
-                out.push(chunk[..val_size].to_vec());
-            }
-            return Ok(Some(out));
-        } else {
-            return Ok(None);
+        Ok(raw_res.map(|raw_vals| split_percpu_values(&raw_vals, val_size)))
+    }
+
+    /// Looks up the per-cpu values for `key` and folds them into a single value.
+    ///
+    /// `f` is called once per cpu with the accumulator and that cpu's value, in cpu order,
+    /// starting with `init`. This is useful for merging per-cpu state that is not a plain
+    /// counter. For counters, see [`Map::lookup_percpu_sum()`].
+    pub fn lookup_percpu_fold<T, F>(
+        &self,
+        key: &[u8],
+        flags: MapFlags,
+        init: T,
+        f: F,
+    ) -> Result<Option<T>>
+    where
+        F: FnMut(T, &[u8]) -> T,
+    {
+        let mut f = f;
+        Ok(self
+            .lookup_percpu(key, flags)?
+            .map(|vals| vals.iter().fold(init, |acc, val| f(acc, val))))
+    }
+
+    /// Looks up the per-cpu values for `key` and returns their sum.
+    ///
+    /// The values are interpreted as native-endian unsigned integers, so [`Map::value_size()`]
+    /// must be 1, 2, 4, or 8. The sum wraps around on overflow, same as the counters being
+    /// summed.
+    pub fn lookup_percpu_sum(&self, key: &[u8], flags: MapFlags) -> Result<Option<u64>> {
+        if !matches!(self.value_size(), 1 | 2 | 4 | 8) {
+            return Err(Error::InvalidInput(format!(
+                "value_size {} is not the size of an integer",
+                self.value_size()
+            )));
         }
+
+        self.lookup_percpu_fold(key, flags, 0u64, |sum, val| {
+            // Size was validated above
+            sum.wrapping_add(util::ne_bytes_to_u64(val).unwrap())
+        })
     }
 
     /// Internal function to return a value from a map into a buffer of the given size.
@@ -293,11 +905,21 @@ impl Map {
 
     /// Same as [`Map::lookup()`] except this also deletes the key from the map.
     ///
-    /// Note that this operation is currently only implemented in the kernel for [`MapType::Queue`]
-    /// and [`MapType::Stack`].
+    /// The lookup and the deletion happen atomically, which makes this suitable for draining a
+    /// map that BPF programs are concurrently adding to. The kernel implements this operation for
+    /// [`MapType::Queue`] and [`MapType::Stack`] and, as of Linux 5.14, for the hash map types.
     ///
     /// `key` must have exactly [`Map::key_size()`] elements.
+    ///
+    /// Per-cpu maps are not supported.
     pub fn lookup_and_delete(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if self.map_type().is_percpu() {
+            return Err(Error::InvalidInput(format!(
+                "lookup_and_delete() is not supported for per-cpu maps (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
         if key.len() != self.key_size() as usize {
             return Err(Error::InvalidInput(format!(
                 "key_size {} != {}",
@@ -336,7 +958,8 @@ impl Map {
     /// `key` must have exactly [`Map::key_size()`] elements. `value` must have exactly
     /// [`Map::value_size()`] elements.
     ///
-    /// For per-cpu maps, [`Map::update_percpu()`] must be used.
+    /// For per-cpu maps, [`Map::update_percpu()`] must be used. See [`Map::update_locked()`] for
+    /// values containing a `struct bpf_spin_lock`.
     pub fn update(&mut self, key: &[u8], value: &[u8], flags: MapFlags) -> Result<()> {
         if self.map_type().is_percpu() {
             return Err(Error::InvalidInput(format!(
@@ -356,111 +979,1269 @@ impl Map {
         self.update_raw(key, value, flags)
     }
 
-    /// Update an element in an per-cpu map with one value per cpu.
+    /// Look up an element while holding the `struct bpf_spin_lock` embedded in its value.
     ///
-    /// `key` must have exactly [`Map::key_size()`] elements. `value` must have one
-    /// element per cpu (see [`num_possible_cpus()`]) with exactly [`Map::value_size()`]
+    /// BPF programs updating the value under the same lock can then not be observed halfway,
+    /// so multi-field values are read consistently. The lock itself reads as zero. This is
+    /// [`Map::lookup()`] with [`MapFlags::LOCK`], and fails with `EINVAL` for maps whose value
+    /// type has no spin lock.
+    pub fn lookup_locked(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.lookup(key, MapFlags::LOCK)
+    }
+
+    /// Update an element while holding the `struct bpf_spin_lock` embedded in its value.
+    ///
+    /// Only the parts of the value around the lock are written. This is [`Map::update()`] with
+    /// [`MapFlags::LOCK`] added to `flags`, and fails with `EINVAL` for maps whose value type has
+    /// no spin lock.
+    pub fn update_locked(&mut self, key: &[u8], value: &[u8], flags: MapFlags) -> Result<()> {
+        self.update(key, value, flags | MapFlags::LOCK)
+    }
+
+    /// Update an element in an per-cpu map with one value per cpu.
+    ///
+    /// `key` must have exactly [`Map::key_size()`] elements. `value` must have one
+    /// element per cpu (see [`num_possible_cpus()`]) with exactly [`Map::value_size()`]
     /// elements each.
     ///
-    /// For per-cpu maps, [`Map::update_percpu()`] must be used.
-    pub fn update_percpu(
+    /// For per-cpu maps, [`Map::update_percpu()`] must be used.
+    pub fn update_percpu(&mut self, key: &[u8], values: &[Vec<u8>], flags: MapFlags) -> Result<()> {
+        if !self.map_type().is_percpu() && self.map_type() != MapType::Unknown {
+            return Err(Error::InvalidInput(format!(
+                "update() must be used for maps that are not per-cpu (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
+        if values.len() != num_possible_cpus()? {
+            return Err(Error::InvalidInput(format!(
+                "number of values {} != number of cpus {}",
+                values.len(),
+                num_possible_cpus()?
+            )));
+        };
+
+        let value_buf = pack_percpu_values(values, self.value_size() as usize)?;
+        self.update_raw(key, &value_buf, flags)
+    }
+
+    /// Internal function to update a map. This does not check the length of the
+    /// supplied value.
+    fn update_raw(&mut self, key: &[u8], value: &[u8], flags: MapFlags) -> Result<()> {
+        flags.check_update()?;
+        if key.len() != self.key_size() as usize {
+            return Err(Error::InvalidInput(format!(
+                "key_size {} != {}",
+                key.len(),
+                self.key_size()
+            )));
+        };
+
+        let ret = unsafe {
+            libbpf_sys::bpf_map_update_elem(
+                self.fd as i32,
+                key.as_ptr() as *const c_void,
+                value.as_ptr() as *const c_void,
+                flags.bits,
+            )
+        };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(Error::System(errno::errno()))
+        }
+    }
+
+    /// Insert the socket `sock` at `key` of a [`MapType::Sockmap`] or [`MapType::Sockhash`] map.
+    ///
+    /// `key` must have exactly [`Map::key_size()`] elements. The kernel takes its own reference
+    /// to the socket, so `sock` may be closed afterwards.
+    pub fn update_sock<S: AsFd>(&mut self, key: &[u8], sock: &S, flags: MapFlags) -> Result<()> {
+        match self.map_type() {
+            MapType::Sockmap | MapType::Sockhash => (),
+            ty => {
+                return Err(Error::InvalidInput(format!(
+                    "update_sock() is only supported for sockmap and sockhash maps (type of the map is {})",
+                    ty,
+                )))
+            }
+        }
+
+        self.update_fd(key, sock.as_fd(), flags)
+    }
+
+    /// Install `prog` at slot `index` of a [`MapType::ProgArray`] map, making it the target of
+    /// BPF tail calls to that slot.
+    ///
+    /// Use [`Map::delete()`] with the index to clear the slot again.
+    pub fn set_prog(&mut self, index: u32, prog: &Program) -> Result<()> {
+        if self.map_type() != MapType::ProgArray {
+            return Err(Error::InvalidInput(format!(
+                "set_prog() is only supported for prog array maps (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
+        self.update_fd(&index.to_ne_bytes(), prog.fd(), MapFlags::ANY)
+    }
+
+    /// Store the cgroup referred to by `cgroup` at slot `index` of a [`MapType::CgroupArray`]
+    /// map, e.g. for use with `bpf_current_task_under_cgroup()`.
+    ///
+    /// `cgroup` is usually an opened cgroup directory, see [`Map::set_cgroup_path()`].
+    pub fn set_cgroup<C: AsFd>(&mut self, index: u32, cgroup: &C) -> Result<()> {
+        if self.map_type() != MapType::CgroupArray {
+            return Err(Error::InvalidInput(format!(
+                "set_cgroup() is only supported for cgroup array maps (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
+        self.update_fd(&index.to_ne_bytes(), cgroup.as_fd(), MapFlags::ANY)
+    }
+
+    /// Store the cgroup at `path`, e.g. `/sys/fs/cgroup/system.slice`, at slot `index` of a
+    /// [`MapType::CgroupArray`] map.
+    pub fn set_cgroup_path<P: AsRef<Path>>(&mut self, index: u32, path: P) -> Result<()> {
+        let cgroup = fs::File::open(path.as_ref())
+            .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)))?;
+        self.set_cgroup(index, &cgroup)
+    }
+
+    /// Set the redirect target for `key` of a [`MapType::Devmap`] or [`MapType::DevmapHash`] map
+    /// to the network device with index `ifindex`.
+    ///
+    /// If `prog` is set, it is run on each frame after it has been redirected to the device.
+    /// This requires a map with a value size of 8, i.e. `struct bpf_devmap_val`.
+    pub fn update_devmap(
+        &mut self,
+        key: u32,
+        ifindex: u32,
+        prog: Option<&Program>,
+        flags: MapFlags,
+    ) -> Result<()> {
+        match self.map_type() {
+            MapType::Devmap | MapType::DevmapHash => (),
+            ty => {
+                return Err(Error::InvalidInput(format!(
+                    "update_devmap() is only supported for devmap maps (type of the map is {})",
+                    ty,
+                )))
+            }
+        }
+
+        self.update_with_prog(key, ifindex, prog, flags)
+    }
+
+    /// Set up the CPU `cpu` of a [`MapType::Cpumap`] map with a queue of `qsize` frames.
+    ///
+    /// If `prog` is set, it is run on each frame on the remote CPU. This requires a map with a
+    /// value size of 8, i.e. `struct bpf_cpumap_val`.
+    pub fn update_cpumap(
+        &mut self,
+        cpu: u32,
+        qsize: u32,
+        prog: Option<&Program>,
+        flags: MapFlags,
+    ) -> Result<()> {
+        if self.map_type() != MapType::Cpumap {
+            return Err(Error::InvalidInput(format!(
+                "update_cpumap() is only supported for cpumap maps (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
+        self.update_with_prog(cpu, qsize, prog, flags)
+    }
+
+    /// Internal function to store the layout shared by `struct bpf_devmap_val` and
+    /// `struct bpf_cpumap_val`: a `u32` followed by an optional program fd.
+    fn update_with_prog(
+        &mut self,
+        key: u32,
+        val: u32,
+        prog: Option<&Program>,
+        flags: MapFlags,
+    ) -> Result<()> {
+        let mut value = val.to_ne_bytes().to_vec();
+        match (self.value_size(), prog) {
+            (4, None) => (),
+            // The kernel treats a program fd of 0 as not set
+            (8, prog) => {
+                let fd = prog.map_or(0, |p| p.fd().as_raw_fd());
+                value.extend_from_slice(&fd.to_ne_bytes())
+            }
+            (4, Some(_)) => {
+                return Err(Error::InvalidInput(
+                    "value_size 4 cannot hold a program".to_string(),
+                ))
+            }
+            (size, _) => {
+                return Err(Error::InvalidInput(format!(
+                    "unexpected value_size {}",
+                    size
+                )))
+            }
+        }
+
+        self.update(&key.to_ne_bytes(), &value, flags)
+    }
+
+    /// Internal function to store a file descriptor as the value of `key`, as expected by maps
+    /// referring to kernel objects.
+    fn update_fd(&mut self, key: &[u8], fd: BorrowedFd<'_>, flags: MapFlags) -> Result<()> {
+        let fd = fd.as_raw_fd();
+        // Such maps take the fd as either a 32 or 64 bit value, depending on how they were
+        // declared
+        let value = match self.value_size() {
+            4 => (fd as u32).to_ne_bytes().to_vec(),
+            8 => (fd as u64).to_ne_bytes().to_vec(),
+            size => {
+                return Err(Error::InvalidInput(format!(
+                    "value_size {} cannot hold a file descriptor",
+                    size
+                )))
+            }
+        };
+
+        self.update(key, &value, flags)
+    }
+
+    /// Push `value` onto a [`MapType::Queue`], [`MapType::Stack`] or [`MapType::BloomFilter`]
+    /// map.
+    ///
+    /// `value` must have exactly [`Map::value_size()`] elements. If a queue or stack is full, this
+    /// fails unless `flags` contains [`MapFlags::EXIST`], in which case the oldest element is
+    /// replaced. Bloom filters only accept [`MapFlags::ANY`].
+    pub fn push(&mut self, value: &[u8], flags: MapFlags) -> Result<()> {
+        if self.map_type() != MapType::BloomFilter {
+            self.check_queue_stack("push")?;
+        }
+        self.check_value_size(value)?;
+        flags.check_update()?;
+
+        let ret = unsafe {
+            libbpf_sys::bpf_map_update_elem(
+                self.fd,
+                ptr::null(),
+                value.as_ptr() as *const c_void,
+                flags.bits,
+            )
+        };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(Error::System(errno::errno()))
+        }
+    }
+
+    /// Remove and return the next element of a [`MapType::Queue`] or [`MapType::Stack`] map.
+    ///
+    /// Returns `None` if the map is empty.
+    pub fn pop(&mut self) -> Result<Option<Vec<u8>>> {
+        self.check_queue_stack("pop")?;
+        self.queue_stack_raw(|fd, out| unsafe {
+            libbpf_sys::bpf_map_lookup_and_delete_elem(fd, ptr::null(), out)
+        })
+    }
+
+    /// Return the next element of a [`MapType::Queue`] or [`MapType::Stack`] map without
+    /// removing it.
+    ///
+    /// Returns `None` if the map is empty.
+    pub fn peek(&self) -> Result<Option<Vec<u8>>> {
+        self.check_queue_stack("peek")?;
+        self.queue_stack_raw(|fd, out| unsafe {
+            libbpf_sys::bpf_map_lookup_elem(fd, ptr::null(), out)
+        })
+    }
+
+    /// Check whether `value` may be in a [`MapType::BloomFilter`] map.
+    ///
+    /// As with any bloom filter, `true` may be a false positive while `false` means `value` was
+    /// definitely never pushed. `value` must have exactly [`Map::value_size()`] elements.
+    pub fn contains(&self, value: &[u8]) -> Result<bool> {
+        if self.map_type() != MapType::BloomFilter {
+            return Err(Error::InvalidInput(format!(
+                "contains() is only supported for bloom filter maps (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+        self.check_value_size(value)?;
+
+        // The kernel only reads the value to hash it, but the syscall takes a mutable pointer.
+        let mut value = value.to_vec();
+        let ret = unsafe {
+            libbpf_sys::bpf_map_lookup_elem(self.fd, ptr::null(), value.as_mut_ptr() as *mut c_void)
+        };
+
+        if ret == 0 {
+            Ok(true)
+        } else {
+            let errno = errno::errno();
+            if errno::Errno::from_i32(errno) == errno::Errno::ENOENT {
+                Ok(false)
+            } else {
+                Err(Error::System(errno))
+            }
+        }
+    }
+
+    /// Internal function to reject queue/stack operations on other maps.
+    fn check_queue_stack(&self, op: &str) -> Result<()> {
+        match self.map_type() {
+            MapType::Queue | MapType::Stack => Ok(()),
+            ty => Err(Error::InvalidInput(format!(
+                "{}() is only supported for queue and stack maps (type of the map is {})",
+                op, ty,
+            ))),
+        }
+    }
+
+    /// Internal function to check the size of a single value.
+    fn check_value_size(&self, value: &[u8]) -> Result<()> {
+        if value.len() != self.value_size() as usize {
+            return Err(Error::InvalidInput(format!(
+                "value_size {} != {}",
+                value.len(),
+                self.value_size()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Internal function to read one value from a queue or stack with `f`.
+    fn queue_stack_raw<F>(&self, f: F) -> Result<Option<Vec<u8>>>
+    where
+        F: FnOnce(i32, *mut c_void) -> i32,
+    {
+        let mut out = vec![0; self.value_size() as usize];
+
+        let ret = f(self.fd, out.as_mut_ptr() as *mut c_void);
+        if ret == 0 {
+            Ok(Some(out))
+        } else {
+            let errno = errno::errno();
+            if errno::Errno::from_i32(errno) == errno::Errno::ENOENT {
+                Ok(None)
+            } else {
+                Err(Error::System(errno))
+            }
+        }
+    }
+
+    /// Returns an iterator over keys in this map
+    ///
+    /// Note that if the map is not stable (stable meaning no updates or deletes) during iteration,
+    /// iteration can skip keys, restart from the beginning, or duplicate keys. In other words,
+    /// iteration becomes unpredictable.
+    pub fn keys(&self) -> MapKeyIter<'_> {
+        MapKeyIter::new(self, self.key_size())
+    }
+
+    /// Returns an iterator over `(key, value)` pairs in this map.
+    ///
+    /// Keys that are deleted between being returned by the kernel and having their value looked
+    /// up are skipped. Otherwise the same caveats as for [`Map::keys()`] apply when the map is
+    /// modified during iteration.
+    ///
+    /// If looking up a value fails, the error is yielded and iteration stops. This is always the
+    /// case for per-cpu maps, use [`Map::keys()`] and [`Map::lookup_percpu()`] for those instead.
+    pub fn iter(&self) -> MapIter<'_> {
+        MapIter {
+            map: self,
+            keys: self.keys(),
+            done: false,
+        }
+    }
+
+    /// Looks up at most `count` elements with a single syscall, resuming from `cursor`.
+    ///
+    /// Returns the `(key, value)` pairs that were read. `cursor` is advanced so that the next
+    /// call continues where this one stopped. Once the end of the map has been reached,
+    /// [`MapBatchCursor::is_done()`] returns `true` and further calls return no elements.
+    ///
+    /// For per-cpu maps, [`Map::lookup_batch_percpu()`] must be used.
+    pub fn lookup_batch(
+        &self,
+        cursor: &mut MapBatchCursor,
+        count: u32,
+        opts: &MapBatchOpts,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        if self.map_type().is_percpu() {
+            return Err(Error::InvalidInput(format!(
+                "lookup_batch_percpu() must be used for per-cpu maps (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
+        let val_size = self.value_size() as usize;
+        let (keys, values, n) = self.lookup_batch_raw(cursor, count, opts, val_size)?;
+
+        Ok(keys
+            .chunks_exact(self.key_size() as usize)
+            .zip(values.chunks_exact(val_size))
+            .take(n as usize)
+            .map(|(k, v)| (k.to_vec(), v.to_vec()))
+            .collect())
+    }
+
+    /// Same as [`Map::lookup_batch()`] except for per-cpu maps.
+    ///
+    /// Each element comes with one value per cpu (see [`num_possible_cpus()`]).
+    #[allow(clippy::type_complexity)]
+    pub fn lookup_batch_percpu(
+        &self,
+        cursor: &mut MapBatchCursor,
+        count: u32,
+        opts: &MapBatchOpts,
+    ) -> Result<Vec<(Vec<u8>, Vec<Vec<u8>>)>> {
+        if !self.map_type().is_percpu() && self.map_type() != MapType::Unknown {
+            return Err(Error::InvalidInput(format!(
+                "lookup_batch() must be used for maps that are not per-cpu (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
+        let buf_size = self.percpu_buffer_size()?;
+        let (keys, values, n) = self.lookup_batch_raw(cursor, count, opts, buf_size)?;
+
+        Ok(keys
+            .chunks_exact(self.key_size() as usize)
+            .zip(values.chunks_exact(buf_size))
+            .take(n as usize)
+            .map(|(k, v)| {
+                (
+                    k.to_vec(),
+                    split_percpu_values(v, self.value_size() as usize),
+                )
+            })
+            .collect())
+    }
+
+    /// Internal function to look up a batch of elements whose values take up `val_size` bytes
+    /// each. Returns the raw keys and values along with the number of elements read.
+    fn lookup_batch_raw(
+        &self,
+        cursor: &mut MapBatchCursor,
+        count: u32,
+        opts: &MapBatchOpts,
+        val_size: usize,
+    ) -> Result<(Vec<u8>, Vec<u8>, u32)> {
+        if cursor.done || count == 0 {
+            return Ok((Vec::new(), Vec::new(), 0));
+        }
+
+        let key_size = self.key_size() as usize;
+        let mut keys = vec![0; key_size * count as usize];
+        let mut values = vec![0; val_size * count as usize];
+        let mut out_batch = vec![0; MapBatchCursor::batch_size(key_size)];
+        let mut n = count;
+
+        let in_batch = cursor
+            .batch
+            .as_mut()
+            .map_or(ptr::null_mut(), |b| b.as_mut_ptr());
+        let batch_opts = opts.to_libbpf();
+
+        let ret = unsafe {
+            libbpf_sys::bpf_map_lookup_batch(
+                self.fd,
+                in_batch as *mut c_void,
+                out_batch.as_mut_ptr() as *mut c_void,
+                keys.as_mut_ptr() as *mut c_void,
+                values.as_mut_ptr() as *mut c_void,
+                &mut n,
+                &batch_opts,
+            )
+        };
+
+        if ret != 0 {
+            // ENOENT signals that the end of the map was reached. `n` still holds the number
+            // of elements copied out by this final call.
+            let errno = errno::errno();
+            if errno::Errno::from_i32(errno) != errno::Errno::ENOENT {
+                return Err(Error::System(errno));
+            }
+            cursor.done = true;
+        }
+
+        cursor.batch = Some(out_batch);
+
+        Ok((keys, values, n))
+    }
+
+    /// Updates multiple elements with a single syscall.
+    ///
+    /// `keys` and `values` hold the keys and values of all elements back to back. Each must
+    /// therefore be a multiple of [`Map::key_size()`] and [`Map::value_size()`] respectively,
+    /// and both must describe the same number of elements.
+    ///
+    /// For per-cpu maps, [`Map::update_batch_percpu()`] must be used.
+    pub fn update_batch(&mut self, keys: &[u8], values: &[u8], opts: &MapBatchOpts) -> Result<()> {
+        if self.map_type().is_percpu() {
+            return Err(Error::InvalidInput(format!(
+                "update_batch_percpu() must be used for per-cpu maps (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
+        let count = self.batch_count(keys)?;
+        if values.len() != count as usize * self.value_size() as usize {
+            return Err(Error::InvalidInput(format!(
+                "values length {} != {} elements * value_size {}",
+                values.len(),
+                count,
+                self.value_size()
+            )));
+        }
+
+        self.update_batch_raw(keys, values, count, opts)
+    }
+
+    /// Same as [`Map::update_batch()`] except for per-cpu maps.
+    ///
+    /// `values` must hold one entry per element in `keys`, each with one value per cpu (see
+    /// [`num_possible_cpus()`]) of exactly [`Map::value_size()`] bytes.
+    pub fn update_batch_percpu(
+        &mut self,
+        keys: &[u8],
+        values: &[Vec<Vec<u8>>],
+        opts: &MapBatchOpts,
+    ) -> Result<()> {
+        if !self.map_type().is_percpu() && self.map_type() != MapType::Unknown {
+            return Err(Error::InvalidInput(format!(
+                "update_batch() must be used for maps that are not per-cpu (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
+        let count = self.batch_count(keys)?;
+        if values.len() != count as usize {
+            return Err(Error::InvalidInput(format!(
+                "number of values {} != number of keys {}",
+                values.len(),
+                count
+            )));
+        }
+
+        let ncpu = num_possible_cpus()?;
+        let mut value_buf = Vec::with_capacity(self.percpu_buffer_size()? * values.len());
+        for percpu_values in values {
+            if percpu_values.len() != ncpu {
+                return Err(Error::InvalidInput(format!(
+                    "number of values {} != number of cpus {}",
+                    percpu_values.len(),
+                    ncpu
+                )));
+            }
+
+            value_buf.extend(pack_percpu_values(
+                percpu_values,
+                self.value_size() as usize,
+            )?);
+        }
+
+        self.update_batch_raw(keys, &value_buf, count, opts)
+    }
+
+    /// Internal function to update `count` elements whose keys and values have already been
+    /// checked to match in size.
+    fn update_batch_raw(
+        &mut self,
+        keys: &[u8],
+        values: &[u8],
+        mut count: u32,
+        opts: &MapBatchOpts,
+    ) -> Result<()> {
+        let batch_opts = opts.to_libbpf();
+        let ret = unsafe {
+            libbpf_sys::bpf_map_update_batch(
+                self.fd,
+                keys.as_ptr() as *mut c_void,
+                values.as_ptr() as *mut c_void,
+                &mut count,
+                &batch_opts,
+            )
+        };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(Error::System(errno::errno()))
+        }
+    }
+
+    /// Deletes multiple elements with a single syscall.
+    ///
+    /// `keys` holds the keys of all elements back to back and must therefore be a multiple of
+    /// [`Map::key_size()`]. This works the same for per-cpu maps.
+    pub fn delete_batch(&mut self, keys: &[u8], opts: &MapBatchOpts) -> Result<()> {
+        let mut count = self.batch_count(keys)?;
+
+        let batch_opts = opts.to_libbpf();
+        let ret = unsafe {
+            libbpf_sys::bpf_map_delete_batch(
+                self.fd,
+                keys.as_ptr() as *mut c_void,
+                &mut count,
+                &batch_opts,
+            )
+        };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(Error::System(errno::errno()))
+        }
+    }
+
+    /// Delete all elements of the map.
+    ///
+    /// The keys are collected first and then removed with [`Map::delete_batch()`] where the
+    /// kernel supports it, falling back to one [`Map::delete()`] per key otherwise. Elements
+    /// added concurrently may survive. Elements of array maps cannot be deleted, so clearing
+    /// those fails.
+    pub fn clear(&mut self) -> Result<()> {
+        let keys: Vec<u8> = self.keys().flatten().collect();
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        match self.delete_batch(&keys, &MapBatchOpts::default()) {
+            Ok(()) => return Ok(()),
+            // Older kernels and some map types lack batch support. The batch may also have
+            // stopped at a key deleted concurrently, so delete whatever is left one by one.
+            Err(Error::System(errno))
+                if errno == libc::EINVAL
+                    || errno == libc::ENOENT
+                    || errno == libc::EOPNOTSUPP
+                    || errno == ENOTSUPP => {}
+            Err(e) => return Err(e),
+        }
+
+        for key in keys.chunks_exact(self.key_size() as usize) {
+            match self.delete(key) {
+                Ok(()) => (),
+                Err(Error::System(errno)) if errno == libc::ENOENT => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Internal function to return the number of keys contained in a batch of keys.
+    fn batch_count(&self, keys: &[u8]) -> Result<u32> {
+        let key_size = self.key_size() as usize;
+        let count = keys.len() / key_size;
+        if count == 0 || count * key_size != keys.len() {
+            return Err(Error::InvalidInput(format!(
+                "keys length {} is not a non-zero multiple of key_size {}",
+                keys.len(),
+                key_size
+            )));
+        }
+
+        Ok(count as u32)
+    }
+}
+
+impl AsFd for Map {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd()
+    }
+}
+
+/// A BPF map that is not part of an [`Object`].
+///
+/// Such maps are referenced only by a file descriptor, for example the inner maps returned by
+/// [`Map::lookup_inner()`]. The handle owns the file descriptor and closes it when dropped,
+/// which releases the map unless something else, like a pin or an outer map, still refers to it.
+///
+/// Operations of [`Map`] that only read the map are available through [`Deref`]. The handle
+/// provides the ones that modify the map itself, as handing out a `&mut Map` would allow
+/// swapping its file descriptor with that of another [`Map`].
+pub struct MapHandle {
+    map: Map,
+    /// Owns `map.fd`
+    _fd: OwnedFd,
+}
+
+impl MapHandle {
+    /// Create a new map directly in the kernel, without loading a BPF object.
+    ///
+    /// This is useful for maps that only exist to be shared, e.g. by pinning them with
+    /// [`Map::pin()`] for other processes to pick up. The kernel truncates `name` to 15 bytes.
+    pub fn create(
+        map_type: MapType,
+        name: &str,
+        key_size: u32,
+        value_size: u32,
+        max_entries: u32,
+        opts: &MapCreateOpts,
+    ) -> Result<Self> {
+        if map_type == MapType::Unknown {
+            return Err(Error::InvalidInput(
+                "cannot create a map of unknown type".to_string(),
+            ));
+        }
+
+        let name_c = util::str_to_cstring(name)?;
+        let opts = opts.to_libbpf();
+
+        let fd = unsafe {
+            libbpf_sys::bpf_map_create(
+                map_type as libbpf_sys::bpf_map_type,
+                name_c.as_ptr(),
+                key_size,
+                value_size,
+                max_entries,
+                &opts,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::System(errno::errno()));
+        }
+
+        MapHandle::from_fd(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Open a map pinned to bpffs at `path`, e.g. by another process.
+    ///
+    /// The name, type, key size and value size of the handle are populated from the kernel.
+    pub fn from_pinned_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path_c = util::path_to_cstring(path)?;
+
+        let fd = unsafe { libbpf_sys::bpf_obj_get(path_c.as_ptr()) };
+        if fd < 0 {
+            return Err(Error::System(errno::errno()));
+        }
+
+        MapHandle::from_fd(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Open the map with the given `id`, as listed by e.g. [`query::MapInfoIter`].
+    pub fn from_map_id(id: u32) -> Result<Self> {
+        let fd = unsafe { libbpf_sys::bpf_map_get_fd_by_id(id) };
+        if fd < 0 {
+            return Err(Error::System(errno::errno()));
+        }
+
+        MapHandle::from_fd(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Create a new handle to the same map with its own file descriptor.
+    ///
+    /// The handles can be used and dropped independently of each other, e.g. after moving them
+    /// to different threads.
+    pub fn try_clone(&self) -> Result<Self> {
+        MapHandle::try_from(&self.map)
+    }
+
+    /// Takes ownership of `fd`, which must refer to a BPF map, and queries the kernel for the
+    /// properties of the map.
+    pub fn from_fd(fd: OwnedFd) -> Result<Self> {
+        let mut handle = MapHandle {
+            map: Map::new(fd.as_raw_fd(), String::new(), 0, 0, 0, ptr::null_mut()),
+            _fd: fd,
+        };
+
+        let info = handle.map.raw_info()?;
+        handle.map.name = query::name_arr_to_string(&info.name, "");
+        handle.map.ty = info.type_;
+        handle.map.key_size = info.key_size;
+        handle.map.value_size = info.value_size;
+
+        Ok(handle)
+    }
+
+    /// See [`Map::pin()`].
+    pub fn pin<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.map.pin(path)
+    }
+
+    /// See [`Map::unpin()`].
+    pub fn unpin<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.map.unpin(path)
+    }
+
+    /// See [`Map::freeze()`].
+    pub fn freeze(&mut self) -> Result<()> {
+        self.map.freeze()
+    }
+
+    /// See [`Map::mmap_mut()`].
+    pub fn mmap_mut(&mut self) -> Result<MapMmapMut<'_>> {
+        self.map.mmap_mut()
+    }
+
+    /// See [`Map::arena()`].
+    pub fn arena(&mut self) -> Result<MapArena<'_>> {
+        self.map.arena()
+    }
+
+    /// See [`Map::delete()`].
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.map.delete(key)
+    }
+
+    /// See [`Map::lookup_and_delete()`].
+    pub fn lookup_and_delete(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.map.lookup_and_delete(key)
+    }
+
+    /// See [`Map::update()`].
+    pub fn update(&mut self, key: &[u8], value: &[u8], flags: MapFlags) -> Result<()> {
+        self.map.update(key, value, flags)
+    }
+
+    /// See [`Map::update_locked()`].
+    pub fn update_locked(&mut self, key: &[u8], value: &[u8], flags: MapFlags) -> Result<()> {
+        self.map.update_locked(key, value, flags)
+    }
+
+    /// See [`Map::update_percpu()`].
+    pub fn update_percpu(&mut self, key: &[u8], values: &[Vec<u8>], flags: MapFlags) -> Result<()> {
+        self.map.update_percpu(key, values, flags)
+    }
+
+    /// See [`Map::update_sock()`].
+    pub fn update_sock<S: AsFd>(&mut self, key: &[u8], sock: &S, flags: MapFlags) -> Result<()> {
+        self.map.update_sock(key, sock, flags)
+    }
+
+    /// See [`Map::set_prog()`].
+    pub fn set_prog(&mut self, index: u32, prog: &Program) -> Result<()> {
+        self.map.set_prog(index, prog)
+    }
+
+    /// See [`Map::set_cgroup()`].
+    pub fn set_cgroup<C: AsFd>(&mut self, index: u32, cgroup: &C) -> Result<()> {
+        self.map.set_cgroup(index, cgroup)
+    }
+
+    /// See [`Map::set_cgroup_path()`].
+    pub fn set_cgroup_path<P: AsRef<Path>>(&mut self, index: u32, path: P) -> Result<()> {
+        self.map.set_cgroup_path(index, path)
+    }
+
+    /// See [`Map::update_devmap()`].
+    pub fn update_devmap(
         &mut self,
-        key: &[u8],
-        values: &Vec<Vec<u8>>,
+        key: u32,
+        ifindex: u32,
+        prog: Option<&Program>,
         flags: MapFlags,
     ) -> Result<()> {
-        if !self.map_type().is_percpu() && self.map_type() != MapType::Unknown {
-            return Err(Error::InvalidInput(format!(
-                "update() must be used for maps that are not per-cpu (type of the map is {})",
-                self.map_type(),
-            )));
-        }
+        self.map.update_devmap(key, ifindex, prog, flags)
+    }
 
-        if values.len() != num_possible_cpus()? {
-            return Err(Error::InvalidInput(format!(
-                "number of values {} != number of cpus {}",
-                values.len(),
-                num_possible_cpus()?
-            )));
-        };
+    /// See [`Map::update_cpumap()`].
+    pub fn update_cpumap(
+        &mut self,
+        cpu: u32,
+        qsize: u32,
+        prog: Option<&Program>,
+        flags: MapFlags,
+    ) -> Result<()> {
+        self.map.update_cpumap(cpu, qsize, prog, flags)
+    }
 
-        let val_size = self.value_size() as usize;
-        let aligned_val_size = self.percpu_aligned_value_size();
-        let buf_size = self.percpu_buffer_size()?;
+    /// See [`Map::push()`].
+    pub fn push(&mut self, value: &[u8], flags: MapFlags) -> Result<()> {
+        self.map.push(value, flags)
+    }
 
-        let mut value_buf = Vec::new();
-        value_buf.resize(buf_size, 0);
+    /// See [`Map::pop()`].
+    pub fn pop(&mut self) -> Result<Option<Vec<u8>>> {
+        self.map.pop()
+    }
 
-        for (i, val) in values.iter().enumerate() {
-            if val.len() != val_size {
-                return Err(Error::InvalidInput(format!(
-                    "value size for cpu {} is {} != {}",
-                    i,
-                    val.len(),
-                    val_size
-                )));
-            }
+    /// See [`Map::update_batch()`].
+    pub fn update_batch(&mut self, keys: &[u8], values: &[u8], opts: &MapBatchOpts) -> Result<()> {
+        self.map.update_batch(keys, values, opts)
+    }
+
+    /// See [`Map::update_batch_percpu()`].
+    pub fn update_batch_percpu(
+        &mut self,
+        keys: &[u8],
+        values: &[Vec<Vec<u8>>],
+        opts: &MapBatchOpts,
+    ) -> Result<()> {
+        self.map.update_batch_percpu(keys, values, opts)
+    }
+
+    /// See [`Map::delete_batch()`].
+    pub fn delete_batch(&mut self, keys: &[u8], opts: &MapBatchOpts) -> Result<()> {
+        self.map.delete_batch(keys, opts)
+    }
+
+    /// See [`Map::clear()`].
+    pub fn clear(&mut self) -> Result<()> {
+        self.map.clear()
+    }
+}
+
+/// Create a [`MapHandle`] with its own file descriptor referring to the same map as a [`Map`],
+/// e.g. one of an [`Object`].
+///
+/// Unlike the [`Map`], the handle does not borrow the [`Object`] and keeps the map alive even
+/// after the [`Object`] is dropped.
+impl TryFrom<&Map> for MapHandle {
+    type Error = Error;
+
+    fn try_from(map: &Map) -> Result<Self> {
+        let fd = map
+            .fd()
+            .try_clone_to_owned()
+            .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)))?;
+
+        Ok(MapHandle {
+            map: Map::new(
+                fd.as_raw_fd(),
+                map.name.clone(),
+                map.ty,
+                map.key_size,
+                map.value_size,
+                ptr::null_mut(),
+            ),
+            _fd: fd,
+        })
+    }
+}
+
+impl Deref for MapHandle {
+    type Target = Map;
+
+    fn deref(&self) -> &Map {
+        &self.map
+    }
+}
+
+// Handles are not part of an object, so the wrapped `Map` never refers to any libbpf state and
+// everything is done through the owned file descriptor
+unsafe impl Send for MapHandle {}
+
+impl AsFd for MapHandle {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.map.fd()
+    }
+}
+
+/// Options for [`MapHandle::create()`]. Maps to `struct bpf_map_create_opts` in libbpf.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MapCreateOpts<'fd> {
+    /// `BPF_F_*` flags to create the map with, e.g. `BPF_F_NO_PREALLOC` or `BPF_F_MMAPABLE`.
+    pub map_flags: u32,
+    /// Map type specific extra value, see [`OpenMap::set_map_extra()`].
+    pub map_extra: u64,
+    /// Allocate the map on this NUMA node. Sets `BPF_F_NUMA_NODE` if present.
+    pub numa_node: Option<u32>,
+    /// Offload the map to the device with this interface index.
+    pub map_ifindex: u32,
+    /// File descriptor of the inner map prototype for map-in-map types.
+    pub inner_map_fd: Option<BorrowedFd<'fd>>,
+}
 
-            value_buf[(i * aligned_val_size)..(i * aligned_val_size + val_size)]
-                .copy_from_slice(val);
+impl MapCreateOpts<'_> {
+    fn to_libbpf(self) -> libbpf_sys::bpf_map_create_opts {
+        let mut map_flags = self.map_flags;
+        if self.numa_node.is_some() {
+            map_flags |= libbpf_sys::BPF_F_NUMA_NODE;
         }
 
-        self.update_raw(key, &value_buf, flags)
+        libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            inner_map_fd: self.inner_map_fd.map_or(0, |fd| fd.as_raw_fd()) as u32,
+            map_flags,
+            map_extra: self.map_extra,
+            numa_node: self.numa_node.unwrap_or(0),
+            map_ifindex: self.map_ifindex,
+            ..Default::default()
+        }
     }
+}
 
-    /// Internal function to update a map. This does not check the length of the
-    /// supplied value.
-    fn update_raw(&mut self, key: &[u8], value: &[u8], flags: MapFlags) -> Result<()> {
-        if key.len() != self.key_size() as usize {
+/// Options for batch [`Map`] operations. Maps to `struct bpf_map_batch_opts` in libbpf.
+#[derive(Clone, Copy, Debug)]
+pub struct MapBatchOpts {
+    /// Flags applied to every element of the batch.
+    pub elem_flags: MapBatchElemFlags,
+    /// Flags applied to the batch operation as a whole.
+    pub flags: MapFlags,
+}
+
+impl MapBatchOpts {
+    fn to_libbpf(self) -> libbpf_sys::bpf_map_batch_opts {
+        libbpf_sys::bpf_map_batch_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_batch_opts>() as libbpf_sys::size_t,
+            elem_flags: self.elem_flags.bits,
+            flags: self.flags.bits,
+        }
+    }
+}
+
+impl Default for MapBatchOpts {
+    fn default() -> Self {
+        MapBatchOpts {
+            elem_flags: MapBatchElemFlags::empty(),
+            flags: MapFlags::ANY,
+        }
+    }
+}
+
+/// Position within a [`Map`] from which [`Map::lookup_batch()`] resumes.
+///
+/// A freshly created cursor starts at the beginning of the map.
+#[derive(Clone, Debug, Default)]
+pub struct MapBatchCursor {
+    batch: Option<Vec<u8>>,
+    done: bool,
+}
+
+impl MapBatchCursor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` once the end of the map has been reached.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Size of the opaque batch token the kernel hands back. Hash maps use a `u32` bucket
+    /// index while other maps use a key, so make room for whichever is larger.
+    fn batch_size(key_size: usize) -> usize {
+        key_size.max(mem::size_of::<u32>())
+    }
+}
+
+/// An mmaped region backing an array map. Unmapped on drop.
+struct RawMapMmap {
+    ptr: *mut u8,
+    /// Length of the region holding map values
+    len: usize,
+    /// Length of the whole mapping, rounded up to pages
+    mmap_len: usize,
+    /// Distance in bytes between two consecutive values
+    stride: usize,
+}
+
+impl RawMapMmap {
+    fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+
+    fn check_stride<T>(&self) -> Result<()> {
+        if mem::size_of::<T>() != self.stride {
             return Err(Error::InvalidInput(format!(
-                "key_size {} != {}",
-                key.len(),
-                self.key_size()
+                "size of type {} != value stride {}",
+                mem::size_of::<T>(),
+                self.stride
             )));
-        };
+        }
 
-        let ret = unsafe {
-            libbpf_sys::bpf_map_update_elem(
-                self.fd as i32,
-                key.as_ptr() as *const c_void,
-                value.as_ptr() as *const c_void,
-                flags.bits,
-            )
-        };
+        Ok(())
+    }
+}
 
-        if ret == 0 {
-            Ok(())
+impl Drop for RawMapMmap {
+    fn drop(&mut self) {
+        let _ = unsafe { libc::munmap(self.ptr as *mut c_void, self.mmap_len) };
+    }
+}
+
+/// A read-only view of the values in an mmapable array [`Map`], created by [`Map::mmap()`].
+///
+/// Dereferences to the raw bytes of all values. Values are laid out back to back, each padded to
+/// a multiple of 8 bytes. Keep in mind that BPF programs may modify the values at any time.
+pub struct MapMmap<'a> {
+    raw: RawMapMmap,
+    _map: PhantomData<&'a Map>,
+}
+
+impl<'a> MapMmap<'a> {
+    /// Returns the values as a slice of `T`.
+    ///
+    /// The size of `T` must be [`Map::value_size()`] rounded up to a multiple of 8.
+    pub fn as_typed_slice<T: Plain>(&self) -> Result<&[T]> {
+        self.raw.check_stride::<T>()?;
+        // Page alignment satisfies the alignment of any `T`
+        plain::slice_from_bytes(self.raw.as_slice())
+            .map_err(|e| Error::Internal(format!("{:?}", e)))
+    }
+}
+
+impl<'a> Deref for MapMmap<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.raw.as_slice()
+    }
+}
+
+/// A writable view of the values in an mmapable array [`Map`], created by [`Map::mmap_mut()`].
+///
+/// See [`MapMmap`].
+pub struct MapMmapMut<'a> {
+    raw: RawMapMmap,
+    _map: PhantomData<&'a mut Map>,
+}
+
+impl<'a> MapMmapMut<'a> {
+    /// See [`MapMmap::as_typed_slice()`].
+    pub fn as_typed_slice<T: Plain>(&self) -> Result<&[T]> {
+        self.raw.check_stride::<T>()?;
+        plain::slice_from_bytes(self.raw.as_slice())
+            .map_err(|e| Error::Internal(format!("{:?}", e)))
+    }
+
+    /// Same as [`MapMmapMut::as_typed_slice()`], but mutable.
+    pub fn as_typed_slice_mut<T: Plain>(&mut self) -> Result<&mut [T]> {
+        self.raw.check_stride::<T>()?;
+        plain::slice_from_mut_bytes(self.raw.as_mut_slice())
+            .map_err(|e| Error::Internal(format!("{:?}", e)))
+    }
+}
+
+impl<'a> Deref for MapMmapMut<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.raw.as_slice()
+    }
+}
+
+impl<'a> DerefMut for MapMmapMut<'a> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.raw.as_mut_slice()
+    }
+}
+
+/// The memory region of a [`MapType::Arena`] map shared between BPF programs and userspace,
+/// created by [`Map::arena()`].
+///
+/// Dereferences to the raw bytes of the arena. Pages only become accessible once they have
+/// been allocated, e.g. by a BPF program calling `bpf_arena_alloc_pages()`; touching other
+/// pages from userspace faults them in.
+///
+/// Pointers stored inside the arena are userspace addresses. Use [`MapArena::addr_to_offset()`]
+/// and [`MapArena::offset_to_addr()`] to translate between them and offsets into the region.
+pub struct MapArena<'a> {
+    ptr: *mut u8,
+    len: usize,
+    /// Whether the region was mapped by us rather than by libbpf while loading the object
+    owned: bool,
+    _map: PhantomData<&'a mut Map>,
+}
+
+impl<'a> MapArena<'a> {
+    /// Returns the userspace address the arena starts at.
+    pub fn base_addr(&self) -> u64 {
+        self.ptr as u64
+    }
+
+    /// Translate the address `addr` of an object in the arena into an offset into the region.
+    ///
+    /// Returns `None` if `addr` points outside of the arena.
+    pub fn addr_to_offset(&self, addr: u64) -> Option<usize> {
+        let offset = addr.checked_sub(self.base_addr())? as usize;
+        if offset < self.len {
+            Some(offset)
         } else {
-            Err(Error::System(errno::errno()))
+            None
         }
     }
 
-    /// Returns an iterator over keys in this map
+    /// Translate `offset` into the region into the address BPF programs and pointers stored in
+    /// the arena use.
     ///
-    /// Note that if the map is not stable (stable meaning no updates or deletes) during iteration,
-    /// iteration can skip keys, restart from the beginning, or duplicate keys. In other words,
-    /// iteration becomes unpredictable.
-    pub fn keys(&self) -> MapKeyIter {
-        MapKeyIter::new(self, self.key_size())
+    /// Returns `None` if `offset` is outside of the arena.
+    pub fn offset_to_addr(&self, offset: usize) -> Option<u64> {
+        if offset < self.len {
+            Some(self.base_addr() + offset as u64)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> Deref for MapArena<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<'a> DerefMut for MapArena<'a> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl<'a> Drop for MapArena<'a> {
+    fn drop(&mut self) {
+        if self.owned {
+            let _ = unsafe { libc::munmap(self.ptr as *mut c_void, self.len) };
+        }
     }
 }
 
 #[rustfmt::skip]
 bitflags! {
     /// Flags to configure [`Map`] operations.
+    ///
+    /// [`MapFlags::NO_EXIST`] and [`MapFlags::EXIST`] are mutually exclusive. Updates using both
+    /// are rejected before reaching the kernel.
     pub struct MapFlags: u64 {
+	/// Create a new element or update an existing one (`BPF_ANY`).
 	const ANY      = 0;
+	/// Only create a new element (`BPF_NOEXIST`).
 	const NO_EXIST = 1;
+	/// Only update an existing element (`BPF_EXIST`).
 	const EXIST    = 1 << 1;
+	/// Take the map value's `struct bpf_spin_lock` for the operation (`BPF_F_LOCK`).
 	const LOCK     = 1 << 2;
     }
 }
 
+impl MapFlags {
+    /// Internal function to reject flag combinations that make no sense for an update.
+    fn check_update(self) -> Result<()> {
+        if self.contains(MapFlags::NO_EXIST | MapFlags::EXIST) {
+            return Err(Error::InvalidInput(
+                "NO_EXIST and EXIST are mutually exclusive".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[rustfmt::skip]
+bitflags! {
+    /// Flags applied to every element of a batch [`Map`] operation, see [`MapBatchOpts`].
+    pub struct MapBatchElemFlags: u64 {
+	/// Take each map value's `struct bpf_spin_lock` for the operation (`BPF_F_LOCK`).
+	const LOCK = 1 << 2;
+    }
+}
+
 /// Type of a [`Map`]. Maps to `enum bpf_map_type` in kernel uapi.
 // If you add a new per-cpu map, also update `is_percpu`.
 #[non_exhaustive]
 #[repr(u32)]
-#[derive(Clone, TryFromPrimitive, PartialEq, Display)]
+#[derive(Clone, TryFromPrimitive, PartialEq, Display, Debug)]
 pub enum MapType {
     Unspec = 0,
     Hash,
@@ -490,6 +2271,12 @@ pub enum MapType {
     DevmapHash,
     StructOps,
     RingBuf,
+    InodeStorage,
+    TaskStorage,
+    BloomFilter,
+    UserRingBuf,
+    CgrpStorage,
+    Arena,
     /// We choose to specify our own "unknown" type here b/c it's really up to the kernel
     /// to decide if it wants to reject the map. If it accepts it, it just means whoever
     /// using this library is a bit out of date.
@@ -509,6 +2296,10 @@ impl MapType {
     }
 }
 
+/// Iterator over the keys of a [`Map`], created by [`Map::keys()`].
+///
+/// Each key is fetched with a separate `bpf_map_get_next_key` syscall. Iteration ends when the
+/// kernel reports there are no keys left.
 pub struct MapKeyIter<'a> {
     map: &'a Map,
     prev: Option<Vec<u8>>,
@@ -532,7 +2323,7 @@ impl<'a> Iterator for MapKeyIter<'a> {
         let prev = self.prev.as_ref().map_or(ptr::null(), |p| p.as_ptr());
 
         let ret = unsafe {
-            libbpf_sys::bpf_map_get_next_key(self.map.fd(), prev as _, self.next.as_mut_ptr() as _)
+            libbpf_sys::bpf_map_get_next_key(self.map.fd, prev as _, self.next.as_mut_ptr() as _)
         };
         if ret != 0 {
             None
@@ -542,3 +2333,106 @@ impl<'a> Iterator for MapKeyIter<'a> {
         }
     }
 }
+
+/// Iterator over the `(key, value)` pairs of a [`Map`], created by [`Map::iter()`].
+pub struct MapIter<'a> {
+    map: &'a Map,
+    keys: MapKeyIter<'a>,
+    done: bool,
+}
+
+impl<'a> Iterator for MapIter<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let key = self.keys.next()?;
+            match self.map.lookup(&key, MapFlags::ANY) {
+                Ok(Some(value)) => return Some(Ok((key, value))),
+                // The key was deleted after we got it, move on to the next one
+                Ok(None) => continue,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percpu_values_roundtrip() {
+        // Value sizes that are and aren't multiples of the 8 byte stride
+        for val_size in &[1, 3, 4, 8, 12, 16] {
+            let val_size = *val_size;
+            let values: Vec<Vec<u8>> = (0..4u8).map(|cpu| vec![cpu + 1; val_size]).collect();
+
+            let packed = pack_percpu_values(&values, val_size).unwrap();
+            assert_eq!(packed.len(), 4 * util::roundup(val_size, 8));
+            assert_eq!(split_percpu_values(&packed, val_size), values);
+        }
+    }
+
+    #[test]
+    fn test_percpu_values_padding() {
+        let packed = pack_percpu_values(&[vec![1, 2, 3], vec![4, 5, 6]], 3).unwrap();
+        assert_eq!(packed, &[1, 2, 3, 0, 0, 0, 0, 0, 4, 5, 6, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_lpm_trie_key() {
+        let key = lpm_trie_key("10.1.0.0".parse().unwrap(), 16).unwrap();
+        let mut expected = 16u32.to_ne_bytes().to_vec();
+        expected.extend_from_slice(&[10, 1, 0, 0]);
+        assert_eq!(key, expected);
+
+        let key = lpm_trie_key("fe80::1".parse().unwrap(), 128).unwrap();
+        assert_eq!(key.len(), 20);
+        assert_eq!(&key[4..6], &[0xfe, 0x80]);
+        assert_eq!(key[19], 1);
+
+        assert!(lpm_trie_key("10.0.0.0".parse().unwrap(), 33).is_err());
+        assert!(lpm_trie_key("::".parse().unwrap(), 129).is_err());
+    }
+
+    #[test]
+    fn test_map_flags_check_update() {
+        assert!(MapFlags::ANY.check_update().is_ok());
+        assert!(MapFlags::NO_EXIST.check_update().is_ok());
+        assert!((MapFlags::EXIST | MapFlags::LOCK).check_update().is_ok());
+        assert!((MapFlags::NO_EXIST | MapFlags::EXIST)
+            .check_update()
+            .is_err());
+    }
+
+    #[test]
+    fn test_arena_addr_translation() {
+        // Never dereferenced, so any address will do
+        let arena = MapArena {
+            ptr: 0x1000_0000 as *mut u8,
+            len: 0x2000,
+            owned: false,
+            _map: PhantomData,
+        };
+
+        assert_eq!(arena.base_addr(), 0x1000_0000);
+        assert_eq!(arena.addr_to_offset(0x1000_0000), Some(0));
+        assert_eq!(arena.addr_to_offset(0x1000_1fff), Some(0x1fff));
+        assert_eq!(arena.addr_to_offset(0x1000_2000), None);
+        assert_eq!(arena.addr_to_offset(0x0fff_ffff), None);
+        assert_eq!(arena.offset_to_addr(0x10), Some(0x1000_0010));
+        assert_eq!(arena.offset_to_addr(0x2000), None);
+    }
+
+    #[test]
+    fn test_percpu_values_wrong_size() {
+        assert!(pack_percpu_values(&[vec![1, 2, 3, 4], vec![1, 2, 3]], 4).is_err());
+    }
+}
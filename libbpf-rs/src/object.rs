@@ -1,10 +1,11 @@
 use core::ffi::c_void;
 use std::collections::HashMap;
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::mem;
 use std::os::raw::c_char;
 use std::path::Path;
 use std::ptr;
+use std::result;
 
 use crate::util;
 use crate::*;
@@ -13,6 +14,7 @@ use crate::*;
 pub struct ObjectBuilder {
     name: String,
     relaxed_maps: bool,
+    pin_root_path: Option<CString>,
 }
 
 impl ObjectBuilder {
@@ -28,6 +30,16 @@ impl ObjectBuilder {
         self
     }
 
+    /// Pin maps declared with `__uint(pinning, LIBBPF_PIN_BY_NAME)` under `path` instead of the
+    /// default of `/sys/fs/bpf`.
+    ///
+    /// Upon load, such maps reuse an existing compatible map pinned at `<path>/<map name>` or
+    /// are pinned there after creation.
+    pub fn pin_root_path<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Self> {
+        self.pin_root_path = Some(util::path_to_cstring(path)?);
+        Ok(self)
+    }
+
     /// Option to print debug output to stderr.
     ///
     /// Note: This function uses [`set_print`] internally and will overwrite any callbacks
@@ -48,13 +60,13 @@ impl ObjectBuilder {
             sz: mem::size_of::<libbpf_sys::bpf_object_open_opts>() as libbpf_sys::size_t,
             object_name: name,
             relaxed_maps: self.relaxed_maps,
-            relaxed_core_relocs: false,
-            pin_root_path: ptr::null(),
-            attach_prog_fd: 0,
+            pin_root_path: self
+                .pin_root_path
+                .as_ref()
+                .map_or(ptr::null(), |path| path.as_ptr()),
             kconfig: ptr::null(),
             btf_custom_path: ptr::null(),
-            __bindgen_padding_0: <[u8; 6]>::default(),
-            __bindgen_padding_1: <[u8; 4]>::default(),
+            ..Default::default()
         }
     }
 
@@ -81,7 +93,7 @@ impl ObjectBuilder {
         let obj = unsafe { libbpf_sys::bpf_object__open_file(path_ptr, &opts) };
         let err = unsafe { libbpf_sys::libbpf_get_error(obj as *const _) };
         if err != 0 {
-            return Err(Error::System(err as i32));
+            return Err(Error::System(-err as i32));
         }
 
         OpenObject::new(obj)
@@ -109,7 +121,7 @@ impl ObjectBuilder {
         };
         let err = unsafe { libbpf_sys::libbpf_get_error(obj as *const _) };
         if err != 0 {
-            return Err(Error::System(err as i32));
+            return Err(Error::System(-err as i32));
         }
 
         OpenObject::new(obj)
@@ -121,6 +133,7 @@ impl Default for ObjectBuilder {
         ObjectBuilder {
             name: String::new(),
             relaxed_maps: false,
+            pin_root_path: None,
         }
     }
 }
@@ -146,7 +159,7 @@ impl OpenObject {
         let mut map: *mut libbpf_sys::bpf_map = std::ptr::null_mut();
         loop {
             // Get the pointer to the next BPF map
-            let next_ptr = unsafe { libbpf_sys::bpf_map__next(map, obj.ptr) };
+            let next_ptr = unsafe { libbpf_sys::bpf_object__next_map(obj.ptr, map) };
             if next_ptr.is_null() {
                 break;
             }
@@ -166,7 +179,7 @@ impl OpenObject {
         let mut prog: *mut libbpf_sys::bpf_program = std::ptr::null_mut();
         loop {
             // Get the pointer to the next BPF program
-            let next_ptr = unsafe { libbpf_sys::bpf_program__next(prog, obj.ptr) };
+            let next_ptr = unsafe { libbpf_sys::bpf_object__next_program(obj.ptr, prog) };
             if next_ptr.is_null() {
                 break;
             }
@@ -203,12 +216,30 @@ impl OpenObject {
         ptr
     }
 
+    /// Takes underlying `libbpf_sys::bpf_object` pointer like [`OpenObject::take_ptr()`], along
+    /// with the verifier log buffers set up with [`OpenProgram::set_log_buf_size()`], by program
+    /// name.
+    ///
+    /// libbpf keeps pointing to the log buffers, so they have to be handed to
+    /// [`Object::from_ptr_with_log_bufs()`] once the object is loaded.
+    pub fn take_ptr_and_log_bufs(
+        mut self,
+    ) -> (*mut libbpf_sys::bpf_object, HashMap<String, Vec<u8>>) {
+        let log_bufs = self
+            .progs
+            .iter_mut()
+            .filter(|(_, prog)| !prog.log_buf.is_empty())
+            .map(|(name, prog)| (name.clone(), mem::take(&mut prog.log_buf)))
+            .collect();
+        (self.take_ptr(), log_bufs)
+    }
+
     pub fn name(&self) -> Result<&str> {
         unsafe {
             let ptr = libbpf_sys::bpf_object__name(self.ptr);
             let err = libbpf_sys::libbpf_get_error(ptr as *const _);
             if err != 0 {
-                return Err(Error::System(err as i32));
+                return Err(Error::System(-err as i32));
             }
 
             CStr::from_ptr(ptr)
@@ -262,19 +293,25 @@ impl OpenObject {
     }
 
     /// Load the maps and programs contained in this BPF object into the system.
-    pub fn load(mut self) -> Result<Object> {
+    pub fn load(self) -> Result<Object> {
+        self.load_or_recover().map_err(|(err, _)| err)
+    }
+
+    /// Load the maps and programs contained in this BPF object into the system like
+    /// [`OpenObject::load()`], but hand back the `OpenObject` if that fails, e.g. to read the
+    /// verifier logs of its programs with [`OpenProgram::log_buf()`].
+    ///
+    /// The returned object cannot be loaded again. There is none if libbpf loaded the object
+    /// but it could not be set up afterwards.
+    pub fn load_or_recover(self) -> result::Result<Object, (Error, Option<Box<OpenObject>>)> {
         let ret = unsafe { libbpf_sys::bpf_object__load(self.ptr) };
         if ret != 0 {
             // bpf_object__load() returns errno as negative, so flip
-            return Err(Error::System(-ret));
+            return Err((Error::System(-ret), Some(Box::new(self))));
         }
 
-        let obj = Object::new(self.ptr)?;
-
-        // Prevent object from being closed once `self` is dropped
-        self.ptr = ptr::null_mut();
-
-        Ok(obj)
+        let (ptr, log_bufs) = self.take_ptr_and_log_bufs();
+        unsafe { Object::from_ptr_with_log_bufs(ptr, log_bufs) }.map_err(|err| (err, None))
     }
 }
 
@@ -303,9 +340,11 @@ pub struct Object {
 }
 
 impl Object {
+    /// Set up an `Object` for the loaded `ptr`. It only takes ownership of `ptr` on success, so
+    /// that the caller decides what happens to it otherwise.
     fn new(ptr: *mut libbpf_sys::bpf_object) -> Result<Self> {
         let mut obj = Object {
-            ptr,
+            ptr: ptr::null_mut(),
             maps: HashMap::new(),
             progs: HashMap::new(),
         };
@@ -314,7 +353,7 @@ impl Object {
         let mut map: *mut libbpf_sys::bpf_map = std::ptr::null_mut();
         loop {
             // Get the pointer to the next BPF map
-            let next_ptr = unsafe { libbpf_sys::bpf_map__next(map, obj.ptr) };
+            let next_ptr = unsafe { libbpf_sys::bpf_object__next_map(ptr, map) };
             if next_ptr.is_null() {
                 break;
             }
@@ -325,10 +364,10 @@ impl Object {
             let name = unsafe { libbpf_sys::bpf_map__name(next_ptr) };
             let name = util::c_ptr_to_string(name)?;
 
-            // Get the map def
-            // bpf_map__def can return null but only if it's passed a null.
-            // We already know next_ptr is not null.
-            let def = unsafe { ptr::read(libbpf_sys::bpf_map__def(next_ptr)) };
+            // Get the map definition
+            let ty = unsafe { libbpf_sys::bpf_map__type(next_ptr) };
+            let key_size = unsafe { libbpf_sys::bpf_map__key_size(next_ptr) };
+            let value_size = unsafe { libbpf_sys::bpf_map__value_size(next_ptr) };
 
             // Get the map fd
             let fd = unsafe { libbpf_sys::bpf_map__fd(next_ptr) };
@@ -339,7 +378,7 @@ impl Object {
             // Add the map to the hashmap
             obj.maps.insert(
                 name.clone(),
-                Map::new(fd, name, def.type_, def.key_size, def.value_size, next_ptr),
+                Map::new(fd, name, ty, key_size, value_size, next_ptr),
             );
             map = next_ptr;
         }
@@ -348,7 +387,7 @@ impl Object {
         let mut prog: *mut libbpf_sys::bpf_program = std::ptr::null_mut();
         loop {
             // Get the pointer to the next BPF program
-            let next_ptr = unsafe { libbpf_sys::bpf_program__next(prog, obj.ptr) };
+            let next_ptr = unsafe { libbpf_sys::bpf_object__next_program(ptr, prog) };
             if next_ptr.is_null() {
                 break;
             }
@@ -363,6 +402,12 @@ impl Object {
             let section = unsafe { libbpf_sys::bpf_program__section_name(next_ptr) };
             let section = util::c_ptr_to_string(section)?;
 
+            // Programs with autoload disabled were not loaded
+            if !unsafe { libbpf_sys::bpf_program__autoload(next_ptr) } {
+                prog = next_ptr;
+                continue;
+            }
+
             // Get the program fd
             let fd = unsafe { libbpf_sys::bpf_program__fd(next_ptr) };
             if fd < 0 {
@@ -375,6 +420,7 @@ impl Object {
             prog = next_ptr;
         }
 
+        obj.ptr = ptr;
         Ok(obj)
     }
 
@@ -387,7 +433,33 @@ impl Object {
     ///
     /// It is not safe to manipulate `ptr` after this operation.
     pub unsafe fn from_ptr(ptr: *mut libbpf_sys::bpf_object) -> Result<Self> {
-        Self::new(ptr)
+        let obj = Self::new(ptr);
+        if obj.is_err() {
+            libbpf_sys::bpf_object__close(ptr);
+        }
+        obj
+    }
+
+    /// Takes ownership from pointer like [`Object::from_ptr()`], along with the verifier log
+    /// buffers returned by [`OpenObject::take_ptr_and_log_bufs()`], which are then available
+    /// through [`Program::log_buf()`].
+    ///
+    /// # Safety
+    ///
+    /// The same as for [`Object::from_ptr()`].
+    pub unsafe fn from_ptr_with_log_bufs(
+        ptr: *mut libbpf_sys::bpf_object,
+        log_bufs: HashMap<String, Vec<u8>>,
+    ) -> Result<Self> {
+        // On error, `ptr` is closed before the log buffers it points to are dropped
+        let mut obj = Self::from_ptr(ptr)?;
+        for (name, log_buf) in log_bufs {
+            if let Some(prog) = obj.progs.get_mut(&name) {
+                prog.log_buf = log_buf;
+            }
+        }
+
+        Ok(obj)
     }
 
     /// Get a reference to `Map` with the name `name`, if one exists.
@@ -1,10 +1,25 @@
 use core::ffi::c_void;
 use std::boxed::Box;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{ControlFlow, Deref};
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd};
+use std::ptr;
 use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
+use nix::{errno, libc};
+use plain::Plain;
+
 use crate::*;
 
+/// How long [`PerfBuffer::run()`] waits for samples before checking whether to stop
+const RUN_POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
 fn is_power_of_two(i: usize) -> bool {
     i > 0 && (i & (i - 1)) == 0
 }
@@ -15,33 +30,98 @@ fn is_power_of_two(i: usize) -> bool {
 pub trait SampleCb: FnMut(i32, &[u8]) + 'static {}
 impl<T> SampleCb for T where T: FnMut(i32, &[u8]) + 'static {}
 
+pub trait ControlSampleCb: FnMut(i32, &[u8]) -> ControlFlow<()> + 'static {}
+impl<T> ControlSampleCb for T where T: FnMut(i32, &[u8]) -> ControlFlow<()> + 'static {}
+
 pub trait LostCb: FnMut(i32, u64) + 'static {}
 impl<T> LostCb for T where T: FnMut(i32, u64) + 'static {}
 
 struct CbStruct {
-    sample_cb: Option<Box<dyn SampleCb>>,
+    sample_cb: Option<Box<dyn ControlSampleCb>>,
     lost_cb: Option<Box<dyn LostCb>>,
+    /// Set once the sample callback asked to stop processing samples, or the sample limit was
+    /// reached
+    stopped: Cell<bool>,
+    /// Maximum number of samples to process per poll, if limited
+    sample_limit: Option<u64>,
+    /// Samples left to process in the ongoing poll, if limited
+    samples_left: Cell<u64>,
+    stats: RefCell<BTreeMap<usize, PerfBufferStats>>,
+}
+
+/// Statistics about the samples from one CPU of a [`PerfBuffer`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PerfBufferStats {
+    /// Number of samples received
+    pub samples: u64,
+    /// Total size of the samples received, in bytes
+    pub bytes: u64,
+    /// Number of samples the kernel dropped because the buffer was full
+    pub lost: u64,
+}
+
+/// When the kernel wakes up a [`PerfBuffer`] waiting for samples.
+#[derive(Clone, Copy, Debug)]
+enum Wakeup {
+    /// After this many samples
+    Events(u32),
+    /// Once this many bytes are in the buffer
+    Watermark(u32),
+}
+
+/// The map a [`PerfBufferBuilder`] builds a [`PerfBuffer`] for.
+enum BuilderMap<'a> {
+    Borrowed(&'a Map),
+    Owned(MapHandle),
+}
+
+impl BuilderMap<'_> {
+    fn map(&self) -> &Map {
+        match self {
+            BuilderMap::Borrowed(map) => map,
+            BuilderMap::Owned(handle) => handle,
+        }
+    }
 }
 
 /// Builds [`PerfBuffer`] instances.
 pub struct PerfBufferBuilder<'a> {
-    map: &'a Map,
+    map: BuilderMap<'a>,
     pages: usize,
-    sample_cb: Option<Box<dyn SampleCb>>,
+    wakeup: Wakeup,
+    cpus: Option<Vec<i32>>,
+    sample_limit: Option<u64>,
+    sample_cb: Option<Box<dyn ControlSampleCb>>,
     lost_cb: Option<Box<dyn LostCb>>,
 }
 
 impl<'a> PerfBufferBuilder<'a> {
     pub fn new(map: &'a Map) -> Self {
+        Self::with_map(BuilderMap::Borrowed(map))
+    }
+
+    fn with_map(map: BuilderMap<'a>) -> Self {
         Self {
             map,
             pages: 64,
+            wakeup: Wakeup::Events(1),
+            cpus: None,
+            sample_limit: None,
             sample_cb: None,
             lost_cb: None,
         }
     }
 }
 
+impl PerfBufferBuilder<'static> {
+    /// Like [`PerfBufferBuilder::new()`], but taking ownership of `map`, e.g. a [`MapHandle`]
+    /// created from a [`Map`] of a skeleton, so that the skeleton stays free to be used
+    /// otherwise. The [`PerfBuffer`] keeps the map open.
+    pub fn from_map_handle(map: MapHandle) -> Self {
+        Self::with_map(BuilderMap::Owned(map))
+    }
+}
+
 impl<'a> PerfBufferBuilder<'a> {
     /// Callback to run when a sample is received.
     ///
@@ -49,12 +129,25 @@ impl<'a> PerfBufferBuilder<'a> {
     /// [`plain`](https://crates.io/crates/plain) helpful.
     ///
     /// Callback arguments are: `(cpu, data)`.
-    pub fn sample_cb<NewCb: SampleCb>(self, cb: NewCb) -> PerfBufferBuilder<'a> {
+    pub fn sample_cb<NewCb: SampleCb>(self, mut cb: NewCb) -> PerfBufferBuilder<'a> {
+        self.sample_cb_with_control(move |cpu, data: &[u8]| {
+            cb(cpu, data);
+            ControlFlow::Continue(())
+        })
+    }
+
+    /// Like [`PerfBufferBuilder::sample_cb()`], but the callback decides whether to go on.
+    ///
+    /// Returning [`ControlFlow::Break`] makes the ongoing [`PerfBuffer::poll()`] or
+    /// [`PerfBuffer::consume()`] return right away, e.g. to stop after a number of samples or on
+    /// a sentinel sample. The remaining samples are processed by the next call.
+    pub fn sample_cb_with_control<NewCb: ControlSampleCb>(
+        self,
+        cb: NewCb,
+    ) -> PerfBufferBuilder<'a> {
         PerfBufferBuilder {
-            map: self.map,
-            pages: self.pages,
             sample_cb: Some(Box::new(cb)),
-            lost_cb: self.lost_cb,
+            ..self
         }
     }
 
@@ -63,21 +156,84 @@ impl<'a> PerfBufferBuilder<'a> {
     /// Callback arguments are: `(cpu, lost_count)`.
     pub fn lost_cb<NewCb: LostCb>(self, cb: NewCb) -> PerfBufferBuilder<'a> {
         PerfBufferBuilder {
-            map: self.map,
-            pages: self.pages,
-            sample_cb: self.sample_cb,
             lost_cb: Some(Box::new(cb)),
+            ..self
         }
     }
 
     /// The number of pages to size the ring buffer.
+    ///
+    /// Each CPU gets a ring buffer of this size. Larger buffers lose fewer samples during bursts.
     pub fn pages(&mut self, pages: usize) -> &mut Self {
         self.pages = pages;
         self
     }
 
+    /// Only set up buffers for `cpus`, instead of for every online CPU, to save memory when the
+    /// BPF program only runs on some CPUs.
+    ///
+    /// The buffer of a CPU is the one the program writes to with `BPF_F_CURRENT_CPU` on that
+    /// CPU. All `cpus` have to be online.
+    pub fn cpus(&mut self, cpus: &[usize]) -> &mut Self {
+        self.cpus = Some(cpus.iter().map(|cpu| *cpu as i32).collect());
+        self
+    }
+
+    /// Process at most `samples` samples per [`PerfBuffer::poll()`] or [`PerfBuffer::consume()`]
+    /// call, so that a burst of samples does not hold up the rest of the application for long.
+    ///
+    /// The remaining samples are processed by the next calls, which start with the buffers of
+    /// the CPUs that did not get their turn. A `poll()` following one that hit the limit does not
+    /// wait for new samples.
+    pub fn max_samples_per_poll(&mut self, samples: u64) -> &mut Self {
+        self.sample_limit = Some(samples);
+        self
+    }
+
+    /// Wake up a waiting [`PerfBuffer::poll()`] only after `events` samples were written to a
+    /// CPU's buffer, instead of after each one. Defaults to 1.
+    ///
+    /// Fewer wakeups cost less CPU time when there are many samples, but samples take longer to
+    /// arrive. Overrides [`PerfBufferBuilder::wakeup_watermark()`].
+    pub fn wakeup_events(&mut self, events: u32) -> &mut Self {
+        self.wakeup = Wakeup::Events(events);
+        self
+    }
+
+    /// Wake up a waiting [`PerfBuffer::poll()`] only once a CPU's buffer holds at least `bytes`
+    /// bytes of samples. Overrides [`PerfBufferBuilder::wakeup_events()`].
+    pub fn wakeup_watermark(&mut self, bytes: u32) -> &mut Self {
+        self.wakeup = Wakeup::Watermark(bytes);
+        self
+    }
+
+    /// Build a [`SendPerfBuffer`] that calls `sample_cb` and `lost_cb`, in place of any
+    /// callbacks already registered with the builder, so that it can be moved to a thread
+    /// dedicated to polling it.
+    ///
+    /// The callbacks work like the ones of [`PerfBufferBuilder::sample_cb_with_control()`] and
+    /// [`PerfBufferBuilder::lost_cb()`], but have to be `Send`.
+    pub fn build_send<NewSampleCb, NewLostCb>(
+        self,
+        sample_cb: NewSampleCb,
+        lost_cb: NewLostCb,
+    ) -> Result<SendPerfBuffer>
+    where
+        NewSampleCb: ControlSampleCb + Send,
+        NewLostCb: LostCb + Send,
+    {
+        let perf = PerfBufferBuilder {
+            sample_cb: Some(Box::new(sample_cb)),
+            lost_cb: Some(Box::new(lost_cb)),
+            ..self
+        }
+        .build()?;
+
+        Ok(SendPerfBuffer { perf })
+    }
+
     pub fn build(self) -> Result<PerfBuffer> {
-        if self.map.map_type() != MapType::PerfEventArray {
+        if self.map.map().map_type() != MapType::PerfEventArray {
             return Err(Error::InvalidInput(
                 "Must use a PerfEventArray map".to_string(),
             ));
@@ -89,76 +245,265 @@ impl<'a> PerfBufferBuilder<'a> {
             ));
         }
 
-        let c_sample_cb: libbpf_sys::perf_buffer_sample_fn = if self.sample_cb.is_some() {
-            Some(Self::call_sample_cb)
-        } else {
-            None
-        };
+        if self.sample_limit == Some(0) {
+            return Err(Error::InvalidInput(
+                "Sample limit must be at least 1".to_string(),
+            ));
+        }
 
-        let c_lost_cb: libbpf_sys::perf_buffer_lost_fn = if self.lost_cb.is_some() {
-            Some(Self::call_lost_cb)
-        } else {
-            None
+        if matches!(&self.cpus, Some(cpus) if cpus.is_empty()) {
+            return Err(Error::InvalidInput(
+                "No CPUs to set up buffers for".to_string(),
+            ));
+        }
+
+        // The same event that `perf_buffer__new()` sets up, except for when to wake up
+        let mut attr = libbpf_sys::perf_event_attr {
+            type_: libbpf_sys::PERF_TYPE_SOFTWARE,
+            size: mem::size_of::<libbpf_sys::perf_event_attr>() as u32,
+            config: libbpf_sys::PERF_COUNT_SW_BPF_OUTPUT as u64,
+            sample_type: libbpf_sys::PERF_SAMPLE_RAW as u64,
+            ..Default::default()
         };
+        attr.__bindgen_anon_1.sample_period = 1;
+        match self.wakeup {
+            Wakeup::Events(events) => attr.__bindgen_anon_2.wakeup_events = events,
+            Wakeup::Watermark(bytes) => {
+                attr.set_watermark(1);
+                attr.__bindgen_anon_2.wakeup_watermark = bytes;
+            }
+        }
 
         let callback_struct_ptr = Box::into_raw(Box::new(CbStruct {
             sample_cb: self.sample_cb,
             lost_cb: self.lost_cb,
+            stopped: Cell::new(false),
+            sample_limit: self.sample_limit,
+            samples_left: Cell::new(self.sample_limit.unwrap_or(0)),
+            stats: RefCell::new(BTreeMap::new()),
         }));
 
-        let opts = libbpf_sys::perf_buffer_opts {
-            sample_cb: c_sample_cb,
-            lost_cb: c_lost_cb,
-            ctx: callback_struct_ptr as *mut _,
+        let mut cpus = self.cpus.unwrap_or_default();
+        // Programs write to the buffer of the current CPU, which is at the key of the CPU
+        let mut map_keys = cpus.clone();
+        let opts = libbpf_sys::perf_buffer_raw_opts {
+            sz: mem::size_of::<libbpf_sys::perf_buffer_raw_opts>() as libbpf_sys::size_t,
+            // No CPUs means all of them
+            cpu_cnt: cpus.len() as i32,
+            cpus: cpus.as_mut_ptr(),
+            map_keys: map_keys.as_mut_ptr(),
+            ..Default::default()
         };
 
         let ptr = unsafe {
-            libbpf_sys::perf_buffer__new(self.map.fd(), self.pages as libbpf_sys::size_t, &opts)
+            libbpf_sys::perf_buffer__new_raw(
+                self.map.map().fd().as_raw_fd(),
+                self.pages as libbpf_sys::size_t,
+                &mut attr,
+                Some(Self::call_event_cb),
+                callback_struct_ptr as *mut _,
+                &opts,
+            )
         };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            drop(unsafe { Box::from_raw(callback_struct_ptr) });
+            Err(Error::System(-err as i32))
         } else {
             Ok(PerfBuffer {
                 ptr,
-                _cb_struct: unsafe { Box::from_raw(callback_struct_ptr) },
+                cb_struct: unsafe { Box::from_raw(callback_struct_ptr) },
+                next_buffer: Cell::new(0),
+                _map: match self.map {
+                    BuilderMap::Borrowed(_) => None,
+                    BuilderMap::Owned(handle) => Some(handle),
+                },
             })
         }
     }
 
-    unsafe extern "C" fn call_sample_cb(ctx: *mut c_void, cpu: i32, data: *mut c_void, size: u32) {
+    unsafe extern "C" fn call_event_cb(
+        ctx: *mut c_void,
+        cpu: i32,
+        event: *mut libbpf_sys::perf_event_header,
+    ) -> libbpf_sys::bpf_perf_event_ret {
         let callback_struct = ctx as *mut CbStruct;
+        // libbpf hands out whole records, even if they wrap around the end of the ring buffer
+        let body = (event as *const u8).add(mem::size_of::<libbpf_sys::perf_event_header>());
 
-        if let Some(cb) = &mut (*callback_struct).sample_cb {
-            cb(cpu, slice::from_raw_parts(data as *const u8, size as usize));
+        match (*event).type_ {
+            libbpf_sys::PERF_RECORD_SAMPLE => {
+                // The size of the raw sample, followed by the sample
+                let size = ptr::read_unaligned(body as *const u32);
+                let data = body.add(mem::size_of::<u32>());
+                {
+                    let mut stats = (*callback_struct).stats.borrow_mut();
+                    let stats = stats.entry(cpu as usize).or_default();
+                    stats.samples += 1;
+                    stats.bytes += size as u64;
+                }
+                if let Some(cb) = &mut (*callback_struct).sample_cb {
+                    if cb(cpu, slice::from_raw_parts(data, size as usize)).is_break() {
+                        // Stop processing this buffer, the others are skipped by the caller
+                        (*callback_struct).stopped.set(true);
+                        return libbpf_sys::LIBBPF_PERF_EVENT_DONE;
+                    }
+                }
+                if (*callback_struct).sample_limit.is_some() {
+                    let samples_left = &(*callback_struct).samples_left;
+                    samples_left.set(samples_left.get().saturating_sub(1));
+                    if samples_left.get() == 0 {
+                        (*callback_struct).stopped.set(true);
+                        return libbpf_sys::LIBBPF_PERF_EVENT_DONE;
+                    }
+                }
+            }
+            libbpf_sys::PERF_RECORD_LOST => {
+                // The id of the event, followed by the number of lost samples
+                let count = ptr::read_unaligned(body.add(mem::size_of::<u64>()) as *const u64);
+                (*callback_struct)
+                    .stats
+                    .borrow_mut()
+                    .entry(cpu as usize)
+                    .or_default()
+                    .lost += count;
+                if let Some(cb) = &mut (*callback_struct).lost_cb {
+                    cb(cpu, count);
+                }
+            }
+            // Only samples and lost records are enabled, but don't choke on anything else
+            _ => (),
         }
-    }
 
-    unsafe extern "C" fn call_lost_cb(ctx: *mut c_void, cpu: i32, count: u64) {
-        let callback_struct = ctx as *mut CbStruct;
-
-        if let Some(cb) = &mut (*callback_struct).lost_cb {
-            cb(cpu, count);
-        }
+        libbpf_sys::LIBBPF_PERF_EVENT_CONT
     }
 }
 
 /// Represents a special kind of [`Map`]. Typically used to transfer data between
 /// [`Program`]s and userspace.
+///
+/// Use [`PerfBufferBuilder::build_send()`] for a buffer that can be moved to another thread.
 pub struct PerfBuffer {
     ptr: *mut libbpf_sys::perf_buffer,
     // Hold onto the box so it'll get dropped when PerfBuffer is dropped
-    _cb_struct: Box<CbStruct>,
+    cb_struct: Box<CbStruct>,
+    /// Buffer to start consuming at, which moves on when stopping early so that a busy CPU
+    /// cannot starve the others
+    next_buffer: Cell<u64>,
+    /// libbpf removes the buffers from the map on free, so an owned map has to outlive `ptr`
+    _map: Option<MapHandle>,
 }
 
 impl PerfBuffer {
+    /// Wait up to `timeout` for samples, then process the samples in all per-CPU buffers, like
+    /// [`PerfBuffer::consume()`].
     pub fn poll(&self, timeout: Duration) -> Result<()> {
-        let ret = unsafe { libbpf_sys::perf_buffer__poll(self.ptr, timeout.as_millis() as i32) };
+        // Not `perf_buffer__poll()`, which cannot stop in between buffers without dropping a
+        // sample, see `PerfBuffer::consume()`
+        if self.limit_reached() {
+            // Samples may be left over from the last call, which won't wake up epoll again
+            return self.consume();
+        }
+
+        let mut event = libc::epoll_event { events: 0, u64: 0 };
+        let ret = unsafe {
+            libc::epoll_wait(
+                self.epoll_fd().as_raw_fd(),
+                &mut event,
+                1,
+                timeout.as_millis() as i32,
+            )
+        };
         if ret < 0 {
-            Err(Error::System(-ret))
-        } else {
-            Ok(())
+            return Err(Error::System(errno::errno()));
+        }
+        if ret == 0 {
+            return Ok(());
         }
+
+        self.consume()
+    }
+
+    /// Keep polling for samples until `stop` is set, e.g. by a signal handler or another thread.
+    ///
+    /// `stop` is checked at least every 100ms. Polls interrupted by signals are retried, so the
+    /// loop ends soon after a signal whose handler sets `stop`.
+    pub fn run(&self, stop: &AtomicBool) -> Result<()> {
+        while !stop.load(Ordering::Relaxed) {
+            match self.poll(RUN_POLL_TIMEOUT) {
+                Err(Error::System(errno)) if errno == libc::EINTR => continue,
+                result => result?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Process the samples in all per-CPU buffers, calling the registered callbacks for each,
+    /// without waiting for any to arrive. Useful after learning that data is ready from an
+    /// event loop watching [`PerfBuffer::epoll_fd()`].
+    pub fn consume(&self) -> Result<()> {
+        self.cb_struct.stopped.set(false);
+        if let Some(limit) = self.cb_struct.sample_limit {
+            self.cb_struct.samples_left.set(limit);
+        }
+
+        // Consume buffer by buffer, as every record that libbpf hands to `call_event_cb()` is
+        // gone from its buffer, even if the callback asks to stop
+        let cnt = unsafe { libbpf_sys::perf_buffer__buffer_cnt(self.ptr) };
+        let start = self.next_buffer.get();
+        for idx in (start..cnt).chain(0..start.min(cnt)) {
+            let ret = unsafe { libbpf_sys::perf_buffer__consume_buffer(self.ptr, idx) };
+            // There are no buffers for offline CPUs
+            if ret < 0 && ret != -libc::ENOENT {
+                // Error code is returned negative, flip to positive to match errno
+                return Err(Error::System(-ret));
+            }
+            if self.cb_struct.stopped.get() {
+                self.next_buffer.set((idx + 1) % cnt);
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the last [`PerfBuffer::consume()`] stopped because of the sample limit.
+    fn limit_reached(&self) -> bool {
+        self.cb_struct.sample_limit.is_some() && self.cb_struct.samples_left.get() == 0
+    }
+
+    /// Returns statistics about the samples received so far, by CPU. CPUs without any samples,
+    /// lost or not, are left out.
+    pub fn stats(&self) -> BTreeMap<usize, PerfBufferStats> {
+        self.cb_struct.stats.borrow().clone()
+    }
+
+    /// Replace the callbacks registered with the [`PerfBufferBuilder`].
+    #[cfg(feature = "tokio")]
+    pub(crate) fn set_callbacks(
+        &mut self,
+        sample_cb: Box<dyn ControlSampleCb + Send>,
+        lost_cb: Box<dyn LostCb + Send>,
+    ) {
+        self.cb_struct.sample_cb = Some(sample_cb);
+        self.cb_struct.lost_cb = Some(lost_cb);
+    }
+
+    /// Returns the epoll file descriptor that all per-CPU buffers are registered with, to
+    /// integrate the buffer into an existing event loop.
+    ///
+    /// The file descriptor becomes readable when there is data in any of the buffers, which
+    /// [`PerfBuffer::consume()`] then processes.
+    pub fn epoll_fd(&self) -> BorrowedFd<'_> {
+        // The fd is owned by the buffer and valid for as long as it exists
+        unsafe { BorrowedFd::borrow_raw(libbpf_sys::perf_buffer__epoll_fd(self.ptr)) }
+    }
+}
+
+impl AsFd for PerfBuffer {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.epoll_fd()
     }
 }
 
@@ -170,10 +515,98 @@ impl Drop for PerfBuffer {
     }
 }
 
+/// A [`PerfBuffer`] whose callbacks are `Send`, built with [`PerfBufferBuilder::build_send()`].
+///
+/// It can be moved to another thread to poll it there, but not be polled from several threads
+/// at once.
+pub struct SendPerfBuffer {
+    perf: PerfBuffer,
+}
+
+impl SendPerfBuffer {
+    /// Turn the buffer back into a plain [`PerfBuffer`].
+    pub fn into_inner(self) -> PerfBuffer {
+        self.perf
+    }
+}
+
+// libbpf's perf buffer is not tied to the thread that created it, and `build_send()` only
+// accepts `Send` callbacks
+unsafe impl Send for SendPerfBuffer {}
+
+impl Deref for SendPerfBuffer {
+    type Target = PerfBuffer;
+
+    fn deref(&self) -> &PerfBuffer {
+        &self.perf
+    }
+}
+
+/// A [`PerfBuffer`] whose samples are of type `T`, e.g. an event struct generated for a
+/// skeleton.
+///
+/// The sample callback receives samples as `T` instead of raw bytes. `T` must implement
+/// [`plain::Plain`], i.e. be valid for any bit pattern. Samples may be longer than `T`, as the
+/// kernel pads them, but samples too short for `T` are dropped and counted in
+/// [`TypedPerfBuffer::short_samples()`].
+pub struct TypedPerfBuffer<T: Plain> {
+    perf: PerfBuffer,
+    short_samples: Arc<AtomicU64>,
+    _t: PhantomData<T>,
+}
+
+impl<T: Plain + 'static> TypedPerfBuffer<T> {
+    /// Build the buffer from `builder`, with `sample_cb` in place of any sample callback
+    /// already registered with it.
+    ///
+    /// Callback arguments are: `(cpu, sample)`.
+    pub fn new<F>(builder: PerfBufferBuilder<'_>, mut sample_cb: F) -> Result<Self>
+    where
+        F: FnMut(i32, &T) + 'static,
+    {
+        let short_samples = Arc::new(AtomicU64::new(0));
+        let short_samples_clone = short_samples.clone();
+        let perf = builder
+            .sample_cb(move |cpu, data: &[u8]| {
+                if data.len() < mem::size_of::<T>() {
+                    short_samples_clone.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+
+                // Samples are not necessarily aligned for `T`, so read them unaligned. `Plain`
+                // guarantees every bit pattern is a valid `T`.
+                let sample = unsafe { ptr::read_unaligned(data.as_ptr() as *const T) };
+                sample_cb(cpu, &sample);
+            })
+            .build()?;
+
+        Ok(TypedPerfBuffer {
+            perf,
+            short_samples,
+            _t: PhantomData,
+        })
+    }
+
+    /// Returns the number of samples dropped because they were shorter than `T`.
+    pub fn short_samples(&self) -> u64 {
+        self.short_samples.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: Plain> Deref for TypedPerfBuffer<T> {
+    type Target = PerfBuffer;
+
+    fn deref(&self) -> &PerfBuffer {
+        &self.perf
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::rc::Rc;
+
     fn is_power_of_two_slow(i: usize) -> bool {
         if i == 0 {
             return false;
@@ -195,4 +628,114 @@ mod tests {
             assert_eq!(is_power_of_two(i), is_power_of_two_slow(i));
         }
     }
+
+    #[test]
+    fn test_call_event_cb() {
+        let samples = Rc::new(RefCell::new(Vec::new()));
+        let lost = Rc::new(RefCell::new(Vec::new()));
+        let samples_clone = samples.clone();
+        let lost_clone = lost.clone();
+        let mut cb_struct = CbStruct {
+            sample_cb: Some(Box::new(move |cpu, data: &[u8]| {
+                samples_clone.borrow_mut().push((cpu, data.to_vec()));
+                if data == [0xff] {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })),
+            lost_cb: Some(Box::new(move |cpu, count| {
+                lost_clone.borrow_mut().push((cpu, count))
+            })),
+            stopped: Cell::new(false),
+            sample_limit: None,
+            samples_left: Cell::new(0),
+            stats: RefCell::new(BTreeMap::new()),
+        };
+        let ctx = &mut cb_struct as *mut CbStruct as *mut c_void;
+
+        // Records are made up of u64s, so that the header is properly aligned
+        let mut sample = [0u64; 3];
+        let header = libbpf_sys::perf_event_header {
+            type_: libbpf_sys::PERF_RECORD_SAMPLE,
+            misc: 0,
+            size: 20,
+        };
+        unsafe {
+            ptr::write(
+                sample.as_mut_ptr() as *mut libbpf_sys::perf_event_header,
+                header,
+            );
+            let body = (sample.as_mut_ptr() as *mut u8).add(8);
+            ptr::write_unaligned(body as *mut u32, 4);
+            ptr::copy_nonoverlapping([1u8, 2, 3, 4].as_ptr(), body.add(4), 4);
+        }
+        let ret =
+            unsafe { PerfBufferBuilder::call_event_cb(ctx, 3, sample.as_mut_ptr() as *mut _) };
+        assert_eq!(ret, libbpf_sys::LIBBPF_PERF_EVENT_CONT);
+        assert_eq!(*samples.borrow(), vec![(3, vec![1, 2, 3, 4])]);
+        assert!(!cb_struct.stopped.get());
+
+        // A sentinel sample that makes the callback stop
+        unsafe {
+            let body = (sample.as_mut_ptr() as *mut u8).add(8);
+            ptr::write_unaligned(body as *mut u32, 1);
+            *body.add(4) = 0xff;
+        }
+        let ret =
+            unsafe { PerfBufferBuilder::call_event_cb(ctx, 3, sample.as_mut_ptr() as *mut _) };
+        assert_eq!(ret, libbpf_sys::LIBBPF_PERF_EVENT_DONE);
+        assert!(cb_struct.stopped.get());
+
+        // Stop once the sample limit is reached
+        cb_struct.stopped.set(false);
+        cb_struct.sample_limit = Some(2);
+        cb_struct.samples_left.set(2);
+        unsafe {
+            let body = (sample.as_mut_ptr() as *mut u8).add(8);
+            *body.add(4) = 0;
+        }
+        let ret =
+            unsafe { PerfBufferBuilder::call_event_cb(ctx, 3, sample.as_mut_ptr() as *mut _) };
+        assert_eq!(ret, libbpf_sys::LIBBPF_PERF_EVENT_CONT);
+        let ret =
+            unsafe { PerfBufferBuilder::call_event_cb(ctx, 3, sample.as_mut_ptr() as *mut _) };
+        assert_eq!(ret, libbpf_sys::LIBBPF_PERF_EVENT_DONE);
+        assert!(cb_struct.stopped.get());
+        cb_struct.sample_limit = None;
+
+        let mut lost_record = [0u64; 4];
+        let header = libbpf_sys::perf_event_header {
+            type_: libbpf_sys::PERF_RECORD_LOST,
+            misc: 0,
+            size: 32,
+        };
+        unsafe {
+            ptr::write(
+                lost_record.as_mut_ptr() as *mut libbpf_sys::perf_event_header,
+                header,
+            );
+        }
+        lost_record[2] = 42;
+        unsafe { PerfBufferBuilder::call_event_cb(ctx, 1, lost_record.as_mut_ptr() as *mut _) };
+        assert_eq!(*lost.borrow(), vec![(1, 42)]);
+
+        let stats = cb_struct.stats.borrow();
+        assert_eq!(
+            stats[&3],
+            PerfBufferStats {
+                samples: 4,
+                bytes: 7,
+                lost: 0,
+            }
+        );
+        assert_eq!(
+            stats[&1],
+            PerfBufferStats {
+                samples: 0,
+                bytes: 0,
+                lost: 42,
+            }
+        );
+    }
 }
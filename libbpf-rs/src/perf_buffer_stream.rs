@@ -0,0 +1,105 @@
+use std::collections::VecDeque;
+use std::ops::ControlFlow;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use nix::libc;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+use crate::*;
+
+impl PerfBuffer {
+    /// Turn the buffer into a [`Stream`] of the events it receives, for consuming it from async
+    /// code without blocking a worker thread.
+    ///
+    /// The stream replaces the callbacks registered with the [`PerfBufferBuilder`]. It has to
+    /// be created from within a tokio runtime with IO enabled.
+    pub fn stream(mut self) -> Result<PerfBufferStream> {
+        let events = Arc::new(Mutex::new(VecDeque::new()));
+
+        let sample_events = events.clone();
+        let lost_events = events.clone();
+        self.set_callbacks(
+            Box::new(move |cpu, data: &[u8]| {
+                sample_events
+                    .lock()
+                    .unwrap()
+                    .push_back(PerfBufferEvent::Sample {
+                        cpu,
+                        data: data.to_vec(),
+                    });
+                ControlFlow::Continue(())
+            }),
+            Box::new(move |cpu, count| {
+                lost_events
+                    .lock()
+                    .unwrap()
+                    .push_back(PerfBufferEvent::Lost { cpu, count })
+            }),
+        );
+
+        let async_fd = AsyncFd::with_interest(self.epoll_fd().as_raw_fd(), Interest::READABLE)
+            .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)))?;
+
+        Ok(PerfBufferStream {
+            async_fd,
+            perf: self,
+            events,
+        })
+    }
+}
+
+/// An event received by a [`PerfBufferStream`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PerfBufferEvent {
+    /// A sample written by a BPF program on `cpu`
+    Sample { cpu: i32, data: Vec<u8> },
+    /// The kernel dropped `count` samples of `cpu` because the buffer was full
+    Lost { cpu: i32, count: u64 },
+}
+
+/// A [`PerfBuffer`] consumed as an async stream, see [`PerfBuffer::stream()`].
+pub struct PerfBufferStream {
+    // Deregisters the epoll fd of `perf` from the runtime, so it has to be dropped first
+    async_fd: AsyncFd<RawFd>,
+    perf: PerfBuffer,
+    events: Arc<Mutex<VecDeque<PerfBufferEvent>>>,
+}
+
+// libbpf's perf buffer is not tied to the thread that created it, and all callbacks were
+// replaced by `Send` ones
+unsafe impl Send for PerfBufferStream {}
+
+impl Stream for PerfBufferStream {
+    type Item = Result<PerfBufferEvent>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(event) = this.events.lock().unwrap().pop_front() {
+                return Poll::Ready(Some(Ok(event)));
+            }
+
+            let mut guard = match this.async_fd.poll_read_ready(cx) {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(e)) => {
+                    let err = Error::System(e.raw_os_error().unwrap_or(libc::EINVAL));
+                    return Poll::Ready(Some(Err(err)));
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+
+            if let Err(e) = this.perf.consume() {
+                return Poll::Ready(Some(Err(e)));
+            }
+            if this.events.lock().unwrap().is_empty() {
+                // Drained, wait for the next wakeup
+                guard.clear_ready();
+            }
+        }
+    }
+}
@@ -1,7 +1,18 @@
 use std::convert::TryFrom;
+use std::fs;
+use std::io::Write;
+use std::mem;
+use std::os::raw::{c_char, c_ulong, c_void};
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd};
 use std::path::Path;
+use std::process;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
-use nix::errno;
+use bitflags::bitflags;
+use nix::{errno, libc};
 use num_enum::TryFromPrimitive;
 use strum_macros::Display;
 
@@ -12,30 +23,212 @@ use crate::*;
 /// This object exposes operations that need to happen before the program is loaded.
 pub struct OpenProgram {
     ptr: *mut libbpf_sys::bpf_program,
+    // Handed to libbpf, so it must not be reallocated
+    pub(crate) log_buf: Vec<u8>,
 }
 
 impl OpenProgram {
     pub(crate) fn new(ptr: *mut libbpf_sys::bpf_program) -> Self {
-        OpenProgram { ptr }
+        OpenProgram {
+            ptr,
+            log_buf: Vec::new(),
+        }
+    }
+
+    /// Verifier log level used when loading the program. See
+    /// [`OpenProgram::set_log_level()`].
+    pub fn log_level(&self) -> u32 {
+        unsafe { libbpf_sys::bpf_program__log_level(self.ptr) }
+    }
+
+    /// Set the verifier log level used when loading the program: 1 for the instructions the
+    /// verifier rejected, 2 for every instruction it checked, and 4 for statistics. Levels can
+    /// be combined.
+    ///
+    /// With level 0, the default, the verifier only logs if it rejects the program.
+    pub fn set_log_level(&mut self, log_level: u32) -> Result<()> {
+        let ret = unsafe { libbpf_sys::bpf_program__set_log_level(self.ptr, log_level) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            Err(Error::System(-ret))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Collect up to `size` bytes of verifier log for this program instead of printing it, to
+    /// be retrieved with [`Program::log_buf()`] after loading, or with
+    /// [`OpenProgram::log_buf()`] if loading failed (see [`OpenObject::load_or_recover()`]).
+    ///
+    /// The kernel fails to load the program with `ENOSPC` if the log does not fit.
+    pub fn set_log_buf_size(&mut self, size: usize) -> Result<()> {
+        let mut log_buf = vec![0; size];
+        let ret = unsafe {
+            libbpf_sys::bpf_program__set_log_buf(
+                self.ptr,
+                log_buf.as_mut_ptr() as *mut c_char,
+                log_buf.len() as libbpf_sys::size_t,
+            )
+        };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret));
+        }
+
+        self.log_buf = log_buf;
+        Ok(())
+    }
+
+    /// Verifier log collected according to [`OpenProgram::set_log_buf_size()`], if any.
+    pub fn log_buf(&self) -> Option<&str> {
+        log_buf_str(&self.log_buf)
+    }
+
+    /// Type the program will be loaded as, usually derived from its section.
+    pub fn prog_type(&self) -> ProgramType {
+        match ProgramType::try_from(unsafe { libbpf_sys::bpf_program__type(self.ptr) }) {
+            Ok(ty) => ty,
+            Err(_) => ProgramType::Unknown,
+        }
     }
 
+    /// Override the type the program will be loaded as, e.g. to load a program from a generic
+    /// section as either [`ProgramType::CgroupSkb`] or [`ProgramType::SocketFilter`] depending
+    /// on how it will be used.
+    ///
+    /// This is also needed for programs whose section libbpf does not recognize. The expected
+    /// attach type is left alone and may have to be set with
+    /// [`OpenProgram::set_attach_type()`] as well.
     pub fn set_prog_type(&mut self, prog_type: ProgramType) {
         unsafe {
             libbpf_sys::bpf_program__set_type(self.ptr, prog_type as u32);
         }
     }
 
+    /// Attach type the kernel will expect the program to be attached with, usually derived from
+    /// its section.
+    pub fn attach_type(&self) -> ProgramAttachType {
+        match ProgramAttachType::try_from(unsafe {
+            libbpf_sys::bpf_program__expected_attach_type(self.ptr)
+        }) {
+            Ok(ty) => ty,
+            Err(_) => ProgramAttachType::Unknown,
+        }
+    }
+
+    /// Override the attach type the kernel will expect the program to be attached with, e.g.
+    /// [`ProgramAttachType::CgroupInetIngress`] or [`ProgramAttachType::CgroupInetEgress`] for
+    /// a [`ProgramType::CgroupSkb`] program.
     pub fn set_attach_type(&mut self, attach_type: ProgramAttachType) {
         unsafe {
             libbpf_sys::bpf_program__set_expected_attach_type(self.ptr, attach_type as u32);
         }
     }
 
+    /// Whether the program is loaded along with its object. Defaults to `true`, unless the
+    /// program's section starts with `?`.
+    pub fn autoload(&self) -> bool {
+        unsafe { libbpf_sys::bpf_program__autoload(self.ptr) }
+    }
+
+    /// Choose whether the program is loaded along with its object, e.g. to only load one of
+    /// several variants of a program depending on what the running kernel supports.
+    ///
+    /// Programs that are not loaded are missing from the loaded [`Object`].
+    pub fn set_autoload(&mut self, autoload: bool) -> Result<()> {
+        let ret = unsafe { libbpf_sys::bpf_program__set_autoload(self.ptr, autoload) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            Err(Error::System(-ret))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Offload the program to the network device with index `idx`, e.g. a SmartNIC, instead of
+    /// loading it into the kernel. The maps it uses have to be offloaded to the same device
+    /// with [`OpenMap::set_map_ifindex()`].
+    ///
+    /// Offloaded XDP programs are attached with [`XdpFlags::HW_MODE`].
     pub fn set_ifindex(&mut self, idx: u32) {
         unsafe {
             libbpf_sys::bpf_program__set_ifindex(self.ptr, idx);
         }
     }
+
+    /// Instructions of the program as libbpf will load them, e.g. for auditing or signing.
+    ///
+    /// References to maps and other programs are only resolved while loading, so they are
+    /// still unresolved here.
+    pub fn insns(&self) -> &[libbpf_sys::bpf_insn] {
+        let count = self.insn_cnt();
+        if count == 0 {
+            return &[];
+        }
+
+        unsafe { slice::from_raw_parts(libbpf_sys::bpf_program__insns(self.ptr), count) }
+    }
+
+    /// Number of instructions of the program, see [`OpenProgram::insns()`].
+    pub fn insn_cnt(&self) -> usize {
+        unsafe { libbpf_sys::bpf_program__insn_cnt(self.ptr) as usize }
+    }
+
+    /// Choose the function an fentry, fexit or freplace program attaches to, overriding the one
+    /// named in its section.
+    ///
+    /// The function is looked up in the kernel if `attach_prog_fd` is `None`, and in the BPF
+    /// program `attach_prog_fd` otherwise. The lookup happens when the object is loaded, so
+    /// `attach_prog_fd` has to stay open until then. Without `attach_func_name`, only the target
+    /// program is replaced and the function is still taken from the section.
+    pub fn set_attach_target(
+        &mut self,
+        attach_prog_fd: Option<BorrowedFd<'_>>,
+        attach_func_name: Option<&str>,
+    ) -> Result<()> {
+        let name = attach_func_name.map(util::str_to_cstring).transpose()?;
+        let ret = unsafe {
+            libbpf_sys::bpf_program__set_attach_target(
+                self.ptr,
+                attach_prog_fd.map_or(0, |fd| fd.as_raw_fd()),
+                name.as_ref().map_or(ptr::null(), |name| name.as_ptr()),
+            )
+        };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            Err(Error::System(-ret))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+bitflags! {
+    /// Flags for [`Program::attach_xdp_with_flags()`]. Map to the `XDP_FLAGS_*` in kernel uapi.
+    ///
+    /// At most one of the modes may be set. Without any, the kernel picks the driver mode if
+    /// available and the generic mode otherwise.
+    pub struct XdpFlags: u32 {
+        /// Fail if the interface already has an XDP program.
+        const UPDATE_IF_NOEXIST = libbpf_sys::XDP_FLAGS_UPDATE_IF_NOEXIST;
+        /// Run the program in generic mode, after the kernel allocated the `sk_buff`.
+        const SKB_MODE          = libbpf_sys::XDP_FLAGS_SKB_MODE;
+        /// Run the program in the driver.
+        const DRV_MODE          = libbpf_sys::XDP_FLAGS_DRV_MODE;
+        /// Offload the program to the network card.
+        const HW_MODE           = libbpf_sys::XDP_FLAGS_HW_MODE;
+        /// Replace a given program.
+        const REPLACE           = libbpf_sys::XDP_FLAGS_REPLACE;
+    }
+}
+
+bitflags! {
+    /// Flags for [`Program::attach_netfilter()`]. Map to the `BPF_F_NETFILTER_*` flags in
+    /// kernel uapi.
+    pub struct NetfilterFlags: u32 {
+        /// Reassemble fragmented IP packets before the program sees them. Requires Linux 6.6.
+        const IP_DEFRAG = libbpf_sys::BPF_F_NETFILTER_IP_DEFRAG;
+    }
 }
 
 /// Type of a [`Program`]. Maps to `enum bpf_prog_type` in kernel uapi.
@@ -75,6 +268,7 @@ pub enum ProgramType {
     Lsm,
     SkLookup,
     Syscall,
+    Netfilter,
     /// See [`MapType::Unknown`]
     Unknown = u32::MAX,
 }
@@ -126,6 +320,20 @@ pub enum ProgramAttachType {
     SkReuseportSelect,
     SkReuseportSelectOrMigrate,
     PerfEvent,
+    TraceKprobeMulti,
+    LsmCgroup,
+    StructOps,
+    Netfilter,
+    TcxIngress,
+    TcxEgress,
+    TraceUprobeMulti,
+    CgroupUnixConnect,
+    CgroupUnixSendmsg,
+    CgroupUnixRecvmsg,
+    CgroupUnixGetpeername,
+    CgroupUnixGetsockname,
+    NetkitPrimary,
+    NetkitPeer,
     /// See [`MapType::Unknown`]
     Unknown = u32::MAX,
 }
@@ -141,11 +349,30 @@ pub struct Program {
     pub(crate) ptr: *mut libbpf_sys::bpf_program,
     name: String,
     section: String,
+    // Taken over from the `OpenProgram`, as libbpf still refers to it
+    pub(crate) log_buf: Vec<u8>,
 }
 
 impl Program {
     pub(crate) fn new(ptr: *mut libbpf_sys::bpf_program, name: String, section: String) -> Self {
-        Program { ptr, name, section }
+        Program {
+            ptr,
+            name,
+            section,
+            log_buf: Vec::new(),
+        }
+    }
+
+    /// Instructions of the program as translated by the verifier, i.e. after rewrites like
+    /// inlined helper calls, but before JIT compilation. Requires `CAP_SYS_ADMIN`.
+    pub fn xlated_insns(&self) -> Result<Vec<libbpf_sys::bpf_insn>> {
+        xlated_insns(self.fd())
+    }
+
+    /// Verifier log of loading this program, if it was collected with
+    /// [`OpenProgram::set_log_buf_size()`].
+    pub fn log_buf(&self) -> Option<&str> {
+        log_buf_str(&self.log_buf)
     }
 
     pub fn name(&self) -> &str {
@@ -165,8 +392,9 @@ impl Program {
     }
 
     /// Returns a file descriptor to the underlying program.
-    pub fn fd(&self) -> i32 {
-        unsafe { libbpf_sys::bpf_program__fd(self.ptr) }
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        // The program is loaded, so the fd is valid for as long as `self` exists
+        unsafe { BorrowedFd::borrow_raw(libbpf_sys::bpf_program__fd(self.ptr)) }
     }
 
     pub fn attach_type(&self) -> ProgramAttachType {
@@ -213,7 +441,33 @@ impl Program {
         let ptr = unsafe { libbpf_sys::bpf_program__attach(self.ptr) };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach a `SEC("iter/...")` program, restricting what it iterates over with `opts`.
+    /// Create an [`Iter`] from the returned link to run the iterator and read its output.
+    pub fn attach_iter(&mut self, opts: &IterOpts<'_>) -> Result<Link> {
+        let mut link_info = opts.to_link_info();
+        let attach_opts = libbpf_sys::bpf_iter_attach_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_iter_attach_opts>() as libbpf_sys::size_t,
+            link_info: link_info
+                .as_mut()
+                .map_or(ptr::null_mut(), |info| info as *mut _),
+            link_info_len: if link_info.is_some() {
+                mem::size_of::<libbpf_sys::bpf_iter_link_info>() as u32
+            } else {
+                0
+            },
+            ..Default::default()
+        };
+
+        let ptr = unsafe { libbpf_sys::bpf_program__attach_iter(self.ptr, &attach_opts) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
         } else {
             Ok(Link::new(ptr))
         }
@@ -221,27 +475,107 @@ impl Program {
 
     /// Attach this program to a
     /// [cgroup](https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html).
-    pub fn attach_cgroup(&mut self, cgroup_fd: i32) -> Result<Link> {
-        let ptr = unsafe { libbpf_sys::bpf_program__attach_cgroup(self.ptr, cgroup_fd) };
+    ///
+    /// Link based attachments always allow other programs to be attached to the same cgroup,
+    /// like [`CgroupAttachFlags::ALLOW_MULTI`] does for [`Program::attach_cgroup_with_opts()`].
+    pub fn attach_cgroup(&mut self, cgroup_fd: BorrowedFd<'_>) -> Result<Link> {
+        let ptr =
+            unsafe { libbpf_sys::bpf_program__attach_cgroup(self.ptr, cgroup_fd.as_raw_fd()) };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(-err as i32))
         } else {
             Ok(Link::new(ptr))
         }
     }
 
     /// Attach this program to a [perf event](https://linux.die.net/man/2/perf_event_open).
-    pub fn attach_perf_event(&mut self, pfd: i32) -> Result<Link> {
-        let ptr = unsafe { libbpf_sys::bpf_program__attach_perf_event(self.ptr, pfd) };
+    ///
+    /// The returned link takes ownership of `pfd` and closes it when destroyed.
+    pub fn attach_perf_event(&mut self, pfd: OwnedFd) -> Result<Link> {
+        let ptr = unsafe { libbpf_sys::bpf_program__attach_perf_event(self.ptr, pfd.as_raw_fd()) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            // libbpf closes the perf event fd when the link is destroyed
+            let _ = pfd.into_raw_fd();
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach this program to a [perf event](https://linux.die.net/man/2/perf_event_open),
+    /// e.g. a `SEC("perf_event")` program to a sampling event opened by the caller, with
+    /// additional options.
+    ///
+    /// The returned link takes ownership of `pfd` and closes it when destroyed.
+    pub fn attach_perf_event_with_opts(
+        &mut self,
+        pfd: OwnedFd,
+        opts: &PerfEventOpts,
+    ) -> Result<Link> {
+        let opts = opts.to_libbpf();
+        let ptr = unsafe {
+            libbpf_sys::bpf_program__attach_perf_event_opts(self.ptr, pfd.as_raw_fd(), &opts)
+        };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(-err as i32))
         } else {
+            // libbpf closes the perf event fd when the link is destroyed
+            let _ = pfd.into_raw_fd();
             Ok(Link::new(ptr))
         }
     }
 
+    /// Open a perf event of type `perf_type` (a `PERF_TYPE_*` constant) with `config` on every
+    /// online CPU and attach this program to each, e.g. to run a `SEC("perf_event")` program
+    /// for sampling all CPUs of the system. Returns one link per CPU.
+    ///
+    /// On error, the events opened so far are closed again.
+    pub fn attach_perf_event_per_cpu(
+        &mut self,
+        perf_type: u32,
+        config: u64,
+        sample: PerfSample,
+    ) -> Result<Vec<Link>> {
+        let mut attr = libbpf_sys::perf_event_attr {
+            type_: perf_type,
+            size: mem::size_of::<libbpf_sys::perf_event_attr>() as u32,
+            config,
+            ..Default::default()
+        };
+        match sample {
+            PerfSample::Frequency(freq) => {
+                attr.__bindgen_anon_1.sample_freq = freq;
+                attr.set_freq(1);
+            }
+            PerfSample::Period(period) => attr.__bindgen_anon_1.sample_period = period,
+        }
+
+        let mut links = Vec::new();
+        for cpu in util::online_cpus()? {
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_perf_event_open,
+                    &attr as *const libbpf_sys::perf_event_attr,
+                    -1,
+                    cpu as i32,
+                    -1,
+                    libbpf_sys::PERF_FLAG_FD_CLOEXEC,
+                )
+            };
+            if fd < 0 {
+                return Err(Error::System(errno::errno()));
+            }
+
+            let pfd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+            links.push(self.attach_perf_event(pfd)?);
+        }
+
+        Ok(links)
+    }
+
     /// Attach this program to a [userspace
     /// probe](https://www.kernel.org/doc/html/latest/trace/uprobetracer.html).
     pub fn attach_uprobe<T: AsRef<Path>>(
@@ -264,111 +598,1804 @@ impl Program {
         };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(-err as i32))
         } else {
             Ok(Link::new(ptr))
         }
     }
 
-    /// Attach this program to a [kernel
-    /// probe](https://www.kernel.org/doc/html/latest/trace/kprobetrace.html).
-    pub fn attach_kprobe<T: AsRef<str>>(&mut self, retprobe: bool, func_name: T) -> Result<Link> {
-        let func_name = util::str_to_cstring(func_name.as_ref())?;
-        let func_name_ptr = func_name.as_ptr();
-        let ptr =
-            unsafe { libbpf_sys::bpf_program__attach_kprobe(self.ptr, retprobe, func_name_ptr) };
+    /// Attach this program to a [userspace
+    /// probe](https://www.kernel.org/doc/html/latest/trace/uprobetracer.html) on the function
+    /// `func_name` of the ELF binary at `binary_path`.
+    ///
+    /// libbpf looks up the offset of the symbol, so unlike with [`Program::attach_uprobe()`] it
+    /// does not have to be determined up front. If `version` is set, only the symbol of that
+    /// version matches, e.g. `GLIBC_2.2.5` for `malloc` of glibc. `pid` works as for
+    /// [`Program::attach_uprobe()`].
+    pub fn attach_uprobe_symbol<T: AsRef<Path>>(
+        &mut self,
+        retprobe: bool,
+        pid: i32,
+        binary_path: T,
+        func_name: &str,
+        version: Option<&str>,
+    ) -> Result<Link> {
+        let func_name = match version {
+            Some(version) => format!("{}@{}", func_name, version),
+            None => func_name.to_string(),
+        };
+        let opts = UprobeOpts {
+            retprobe,
+            func_name: Some(func_name),
+            ..Default::default()
+        };
+        self.attach_uprobe_with_opts(pid, binary_path, 0, &opts)
+    }
+
+    /// Attach this program to a userspace return probe, i.e. run it whenever the function at
+    /// `func_offset` of the ELF binary at `binary_path` returns.
+    ///
+    /// `pid` works as for [`Program::attach_uprobe()`].
+    pub fn attach_uretprobe<T: AsRef<Path>>(
+        &mut self,
+        pid: i32,
+        binary_path: T,
+        func_offset: usize,
+    ) -> Result<Link> {
+        let opts = UprobeOpts {
+            retprobe: true,
+            ..Default::default()
+        };
+        self.attach_uprobe_with_opts(pid, binary_path, func_offset, &opts)
+    }
+
+    /// Attach this program to a [userspace
+    /// probe](https://www.kernel.org/doc/html/latest/trace/uprobetracer.html) of the ELF binary
+    /// at `binary_path`.
+    ///
+    /// The probe fires only in the process `pid`, or in all processes if `pid` is -1. It is
+    /// placed at `func_offset`, which is relative to the function `opts.func_name` if that is set
+    /// and to the start of the binary otherwise.
+    pub fn attach_uprobe_with_opts<T: AsRef<Path>>(
+        &mut self,
+        pid: i32,
+        binary_path: T,
+        func_offset: usize,
+        opts: &UprobeOpts,
+    ) -> Result<Link> {
+        let path = util::path_to_cstring(binary_path.as_ref())?;
+        let func_name = opts
+            .func_name
+            .as_deref()
+            .map(util::str_to_cstring)
+            .transpose()?;
+        let opts = libbpf_sys::bpf_uprobe_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_uprobe_opts>() as libbpf_sys::size_t,
+            ref_ctr_offset: opts.ref_ctr_offset as libbpf_sys::size_t,
+            bpf_cookie: opts.cookie,
+            retprobe: opts.retprobe,
+            func_name: func_name.as_ref().map_or(ptr::null(), |name| name.as_ptr()),
+            attach_mode: opts.attach_mode as libbpf_sys::probe_attach_mode,
+            ..Default::default()
+        };
+
+        let ptr = unsafe {
+            libbpf_sys::bpf_program__attach_uprobe_opts(
+                self.ptr,
+                pid,
+                path.as_ptr(),
+                func_offset as libbpf_sys::size_t,
+                &opts,
+            )
+        };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(-err as i32))
         } else {
             Ok(Link::new(ptr))
         }
     }
 
-    /// Attach this program to a [kernel
-    /// tracepoint](https://www.kernel.org/doc/html/latest/trace/tracepoints.html).
-    pub fn attach_tracepoint<T: AsRef<str>>(&mut self, tp_category: T, tp_name: T) -> Result<Link> {
-        let tp_category = util::str_to_cstring(tp_category.as_ref())?;
-        let tp_category_ptr = tp_category.as_ptr();
-        let tp_name = util::str_to_cstring(tp_name.as_ref())?;
-        let tp_name_ptr = tp_name.as_ptr();
+    /// Attach this program to uprobes on many functions of the ELF binary at `binary_path` at
+    /// once, given either by `opts.symbols` or by `opts.offsets`.
+    ///
+    /// This uses a single `BPF_TRACE_UPROBE_MULTI` link, which is a lot faster than attaching
+    /// uprobes one by one. Requires Linux 6.6. `pid` works as for [`Program::attach_uprobe()`].
+    pub fn attach_uprobe_multi<T: AsRef<Path>>(
+        &mut self,
+        pid: i32,
+        binary_path: T,
+        opts: &UprobeMultiOpts,
+    ) -> Result<Link> {
+        let cnt = match (opts.symbols.len(), opts.offsets.len()) {
+            (0, 0) => {
+                return Err(Error::InvalidInput(
+                    "no symbols or offsets to attach to".to_string(),
+                ))
+            }
+            (cnt, 0) | (0, cnt) => cnt,
+            _ => {
+                return Err(Error::InvalidInput(
+                    "only one of symbols and offsets may be set".to_string(),
+                ))
+            }
+        };
+        for (field, len) in [
+            ("ref_ctr_offsets", opts.ref_ctr_offsets.len()),
+            ("cookies", opts.cookies.len()),
+        ] {
+            if len != 0 && len != cnt {
+                return Err(Error::InvalidInput(format!(
+                    "number of {} {} != number of functions {}",
+                    field, len, cnt
+                )));
+            }
+        }
+
+        let syms = opts
+            .symbols
+            .iter()
+            .map(|sym| util::str_to_cstring(sym))
+            .collect::<Result<Vec<_>>>()?;
+        let mut sym_ptrs = syms.iter().map(|sym| sym.as_ptr()).collect::<Vec<_>>();
+        let offsets = opts
+            .offsets
+            .iter()
+            .map(|off| *off as c_ulong)
+            .collect::<Vec<_>>();
+        let ref_ctr_offsets = opts
+            .ref_ctr_offsets
+            .iter()
+            .map(|off| *off as c_ulong)
+            .collect::<Vec<_>>();
+        let libbpf_opts = libbpf_sys::bpf_uprobe_multi_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_uprobe_multi_opts>() as libbpf_sys::size_t,
+            syms: if sym_ptrs.is_empty() {
+                ptr::null_mut()
+            } else {
+                sym_ptrs.as_mut_ptr()
+            },
+            offsets: if offsets.is_empty() {
+                ptr::null()
+            } else {
+                offsets.as_ptr()
+            },
+            ref_ctr_offsets: if ref_ctr_offsets.is_empty() {
+                ptr::null()
+            } else {
+                ref_ctr_offsets.as_ptr()
+            },
+            cookies: if opts.cookies.is_empty() {
+                ptr::null()
+            } else {
+                opts.cookies.as_ptr()
+            },
+            cnt: cnt as libbpf_sys::size_t,
+            retprobe: opts.retprobe,
+            ..Default::default()
+        };
+
+        self.attach_uprobe_multi_impl(pid, binary_path, ptr::null(), &libbpf_opts)
+    }
+
+    /// Attach this program to uprobes on all functions of the ELF binary at `binary_path`
+    /// matching the glob `pattern` at once.
+    ///
+    /// See [`Program::attach_uprobe_multi()`].
+    pub fn attach_uprobe_multi_pattern<T: AsRef<Path>>(
+        &mut self,
+        pid: i32,
+        binary_path: T,
+        retprobe: bool,
+        pattern: &str,
+    ) -> Result<Link> {
+        let pattern = util::str_to_cstring(pattern)?;
+        let opts = libbpf_sys::bpf_uprobe_multi_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_uprobe_multi_opts>() as libbpf_sys::size_t,
+            retprobe,
+            ..Default::default()
+        };
+
+        self.attach_uprobe_multi_impl(pid, binary_path, pattern.as_ptr(), &opts)
+    }
+
+    fn attach_uprobe_multi_impl<T: AsRef<Path>>(
+        &mut self,
+        pid: i32,
+        binary_path: T,
+        pattern: *const c_char,
+        opts: &libbpf_sys::bpf_uprobe_multi_opts,
+    ) -> Result<Link> {
+        let path = util::path_to_cstring(binary_path.as_ref())?;
         let ptr = unsafe {
-            libbpf_sys::bpf_program__attach_tracepoint(self.ptr, tp_category_ptr, tp_name_ptr)
+            libbpf_sys::bpf_program__attach_uprobe_multi(
+                self.ptr,
+                pid,
+                path.as_ptr(),
+                pattern,
+                opts,
+            )
         };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(-err as i32))
         } else {
             Ok(Link::new(ptr))
         }
     }
 
-    /// Attach this program to a [raw kernel
-    /// tracepoint](https://lwn.net/Articles/748352/).
-    pub fn attach_raw_tracepoint<T: AsRef<str>>(&mut self, tp_name: T) -> Result<Link> {
-        let tp_name = util::str_to_cstring(tp_name.as_ref())?;
-        let tp_name_ptr = tp_name.as_ptr();
-        let ptr = unsafe { libbpf_sys::bpf_program__attach_raw_tracepoint(self.ptr, tp_name_ptr) };
+    /// Attach this program to a [USDT](https://lwn.net/Articles/753601/) probe, i.e. a static
+    /// tracepoint `provider:name` compiled into the binary at `binary_path`.
+    ///
+    /// The program has to be written against libbpf's `usdt.bpf.h` to access the probe's
+    /// arguments. `pid` works as for [`Program::attach_uprobe()`].
+    pub fn attach_usdt<T: AsRef<Path>>(
+        &mut self,
+        pid: i32,
+        binary_path: T,
+        provider: &str,
+        name: &str,
+    ) -> Result<Link> {
+        self.attach_usdt_with_opts(pid, binary_path, provider, name, &UsdtOpts::default())
+    }
+
+    /// Like [`Program::attach_usdt()`], with additional options.
+    pub fn attach_usdt_with_opts<T: AsRef<Path>>(
+        &mut self,
+        pid: i32,
+        binary_path: T,
+        provider: &str,
+        name: &str,
+        opts: &UsdtOpts,
+    ) -> Result<Link> {
+        let path = util::path_to_cstring(binary_path.as_ref())?;
+        let provider = util::str_to_cstring(provider)?;
+        let name = util::str_to_cstring(name)?;
+        let opts = opts.to_libbpf();
+
+        let ptr = unsafe {
+            libbpf_sys::bpf_program__attach_usdt(
+                self.ptr,
+                pid,
+                path.as_ptr(),
+                provider.as_ptr(),
+                name.as_ptr(),
+                &opts,
+            )
+        };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(-err as i32))
         } else {
             Ok(Link::new(ptr))
         }
     }
 
-    /// Attach to an [LSM](https://en.wikipedia.org/wiki/Linux_Security_Modules) hook
-    pub fn attach_lsm(&mut self) -> Result<Link> {
-        let ptr = unsafe { libbpf_sys::bpf_program__attach_lsm(self.ptr) };
+    /// Attach this program to a [kernel
+    /// probe](https://www.kernel.org/doc/html/latest/trace/kprobetrace.html).
+    pub fn attach_kprobe<T: AsRef<str>>(&mut self, retprobe: bool, func_name: T) -> Result<Link> {
+        let func_name = util::str_to_cstring(func_name.as_ref())?;
+        let func_name_ptr = func_name.as_ptr();
+        let ptr =
+            unsafe { libbpf_sys::bpf_program__attach_kprobe(self.ptr, retprobe, func_name_ptr) };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(-err as i32))
         } else {
             Ok(Link::new(ptr))
         }
     }
 
-    /// Attach to a [fentry/fexit kernel probe](https://lwn.net/Articles/801479/)
-    pub fn attach_trace(&mut self) -> Result<Link> {
-        let ptr = unsafe { libbpf_sys::bpf_program__attach_trace(self.ptr) };
+    /// Attach this program to a kernel return probe, i.e. run it whenever `func_name` returns.
+    ///
+    /// The kernel limits the number of concurrently probed invocations of `func_name` per
+    /// kretprobe ("maxactive"). Returns beyond that are missed rather than reported. Use
+    /// [`Program::attach_kprobe_with_opts()`] with [`KprobeOpts::maxactive`] to raise the limit
+    /// for functions that many tasks can be sleeping in at once.
+    pub fn attach_kretprobe<T: AsRef<str>>(&mut self, func_name: T) -> Result<Link> {
+        let opts = KprobeOpts {
+            retprobe: true,
+            ..Default::default()
+        };
+        self.attach_kprobe_with_opts(func_name, &opts)
+    }
+
+    /// Attach this program to a [kernel
+    /// probe](https://www.kernel.org/doc/html/latest/trace/kprobetrace.html) at `opts.offset`
+    /// bytes into `func_name`.
+    ///
+    /// Attaching the same program several times with different `opts.cookie` values lets it
+    /// tell the attachments apart at runtime with `bpf_get_attach_cookie()`.
+    pub fn attach_kprobe_with_opts<T: AsRef<str>>(
+        &mut self,
+        func_name: T,
+        opts: &KprobeOpts,
+    ) -> Result<Link> {
+        if opts.maxactive != 0 {
+            return self.attach_kretprobe_with_maxactive(func_name.as_ref(), opts);
+        }
+
+        let func_name = util::str_to_cstring(func_name.as_ref())?;
+        let func_name_ptr = func_name.as_ptr();
+        let opts = opts.to_libbpf();
+        let ptr =
+            unsafe { libbpf_sys::bpf_program__attach_kprobe_opts(self.ptr, func_name_ptr, &opts) };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(-err as i32))
         } else {
             Ok(Link::new(ptr))
         }
     }
 
-    /// Attach a verdict/parser to a [sockmap/sockhash](https://lwn.net/Articles/731133/)
-    pub fn attach_sockmap(&self, map_fd: i32) -> Result<()> {
-        let err =
-            unsafe { libbpf_sys::bpf_prog_attach(self.fd(), map_fd, self.attach_type() as u32, 0) };
-        if err != 0 {
-            Err(Error::System(errno::errno()))
-        } else {
-            Ok(())
+    /// Attach a kretprobe with `opts.maxactive` by creating it through `kprobe_events`, as
+    /// neither perf-based kprobes nor libbpf offer a way to set maxactive.
+    fn attach_kretprobe_with_maxactive(
+        &mut self,
+        func_name: &str,
+        opts: &KprobeOpts,
+    ) -> Result<Link> {
+        if !opts.retprobe {
+            return Err(Error::InvalidInput(
+                "maxactive only applies to kretprobes".to_string(),
+            ));
         }
-    }
+        // Reject names with NULs the same way as when attaching through libbpf
+        util::str_to_cstring(func_name)?;
 
-    /// Attach this program to [XDP](https://lwn.net/Articles/825998/)
-    pub fn attach_xdp(&mut self, ifindex: i32) -> Result<Link> {
-        let ptr = unsafe { libbpf_sys::bpf_program__attach_xdp(self.ptr, ifindex) };
-        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
-        if err != 0 {
-            Err(Error::System(err as i32))
-        } else {
-            Ok(Link::new(ptr))
+        let event = add_kretprobe_event(func_name, opts.offset, opts.maxactive)?;
+        let link = tracepoint_id("kretprobes", &event).and_then(|id| {
+            let attr = libbpf_sys::perf_event_attr {
+                type_: libbpf_sys::PERF_TYPE_TRACEPOINT,
+                size: mem::size_of::<libbpf_sys::perf_event_attr>() as u32,
+                config: id,
+                ..Default::default()
+            };
+
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_perf_event_open,
+                    &attr as *const libbpf_sys::perf_event_attr,
+                    -1,
+                    0,
+                    -1,
+                    libbpf_sys::PERF_FLAG_FD_CLOEXEC,
+                )
+            };
+            if fd < 0 {
+                return Err(Error::System(errno::errno()));
+            }
+
+            let pfd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+            let perf_opts = PerfEventOpts {
+                cookie: opts.cookie,
+                force_ioctl_attach: match opts.attach_mode {
+                    ProbeAttachMode::Legacy | ProbeAttachMode::Perf => true,
+                    ProbeAttachMode::Default | ProbeAttachMode::Link => false,
+                },
+            };
+            self.attach_perf_event_with_opts(pfd, &perf_opts)
+        });
+
+        match link {
+            Ok(link) => Ok(link.with_kretprobe_event(event)),
+            Err(e) => {
+                let _ = remove_kretprobe_event(&event);
+                Err(e)
+            }
         }
     }
 
-    /// Attach this program to [netns-based programs](https://lwn.net/Articles/819618/)
-    pub fn attach_netns(&mut self, netns_fd: i32) -> Result<Link> {
-        let ptr = unsafe { libbpf_sys::bpf_program__attach_netns(self.ptr, netns_fd) };
+    /// Attach this program to a kprobe on the kernel entry point of the system call
+    /// `syscall_name`, e.g. `openat`.
+    ///
+    /// libbpf picks the architecture specific name of the entry point, like
+    /// `__x64_sys_openat`, so the same code works across architectures. On return probes, the
+    /// program sees the return value of the system call.
+    pub fn attach_ksyscall<T: AsRef<str>>(
+        &mut self,
+        retprobe: bool,
+        syscall_name: T,
+    ) -> Result<Link> {
+        let syscall_name = util::str_to_cstring(syscall_name.as_ref())?;
+        let opts = libbpf_sys::bpf_ksyscall_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_ksyscall_opts>() as libbpf_sys::size_t,
+            retprobe,
+            ..Default::default()
+        };
+
+        let ptr = unsafe {
+            libbpf_sys::bpf_program__attach_ksyscall(self.ptr, syscall_name.as_ptr(), &opts)
+        };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(-err as i32))
         } else {
             Ok(Link::new(ptr))
         }
     }
+
+    /// Attach this program to kprobes on all of `opts.symbols` at once.
+    ///
+    /// This uses a single `BPF_TRACE_KPROBE_MULTI` link, which is a lot faster than attaching
+    /// kprobes one by one. Requires Linux 5.18.
+    pub fn attach_kprobe_multi(&mut self, opts: &KprobeMultiOpts) -> Result<Link> {
+        if opts.symbols.is_empty() {
+            return Err(Error::InvalidInput("no symbols to attach to".to_string()));
+        }
+        if !opts.cookies.is_empty() && opts.cookies.len() != opts.symbols.len() {
+            return Err(Error::InvalidInput(format!(
+                "number of cookies {} != number of symbols {}",
+                opts.cookies.len(),
+                opts.symbols.len()
+            )));
+        }
+
+        let syms = opts
+            .symbols
+            .iter()
+            .map(|sym| util::str_to_cstring(sym))
+            .collect::<Result<Vec<_>>>()?;
+        let mut sym_ptrs = syms.iter().map(|sym| sym.as_ptr()).collect::<Vec<_>>();
+        let libbpf_opts = libbpf_sys::bpf_kprobe_multi_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_kprobe_multi_opts>() as libbpf_sys::size_t,
+            syms: sym_ptrs.as_mut_ptr(),
+            cookies: if opts.cookies.is_empty() {
+                ptr::null()
+            } else {
+                opts.cookies.as_ptr()
+            },
+            cnt: sym_ptrs.len() as libbpf_sys::size_t,
+            retprobe: opts.retprobe,
+            ..Default::default()
+        };
+
+        self.attach_kprobe_multi_impl(ptr::null(), &libbpf_opts)
+    }
+
+    /// Attach this program to kprobes on all kernel functions matching the glob `pattern`, e.g.
+    /// `tcp_*`, at once.
+    ///
+    /// See [`Program::attach_kprobe_multi()`].
+    pub fn attach_kprobe_multi_pattern<T: AsRef<str>>(
+        &mut self,
+        retprobe: bool,
+        pattern: T,
+    ) -> Result<Link> {
+        let pattern = util::str_to_cstring(pattern.as_ref())?;
+        let opts = libbpf_sys::bpf_kprobe_multi_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_kprobe_multi_opts>() as libbpf_sys::size_t,
+            retprobe,
+            ..Default::default()
+        };
+
+        self.attach_kprobe_multi_impl(pattern.as_ptr(), &opts)
+    }
+
+    fn attach_kprobe_multi_impl(
+        &mut self,
+        pattern: *const c_char,
+        opts: &libbpf_sys::bpf_kprobe_multi_opts,
+    ) -> Result<Link> {
+        let ptr =
+            unsafe { libbpf_sys::bpf_program__attach_kprobe_multi_opts(self.ptr, pattern, opts) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach this program to a [kernel
+    /// tracepoint](https://www.kernel.org/doc/html/latest/trace/tracepoints.html).
+    pub fn attach_tracepoint<T: AsRef<str>>(&mut self, tp_category: T, tp_name: T) -> Result<Link> {
+        let tp_category = util::str_to_cstring(tp_category.as_ref())?;
+        let tp_category_ptr = tp_category.as_ptr();
+        let tp_name = util::str_to_cstring(tp_name.as_ref())?;
+        let tp_name_ptr = tp_name.as_ptr();
+        let ptr = unsafe {
+            libbpf_sys::bpf_program__attach_tracepoint(self.ptr, tp_category_ptr, tp_name_ptr)
+        };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Like [`Program::attach_tracepoint()`], with additional options.
+    pub fn attach_tracepoint_with_opts<T: AsRef<str>>(
+        &mut self,
+        tp_category: T,
+        tp_name: T,
+        opts: &TracepointOpts,
+    ) -> Result<Link> {
+        if opts.attach_mode != ProbeAttachMode::Default {
+            return self.attach_tracepoint_with_mode(tp_category.as_ref(), tp_name.as_ref(), opts);
+        }
+
+        let tp_category = util::str_to_cstring(tp_category.as_ref())?;
+        let tp_name = util::str_to_cstring(tp_name.as_ref())?;
+        let opts = opts.to_libbpf();
+        let ptr = unsafe {
+            libbpf_sys::bpf_program__attach_tracepoint_opts(
+                self.ptr,
+                tp_category.as_ptr(),
+                tp_name.as_ptr(),
+                &opts,
+            )
+        };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach to a tracepoint by opening its perf event here, as libbpf offers no choice of
+    /// attach mode for tracepoints.
+    fn attach_tracepoint_with_mode(
+        &mut self,
+        tp_category: &str,
+        tp_name: &str,
+        opts: &TracepointOpts,
+    ) -> Result<Link> {
+        // Reject names with NULs the same way as when attaching through libbpf
+        util::str_to_cstring(tp_category)?;
+        util::str_to_cstring(tp_name)?;
+
+        let mut attr = libbpf_sys::perf_event_attr {
+            type_: libbpf_sys::PERF_TYPE_TRACEPOINT,
+            size: mem::size_of::<libbpf_sys::perf_event_attr>() as u32,
+            config: tracepoint_id(tp_category, tp_name)?,
+            ..Default::default()
+        };
+        attr.__bindgen_anon_1.sample_period = 1;
+        attr.__bindgen_anon_2.wakeup_events = 1;
+
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                &attr as *const libbpf_sys::perf_event_attr,
+                -1,
+                0,
+                -1,
+                libbpf_sys::PERF_FLAG_FD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::System(errno::errno()));
+        }
+
+        let pfd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+        let perf_opts = PerfEventOpts {
+            cookie: opts.cookie,
+            force_ioctl_attach: opts.attach_mode != ProbeAttachMode::Link,
+        };
+        self.attach_perf_event_with_opts(pfd, &perf_opts)
+    }
+
+    /// Attach this program to a [raw kernel
+    /// tracepoint](https://lwn.net/Articles/748352/).
+    ///
+    /// Fails with [`Error::InvalidInput`] if the running kernel has no tracepoint `tp_name`.
+    pub fn attach_raw_tracepoint<T: AsRef<str>>(&mut self, tp_name: T) -> Result<Link> {
+        self.attach_raw_tracepoint_with_opts(tp_name, &RawTracepointOpts::default())
+    }
+
+    /// Like [`Program::attach_raw_tracepoint()`], with additional options.
+    pub fn attach_raw_tracepoint_with_opts<T: AsRef<str>>(
+        &mut self,
+        tp_name: T,
+        opts: &RawTracepointOpts,
+    ) -> Result<Link> {
+        let tp_name = tp_name.as_ref();
+        let tp_name_c = util::str_to_cstring(tp_name)?;
+        let mut opts = opts.to_libbpf();
+        let ptr = unsafe {
+            libbpf_sys::bpf_program__attach_raw_tracepoint_opts(
+                self.ptr,
+                tp_name_c.as_ptr(),
+                &mut opts,
+            )
+        };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            // The kernel only reports ENOENT, so point out the likely typo
+            if raw_tracepoint_exists(tp_name) == Some(false) {
+                return Err(Error::InvalidInput(format!(
+                    "raw tracepoint '{}' does not exist in the running kernel",
+                    tp_name
+                )));
+            }
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach to an [LSM](https://en.wikipedia.org/wiki/Linux_Security_Modules) hook
+    ///
+    /// The hook is taken from the `SEC("lsm/<hook>")` of the program.
+    pub fn attach_lsm(&mut self) -> Result<Link> {
+        let ptr = unsafe { libbpf_sys::bpf_program__attach_lsm(self.ptr) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach a `SEC("lsm_cgroup/<hook>")` program to the cgroup `cgroup_fd`, so that it only
+    /// runs for tasks in that cgroup and its descendants. Requires Linux 6.0.
+    pub fn attach_lsm_cgroup(&mut self, cgroup_fd: BorrowedFd<'_>) -> Result<Link> {
+        if !matches!(self.attach_type(), ProgramAttachType::LsmCgroup) {
+            return Err(Error::InvalidInput(format!(
+                "attach_lsm_cgroup() requires attach type LsmCgroup (attach type of the program is {})",
+                self.attach_type(),
+            )));
+        }
+
+        let ptr =
+            unsafe { libbpf_sys::bpf_program__attach_cgroup(self.ptr, cgroup_fd.as_raw_fd()) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach a `SEC("cgroup/sysctl")` program to the cgroup `cgroup_fd`, to filter access to
+    /// `/proc/sys` by tasks in that cgroup and its descendants.
+    pub fn attach_cgroup_sysctl(&mut self, cgroup_fd: BorrowedFd<'_>) -> Result<Link> {
+        self.attach_cgroup_typed(cgroup_fd, ProgramAttachType::CgroupSysctl)
+    }
+
+    /// Attach a `SEC("cgroup/getsockopt")` program to the cgroup `cgroup_fd`, to intercept
+    /// `getsockopt(2)` calls by tasks in that cgroup and its descendants.
+    pub fn attach_cgroup_getsockopt(&mut self, cgroup_fd: BorrowedFd<'_>) -> Result<Link> {
+        self.attach_cgroup_typed(cgroup_fd, ProgramAttachType::CgroupGetsockopt)
+    }
+
+    /// Attach a `SEC("cgroup/setsockopt")` program to the cgroup `cgroup_fd`, to intercept
+    /// `setsockopt(2)` calls by tasks in that cgroup and its descendants.
+    pub fn attach_cgroup_setsockopt(&mut self, cgroup_fd: BorrowedFd<'_>) -> Result<Link> {
+        self.attach_cgroup_typed(cgroup_fd, ProgramAttachType::CgroupSetsockopt)
+    }
+
+    /// Attach a `SEC("sockops")` program ([`ProgramType::SockOps`]) to the cgroup `cgroup_fd`,
+    /// to be called on TCP events of sockets created by tasks in that cgroup and its
+    /// descendants, e.g. to set TCP options or add the sockets to a sockmap.
+    pub fn attach_sockops(&mut self, cgroup_fd: BorrowedFd<'_>) -> Result<Link> {
+        self.attach_cgroup_typed(cgroup_fd, ProgramAttachType::CgroupSockOps)
+    }
+
+    /// Internal function to attach to a cgroup after checking that the program has attach type
+    /// `expected`.
+    fn attach_cgroup_typed(
+        &mut self,
+        cgroup_fd: BorrowedFd<'_>,
+        expected: ProgramAttachType,
+    ) -> Result<Link> {
+        if self.attach_type() as u32 != expected.clone() as u32 {
+            return Err(Error::InvalidInput(format!(
+                "program must have attach type {} (attach type of the program is {})",
+                expected,
+                self.attach_type(),
+            )));
+        }
+
+        self.attach_cgroup(cgroup_fd)
+    }
+
+    /// Attach to a [fentry/fexit kernel probe](https://lwn.net/Articles/801479/)
+    pub fn attach_trace(&mut self) -> Result<Link> {
+        let ptr = unsafe { libbpf_sys::bpf_program__attach_trace(self.ptr) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach an extension program, i.e. one in a `SEC("freplace/<func>")`, to replace the
+    /// global function `func_name` of the BPF program `target_prog_fd`.
+    ///
+    /// Extension programs are verified against their target when loaded, so the target has to
+    /// be set with [`OpenProgram::set_attach_target()`] before loading the object. The target
+    /// given here may differ from that one, as long as its function has the same signature.
+    pub fn attach_freplace(
+        &mut self,
+        target_prog_fd: BorrowedFd<'_>,
+        func_name: &str,
+    ) -> Result<Link> {
+        if !matches!(self.prog_type(), ProgramType::Ext) {
+            return Err(Error::InvalidInput(format!(
+                "attach_freplace() requires an extension program (type of the program is {})",
+                self.prog_type(),
+            )));
+        }
+
+        let func_name = util::str_to_cstring(func_name)?;
+        let ptr = unsafe {
+            libbpf_sys::bpf_program__attach_freplace(
+                self.ptr,
+                target_prog_fd.as_raw_fd(),
+                func_name.as_ptr(),
+            )
+        };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach this program to the cgroup `cgroup_fd` the legacy way, without a [`Link`], for
+    /// the attach type of the program.
+    ///
+    /// The program stays attached until detached with [`Program::detach_cgroup()`], replaced
+    /// through `opts`, or the cgroup goes away.
+    pub fn attach_cgroup_with_opts(
+        &self,
+        cgroup_fd: BorrowedFd<'_>,
+        opts: &CgroupAttachOpts<'_>,
+    ) -> Result<()> {
+        if opts.flags.contains(CgroupAttachFlags::REPLACE) != opts.replace_prog_fd.is_some() {
+            return Err(Error::InvalidInput(
+                "CgroupAttachFlags::REPLACE requires replace_prog_fd and vice versa".to_string(),
+            ));
+        }
+
+        let mut attach_opts = libbpf_sys::bpf_prog_attach_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_prog_attach_opts>() as libbpf_sys::size_t,
+            flags: opts.flags.bits,
+            ..Default::default()
+        };
+        attach_opts.__bindgen_anon_1.replace_prog_fd =
+            opts.replace_prog_fd.map_or(0, |fd| fd.as_raw_fd());
+
+        let ret = unsafe {
+            libbpf_sys::bpf_prog_attach_opts(
+                self.fd().as_raw_fd(),
+                cgroup_fd.as_raw_fd(),
+                self.attach_type() as u32,
+                &attach_opts,
+            )
+        };
+        if ret != 0 {
+            Err(Error::System(errno::errno()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Detach this program from the cgroup `cgroup_fd` after attaching it with
+    /// [`Program::attach_cgroup_with_opts()`].
+    pub fn detach_cgroup(&self, cgroup_fd: BorrowedFd<'_>) -> Result<()> {
+        let ret = unsafe {
+            libbpf_sys::bpf_prog_detach2(
+                self.fd().as_raw_fd(),
+                cgroup_fd.as_raw_fd(),
+                self.attach_type() as u32,
+            )
+        };
+        if ret != 0 {
+            Err(Error::System(errno::errno()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Attach a verdict/parser to a [sockmap/sockhash](https://lwn.net/Articles/731133/)
+    pub fn attach_sockmap(&self, map_fd: BorrowedFd<'_>) -> Result<()> {
+        let err = unsafe {
+            libbpf_sys::bpf_prog_attach(
+                self.fd().as_raw_fd(),
+                map_fd.as_raw_fd(),
+                self.attach_type() as u32,
+                0,
+            )
+        };
+        if err != 0 {
+            Err(Error::System(errno::errno()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Attach a `SEC("sk_skb/...")` or `SEC("sk_msg")` program to the [`MapType::Sockmap`] or
+    /// [`MapType::Sockhash`] `map` as `attach_type`, which can differ from the attach type of
+    /// the program, e.g. to use one program as both stream parser and verdict.
+    ///
+    /// `attach_type` must be [`ProgramAttachType::SkSkbStreamParser`],
+    /// [`ProgramAttachType::SkSkbStreamVerdict`] or [`ProgramAttachType::SkSkbVerdict`] for
+    /// [`ProgramType::SkSkb`] programs, and [`ProgramAttachType::SkMsgVerdict`] for
+    /// [`ProgramType::SkMsg`] programs.
+    pub fn attach_sockmap_with_type(
+        &self,
+        map: &Map,
+        attach_type: ProgramAttachType,
+    ) -> Result<()> {
+        self.check_sockmap_attach(map, &attach_type)?;
+
+        let ret = unsafe {
+            libbpf_sys::bpf_prog_attach(
+                self.fd().as_raw_fd(),
+                map.fd().as_raw_fd(),
+                attach_type as u32,
+                0,
+            )
+        };
+        if ret != 0 {
+            Err(Error::System(errno::errno()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Detach this program from `map` after attaching it as `attach_type` with
+    /// [`Program::attach_sockmap_with_type()`] or [`Program::attach_sockmap()`].
+    pub fn detach_sockmap(&self, map: &Map, attach_type: ProgramAttachType) -> Result<()> {
+        self.check_sockmap_attach(map, &attach_type)?;
+
+        let ret = unsafe {
+            libbpf_sys::bpf_prog_detach2(
+                self.fd().as_raw_fd(),
+                map.fd().as_raw_fd(),
+                attach_type as u32,
+            )
+        };
+        if ret != 0 {
+            Err(Error::System(errno::errno()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Internal function to check that this program can be attached to `map` as `attach_type`.
+    fn check_sockmap_attach(&self, map: &Map, attach_type: &ProgramAttachType) -> Result<()> {
+        if !matches!(map.map_type(), MapType::Sockmap | MapType::Sockhash) {
+            return Err(Error::InvalidInput(format!(
+                "programs can only be attached to sockmap and sockhash maps (type of the map is {})",
+                map.map_type(),
+            )));
+        }
+
+        let valid = match attach_type {
+            ProgramAttachType::SkSkbStreamParser
+            | ProgramAttachType::SkSkbStreamVerdict
+            | ProgramAttachType::SkSkbVerdict => matches!(self.prog_type(), ProgramType::SkSkb),
+            ProgramAttachType::SkMsgVerdict => matches!(self.prog_type(), ProgramType::SkMsg),
+            _ => false,
+        };
+        if !valid {
+            return Err(Error::InvalidInput(format!(
+                "program type {} cannot be attached to a sockmap as {}",
+                self.prog_type(),
+                attach_type,
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Attach this program to [XDP](https://lwn.net/Articles/825998/)
+    ///
+    /// The kernel runs the program in the driver if it supports XDP, and in generic mode
+    /// otherwise. Use [`Program::attach_xdp_with_flags()`] to choose the mode.
+    pub fn attach_xdp(&mut self, ifindex: i32) -> Result<Link> {
+        let ptr = unsafe { libbpf_sys::bpf_program__attach_xdp(self.ptr, ifindex) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach this program to [XDP](https://lwn.net/Articles/825998/) in the mode selected by
+    /// `flags`.
+    ///
+    /// Like every XDP link, the attachment fails if the interface already has an XDP program in
+    /// that mode, so [`XdpFlags::UPDATE_IF_NOEXIST`] is implied. [`XdpFlags::REPLACE`] is
+    /// rejected: swap the program of an existing link with [`Link::update_prog()`] instead.
+    pub fn attach_xdp_with_flags(&mut self, ifindex: i32, flags: XdpFlags) -> Result<Link> {
+        if flags.contains(XdpFlags::REPLACE) {
+            return Err(Error::InvalidInput(
+                "XDP links cannot replace programs, use Link::update_prog() instead".to_string(),
+            ));
+        }
+
+        let opts = libbpf_sys::bpf_link_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_link_create_opts>() as libbpf_sys::size_t,
+            flags: (flags - XdpFlags::UPDATE_IF_NOEXIST).bits,
+            ..Default::default()
+        };
+        let fd = unsafe {
+            libbpf_sys::bpf_link_create(self.fd().as_raw_fd(), ifindex, libbpf_sys::BPF_XDP, &opts)
+        };
+        if fd < 0 {
+            return Err(Error::System(errno::errno()));
+        }
+
+        Ok(Link::from_fd(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Attach a `SEC("tcx/ingress")` or `SEC("tcx/egress")` program to the network interface
+    /// `ifindex`, ordered relative to the other tcx programs of the interface by
+    /// `opts.order`. Requires Linux 6.6.
+    ///
+    /// Unlike with [`TcHook`], the program is detached when the [`Link`] is dropped, and
+    /// programs of different applications can coexist without agreeing on handles and
+    /// priorities.
+    pub fn attach_tcx(&mut self, ifindex: i32, opts: &TcxOpts<'_>) -> Result<Link> {
+        let opts = opts.to_libbpf();
+        let ptr = unsafe { libbpf_sys::bpf_program__attach_tcx(self.ptr, ifindex, &opts) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach a `SEC("netkit/primary")` or `SEC("netkit/peer")` program to the netkit device
+    /// `ifindex`, ordered relative to its other programs by `opts.order`. Requires Linux 6.7.
+    ///
+    /// Whether the program handles traffic of the primary device or of its peer, e.g. inside a
+    /// container, comes from the attach type of the program.
+    pub fn attach_netkit(&mut self, ifindex: i32, opts: &NetkitOpts<'_>) -> Result<Link> {
+        match self.attach_type() {
+            ProgramAttachType::NetkitPrimary | ProgramAttachType::NetkitPeer => (),
+            ty => {
+                return Err(Error::InvalidInput(format!(
+                    "attach_netkit() requires attach type NetkitPrimary or NetkitPeer (attach type of the program is {})",
+                    ty,
+                )))
+            }
+        }
+
+        let opts = opts.to_libbpf();
+        let ptr = unsafe { libbpf_sys::bpf_program__attach_netkit(self.ptr, ifindex, &opts) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach a `SEC("netfilter")` program ([`ProgramType::Netfilter`]) to the netfilter hook
+    /// `hooknum` (an `NF_INET_*` value like `NF_INET_LOCAL_IN`) of the protocol family `pf`
+    /// (`NFPROTO_IPV4` or `NFPROTO_IPV6`). Requires Linux 6.4.
+    ///
+    /// Programs on the same hook run in ascending order of `priority`, interleaved with the
+    /// hooks of the kernel, e.g. of conntrack.
+    pub fn attach_netfilter(
+        &mut self,
+        pf: u32,
+        hooknum: u32,
+        priority: i32,
+        flags: NetfilterFlags,
+    ) -> Result<Link> {
+        let opts = libbpf_sys::bpf_netfilter_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_netfilter_opts>() as libbpf_sys::size_t,
+            pf,
+            hooknum,
+            priority,
+            flags: flags.bits,
+        };
+        let ptr = unsafe { libbpf_sys::bpf_program__attach_netfilter(self.ptr, &opts) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach this program to [netns-based programs](https://lwn.net/Articles/819618/)
+    ///
+    /// This is how `SEC("sk_lookup")` programs ([`ProgramType::SkLookup`]) are attached to
+    /// steer incoming connections to sockets in the network namespace `netns_fd`, which can be
+    /// obtained by opening `/proc/<pid>/ns/net` of any process in the namespace, e.g. of a
+    /// container.
+    pub fn attach_netns(&mut self, netns_fd: BorrowedFd<'_>) -> Result<Link> {
+        if !matches!(
+            self.prog_type(),
+            ProgramType::SkLookup | ProgramType::FlowDissector
+        ) {
+            return Err(Error::InvalidInput(format!(
+                "attach_netns() requires program type SkLookup or FlowDissector (program type is {})",
+                self.prog_type(),
+            )));
+        }
+
+        let ptr = unsafe { libbpf_sys::bpf_program__attach_netns(self.ptr, netns_fd.as_raw_fd()) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(-err as i32))
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach a `SEC("flow_dissector")` program ([`ProgramType::FlowDissector`]) to the network
+    /// namespace `netns_fd`, replacing the kernel's flow dissector for packets in it.
+    ///
+    /// Only one flow dissector can be attached to a network namespace at a time.
+    pub fn attach_flow_dissector(&mut self, netns_fd: BorrowedFd<'_>) -> Result<Link> {
+        if !matches!(self.prog_type(), ProgramType::FlowDissector) {
+            return Err(Error::InvalidInput(format!(
+                "attach_flow_dissector() requires program type FlowDissector (program type is {})",
+                self.prog_type(),
+            )));
+        }
+
+        self.attach_netns(netns_fd)
+    }
+
+    /// Detach this program from the network namespace `netns_fd` if it was attached as flow
+    /// dissector without a [`Link`], e.g. by `bpftool`.
+    ///
+    /// Links returned by [`Program::attach_flow_dissector()`] detach the program when dropped
+    /// instead.
+    pub fn detach_flow_dissector(&self, netns_fd: BorrowedFd<'_>) -> Result<()> {
+        let ret = unsafe {
+            libbpf_sys::bpf_prog_detach2(
+                self.fd().as_raw_fd(),
+                netns_fd.as_raw_fd(),
+                libbpf_sys::BPF_FLOW_DISSECTOR,
+            )
+        };
+        if ret != 0 {
+            Err(Error::System(errno::errno()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the id of the flow dissector attached to the network namespace `netns_fd`, if
+    /// any.
+    pub fn query_flow_dissector(netns_fd: BorrowedFd<'_>) -> Result<Option<u32>> {
+        let mut attach_flags = 0;
+        let mut prog_id = 0;
+        let mut prog_cnt = 1;
+        let ret = unsafe {
+            libbpf_sys::bpf_prog_query(
+                netns_fd.as_raw_fd(),
+                libbpf_sys::BPF_FLOW_DISSECTOR,
+                0,
+                &mut attach_flags,
+                &mut prog_id,
+                &mut prog_cnt,
+            )
+        };
+        if ret != 0 {
+            return Err(Error::System(errno::errno()));
+        }
+
+        Ok(if prog_cnt > 0 { Some(prog_id) } else { None })
+    }
+
+    /// Run this program once or [`TestRunOpts::repeat`] times on `opts.data_in` in the kernel,
+    /// without attaching it, e.g. to unit test XDP, tc or `SEC("syscall")` programs.
+    ///
+    /// Not all program types support being run like this. Fails with `ENOSPC` if the output
+    /// data or context does not fit into [`TestRunOpts::data_out_size`] or
+    /// [`TestRunOpts::ctx_out_size`] bytes.
+    pub fn test_run(&self, opts: &TestRunOpts<'_>) -> Result<TestRunOutput> {
+        let live_frames = opts.flags.contains(TestRunFlags::XDP_LIVE_FRAMES);
+        let out_size = |size: Option<usize>, in_size: usize| match size {
+            // The kernel does not return output for live frames
+            None if live_frames => Ok(0),
+            None => Ok(in_size),
+            Some(size) if live_frames && size != 0 => Err(Error::InvalidInput(
+                "TestRunFlags::XDP_LIVE_FRAMES does not support output data or context".to_string(),
+            )),
+            Some(size) => Ok(size),
+        };
+        let mut data_out = vec![0; out_size(opts.data_out_size, opts.data_in.len())?];
+        let mut ctx_out = vec![0; out_size(opts.ctx_out_size, opts.ctx_in.len())?];
+        let mut flags = opts.flags.bits;
+        if opts.cpu.is_some() {
+            flags |= libbpf_sys::BPF_F_TEST_RUN_ON_CPU;
+        }
+
+        let mut run_opts = libbpf_sys::bpf_test_run_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_test_run_opts>() as libbpf_sys::size_t,
+            data_in: opts.data_in.as_ptr() as *const _,
+            data_size_in: opts.data_in.len() as u32,
+            data_out: slice_ptr_or_null(&mut data_out),
+            data_size_out: data_out.len() as u32,
+            ctx_in: if opts.ctx_in.is_empty() {
+                ptr::null()
+            } else {
+                opts.ctx_in.as_ptr() as *const _
+            },
+            ctx_size_in: opts.ctx_in.len() as u32,
+            ctx_out: slice_ptr_or_null(&mut ctx_out),
+            ctx_size_out: ctx_out.len() as u32,
+            repeat: opts.repeat as i32,
+            flags,
+            cpu: opts.cpu.unwrap_or(0),
+            batch_size: opts.batch_size,
+            ..Default::default()
+        };
+
+        let ret =
+            unsafe { libbpf_sys::bpf_prog_test_run_opts(self.fd().as_raw_fd(), &mut run_opts) };
+        if ret != 0 {
+            return Err(Error::System(errno::errno()));
+        }
+
+        data_out.truncate(run_opts.data_size_out as usize);
+        ctx_out.truncate(run_opts.ctx_size_out as usize);
+        Ok(TestRunOutput {
+            return_value: run_opts.retval,
+            data_out,
+            ctx_out,
+            duration: Duration::from_nanos(run_opts.duration.into()),
+        })
+    }
+}
+
+impl AsFd for Program {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd()
+    }
+}
+
+/// A loaded BPF program that is not part of an [`Object`].
+///
+/// Such programs are referenced only by a file descriptor, for example programs pinned to bpffs
+/// by a daemon. The handle owns the file descriptor and closes it when dropped, which unloads
+/// the program unless something else, like a pin or a link, still refers to it.
+pub struct ProgramHandle {
+    fd: OwnedFd,
+    name: String,
+    ty: ProgramType,
+}
+
+impl ProgramHandle {
+    /// Open a program pinned to bpffs at `path`, e.g. by another process.
+    ///
+    /// The name and type of the handle are populated from the kernel.
+    pub fn from_pinned_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path_c = util::path_to_cstring(path)?;
+
+        let fd = unsafe { libbpf_sys::bpf_obj_get(path_c.as_ptr()) };
+        if fd < 0 {
+            return Err(Error::System(errno::errno()));
+        }
+
+        ProgramHandle::from_fd(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Open the program with the given `id`, as listed by e.g. [`query::ProgInfoIter`].
+    pub fn from_prog_id(id: u32) -> Result<Self> {
+        let fd = unsafe { libbpf_sys::bpf_prog_get_fd_by_id(id) };
+        if fd < 0 {
+            return Err(Error::System(errno::errno()));
+        }
+
+        ProgramHandle::from_fd(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Takes ownership of `fd`, which must refer to a BPF program, and queries the kernel for
+    /// the properties of the program.
+    pub fn from_fd(fd: OwnedFd) -> Result<Self> {
+        let info = prog_info(fd.as_fd())?;
+        let ty = match ProgramType::try_from(info.type_) {
+            Ok(ty) => ty,
+            Err(_) => ProgramType::Unknown,
+        };
+
+        Ok(ProgramHandle {
+            fd,
+            name: query::name_arr_to_string(&info.name, ""),
+            ty,
+        })
+    }
+
+    /// Create a new handle to the same program with its own file descriptor.
+    pub fn try_clone(&self) -> Result<Self> {
+        let fd = self
+            .fd
+            .try_clone()
+            .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)))?;
+
+        Ok(ProgramHandle {
+            fd,
+            name: self.name.clone(),
+            ty: self.ty.clone(),
+        })
+    }
+
+    /// Name of the program, as truncated to 15 bytes by the kernel.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn prog_type(&self) -> ProgramType {
+        self.ty.clone()
+    }
+
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+
+    /// Instructions of the program as translated by the verifier. See
+    /// [`Program::xlated_insns()`].
+    pub fn xlated_insns(&self) -> Result<Vec<libbpf_sys::bpf_insn>> {
+        xlated_insns(self.fd())
+    }
+
+    /// Query the kernel for information about the program, e.g. its id and run statistics.
+    pub fn info(&self) -> Result<query::ProgramInfo> {
+        let info = prog_info(self.fd())?;
+        query::ProgramInfo::from_uapi(self.fd.as_raw_fd(), info)
+            .ok_or_else(|| Error::Internal("failed to parse program info".to_string()))
+    }
+
+    /// [Pin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
+    /// this program to bpffs.
+    pub fn pin<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path_c = util::path_to_cstring(path)?;
+
+        let ret = unsafe { libbpf_sys::bpf_obj_pin(self.fd.as_raw_fd(), path_c.as_ptr()) };
+        if ret != 0 {
+            Err(Error::System(errno::errno()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// [Unpin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
+    /// this program from bpffs
+    pub fn unpin<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::remove_file(path.as_ref())
+            .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)))
+    }
+}
+
+/// Create a [`ProgramHandle`] with its own file descriptor referring to the same program as a
+/// [`Program`], e.g. one of an [`Object`].
+///
+/// Unlike the [`Program`], the handle does not borrow the [`Object`] and keeps the program
+/// loaded even after the [`Object`] is dropped.
+impl TryFrom<&Program> for ProgramHandle {
+    type Error = Error;
+
+    fn try_from(prog: &Program) -> Result<Self> {
+        let fd = prog
+            .fd()
+            .try_clone_to_owned()
+            .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)))?;
+
+        Ok(ProgramHandle {
+            fd,
+            name: prog.name.clone(),
+            ty: prog.prog_type(),
+        })
+    }
+}
+
+impl AsFd for ProgramHandle {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd()
+    }
+}
+
+/// Internal function to fetch the instructions of the program `fd` after verification.
+fn xlated_insns(fd: BorrowedFd<'_>) -> Result<Vec<libbpf_sys::bpf_insn>> {
+    let len = prog_info(fd)?.xlated_prog_len as usize;
+    let mut insns =
+        vec![libbpf_sys::bpf_insn::default(); len / mem::size_of::<libbpf_sys::bpf_insn>()];
+
+    let mut info: libbpf_sys::bpf_prog_info = unsafe { mem::zeroed() };
+    info.xlated_prog_len = len as u32;
+    info.xlated_prog_insns = insns.as_mut_ptr() as u64;
+    let mut info_len = mem::size_of::<libbpf_sys::bpf_prog_info>() as u32;
+    let ret = unsafe {
+        libbpf_sys::bpf_obj_get_info_by_fd(
+            fd.as_raw_fd(),
+            &mut info as *mut libbpf_sys::bpf_prog_info as *mut c_void,
+            &mut info_len,
+        )
+    };
+    if ret != 0 {
+        return Err(Error::System(errno::errno()));
+    }
+
+    // The kernel reports a length of zero if the caller may not see the instructions
+    if info.xlated_prog_len == 0 && len != 0 {
+        return Err(Error::System(libc::EPERM));
+    }
+
+    Ok(insns)
+}
+
+/// Internal function to query the kernel for information about the program `fd`.
+fn prog_info(fd: BorrowedFd<'_>) -> Result<libbpf_sys::bpf_prog_info> {
+    // Padding bytes need to be zero as well, see `query::gen_info_impl`
+    let mut info: libbpf_sys::bpf_prog_info = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libbpf_sys::bpf_prog_info>() as u32;
+
+    let ret = unsafe {
+        libbpf_sys::bpf_obj_get_info_by_fd(
+            fd.as_raw_fd(),
+            &mut info as *mut libbpf_sys::bpf_prog_info as *mut c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(Error::System(errno::errno()));
+    }
+
+    Ok(info)
+}
+
+/// How often the perf events opened by [`Program::attach_perf_event_per_cpu()`] fire.
+#[derive(Clone, Copy, Debug)]
+pub enum PerfSample {
+    /// Fire this many times per second on average, with the kernel adjusting the period.
+    Frequency(u64),
+    /// Fire every time the event counted this many occurrences, e.g. nanoseconds for
+    /// `PERF_COUNT_SW_CPU_CLOCK`.
+    Period(u64),
+}
+
+/// Options for [`Program::attach_perf_event_with_opts()`]. Maps to `struct bpf_perf_event_opts`
+/// in libbpf.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PerfEventOpts {
+    /// Value returned by `bpf_get_attach_cookie()` to the program when the event fires.
+    pub cookie: u64,
+    /// Attach with the `PERF_EVENT_IOC_SET_BPF` ioctl even if the kernel supports perf event
+    /// links. The program then stays attached until the perf event is closed.
+    pub force_ioctl_attach: bool,
+}
+
+impl PerfEventOpts {
+    fn to_libbpf(self) -> libbpf_sys::bpf_perf_event_opts {
+        libbpf_sys::bpf_perf_event_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_perf_event_opts>() as libbpf_sys::size_t,
+            bpf_cookie: self.cookie,
+            force_ioctl_attach: self.force_ioctl_attach,
+            ..Default::default()
+        }
+    }
+}
+
+/// How kprobes, uprobes and tracepoints are attached. Maps to `enum probe_attach_mode` in
+/// libbpf.
+///
+/// Older kernels and some container runtimes only support some of the modes, or behave
+/// differently depending on the mode.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProbeAttachMode {
+    /// Use the most modern mode the kernel supports.
+    #[default]
+    Default = libbpf_sys::PROBE_ATTACH_MODE_DEFAULT,
+    /// Create kprobes and uprobes through tracefs and attach with the `PERF_EVENT_IOC_SET_BPF`
+    /// ioctl, as on kernels before 4.17.
+    Legacy = libbpf_sys::PROBE_ATTACH_MODE_LEGACY,
+    /// Open a perf event and attach with the `PERF_EVENT_IOC_SET_BPF` ioctl.
+    Perf = libbpf_sys::PROBE_ATTACH_MODE_PERF,
+    /// Open a perf event and attach with a BPF perf link. Requires Linux 5.15.
+    Link = libbpf_sys::PROBE_ATTACH_MODE_LINK,
+}
+
+/// Options for [`Program::attach_kprobe_with_opts()`]. Maps to `struct bpf_kprobe_opts` in
+/// libbpf.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KprobeOpts {
+    /// Offset of the probe from the start of the function.
+    pub offset: usize,
+    /// Attach a kretprobe, i.e. fire on return from the function instead of on entry.
+    pub retprobe: bool,
+    /// Value returned by `bpf_get_attach_cookie()` to the program when the probe fires.
+    pub cookie: u64,
+    pub attach_mode: ProbeAttachMode,
+    /// Number of invocations of the function a kretprobe can track at once, or 0 for the
+    /// kernel's default. Setting this creates the kretprobe through `kprobe_events` in tracefs,
+    /// which is the only interface accepting it.
+    pub maxactive: u32,
+}
+
+impl KprobeOpts {
+    fn to_libbpf(self) -> libbpf_sys::bpf_kprobe_opts {
+        libbpf_sys::bpf_kprobe_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_kprobe_opts>() as libbpf_sys::size_t,
+            bpf_cookie: self.cookie,
+            offset: self.offset as libbpf_sys::size_t,
+            retprobe: self.retprobe,
+            attach_mode: self.attach_mode as libbpf_sys::probe_attach_mode,
+            ..Default::default()
+        }
+    }
+}
+
+/// Options for [`Program::attach_kprobe_multi()`]. Maps to `struct bpf_kprobe_multi_opts` in
+/// libbpf.
+#[derive(Clone, Debug, Default)]
+pub struct KprobeMultiOpts {
+    /// Names of the kernel functions to attach to.
+    pub symbols: Vec<String>,
+    /// Values returned by `bpf_get_attach_cookie()` to the program when the probe on the
+    /// function at the same index of `symbols` fires. Either empty or as long as `symbols`.
+    pub cookies: Vec<u64>,
+    /// Attach kretprobes, i.e. fire on return from the functions instead of on entry.
+    pub retprobe: bool,
+}
+
+/// Options for [`Program::attach_uprobe_with_opts()`]. Maps to `struct bpf_uprobe_opts` in
+/// libbpf.
+#[derive(Clone, Debug, Default)]
+pub struct UprobeOpts {
+    /// Offset of the reference counter of a USDT semaphore guarding the probe, if any. The
+    /// kernel increments it while the probe is attached.
+    pub ref_ctr_offset: usize,
+    /// Value returned by `bpf_get_attach_cookie()` to the program when the probe fires.
+    pub cookie: u64,
+    /// Attach a uretprobe, i.e. fire on return from the function instead of on entry.
+    pub retprobe: bool,
+    /// Name of the function to probe, optionally qualified with a symbol version as in
+    /// `malloc@GLIBC_2.2.5`. libbpf resolves its offset in the binary.
+    pub func_name: Option<String>,
+    pub attach_mode: ProbeAttachMode,
+}
+
+/// Options for [`Program::attach_uprobe_multi()`]. Maps to `struct bpf_uprobe_multi_opts` in
+/// libbpf.
+#[derive(Clone, Debug, Default)]
+pub struct UprobeMultiOpts {
+    /// Names of the functions to attach to. Mutually exclusive with `offsets`.
+    pub symbols: Vec<String>,
+    /// Offsets of the functions to attach to in the binary. Mutually exclusive with `symbols`.
+    pub offsets: Vec<usize>,
+    /// Offsets of the USDT semaphores guarding the probes, see [`UprobeOpts::ref_ctr_offset`].
+    /// Either empty or one per function.
+    pub ref_ctr_offsets: Vec<usize>,
+    /// Values returned by `bpf_get_attach_cookie()` to the program, one per function. Either
+    /// empty or one per function.
+    pub cookies: Vec<u64>,
+    /// Attach uretprobes, i.e. fire on return from the functions instead of on entry.
+    pub retprobe: bool,
+}
+
+/// Options for [`Program::attach_tracepoint_with_opts()`]. Maps to `struct bpf_tracepoint_opts`
+/// in libbpf.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TracepointOpts {
+    /// Value returned by `bpf_get_attach_cookie()` to the program when the tracepoint fires.
+    pub cookie: u64,
+    /// Tracepoints always use perf events, so [`ProbeAttachMode::Legacy`] is the same as
+    /// [`ProbeAttachMode::Perf`] here.
+    pub attach_mode: ProbeAttachMode,
+}
+
+impl TracepointOpts {
+    fn to_libbpf(self) -> libbpf_sys::bpf_tracepoint_opts {
+        libbpf_sys::bpf_tracepoint_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_tracepoint_opts>() as libbpf_sys::size_t,
+            bpf_cookie: self.cookie,
+        }
+    }
+}
+
+/// Returns where tracefs is mounted.
+fn tracefs_path() -> &'static str {
+    // tracefs used to be available only as part of debugfs
+    if Path::new("/sys/kernel/tracing/events").exists() {
+        "/sys/kernel/tracing"
+    } else {
+        "/sys/kernel/debug/tracing"
+    }
+}
+
+/// Returns the id of the tracepoint `tp_category:tp_name`, as found in tracefs.
+fn tracepoint_id(tp_category: &str, tp_name: &str) -> Result<u64> {
+    let path = format!("{}/events/{}/{}/id", tracefs_path(), tp_category, tp_name);
+    let id = fs::read_to_string(&path)
+        .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)))?;
+    id.trim()
+        .parse()
+        .map_err(|_| Error::Internal(format!("malformed tracepoint id in {}", path)))
+}
+
+/// Append `cmd` to `kprobe_events` in tracefs.
+fn write_kprobe_events(cmd: &str) -> Result<()> {
+    fs::OpenOptions::new()
+        .append(true)
+        .open(format!("{}/kprobe_events", tracefs_path()))
+        .and_then(|mut file| file.write_all(cmd.as_bytes()))
+        .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)))
+}
+
+/// Create a kretprobe event in the `kretprobes` group for `func_name+offset`, tracking up to
+/// `maxactive` invocations at once. Returns the name of the event.
+fn add_kretprobe_event(func_name: &str, offset: usize, maxactive: u32) -> Result<String> {
+    static INDEX: AtomicUsize = AtomicUsize::new(0);
+
+    // Named like the events libbpf creates. The kernel only allows alphanumerics and
+    // underscores, and at most 63 of them.
+    let event: String = format!(
+        "libbpf_rs_{}_{}_{}_0x{:x}",
+        process::id(),
+        INDEX.fetch_add(1, Ordering::Relaxed),
+        func_name,
+        offset
+    )
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+    .take(63)
+    .collect();
+
+    write_kprobe_events(&format!(
+        "r{}:kretprobes/{} {}+0x{:x}",
+        maxactive, event, func_name, offset
+    ))?;
+    Ok(event)
+}
+
+/// Remove the kretprobe event `event` created by `add_kretprobe_event()`.
+pub(crate) fn remove_kretprobe_event(event: &str) -> Result<()> {
+    write_kprobe_events(&format!("-:kretprobes/{}", event))
+}
+
+/// Returns whether the running kernel has the raw tracepoint `tp_name`, or `None` if that cannot
+/// be determined because the kernel has no BTF.
+fn raw_tracepoint_exists(tp_name: &str) -> Option<bool> {
+    // Every raw tracepoint comes with a `btf_trace_<name>` typedef describing its arguments
+    let type_name = util::str_to_cstring(&format!("btf_trace_{}", tp_name)).ok()?;
+
+    let btf = unsafe { libbpf_sys::libbpf_find_kernel_btf() };
+    let err = unsafe { libbpf_sys::libbpf_get_error(btf as *const _) };
+    if err != 0 {
+        return None;
+    }
+
+    let id = unsafe {
+        libbpf_sys::btf__find_by_name_kind(btf, type_name.as_ptr(), libbpf_sys::BTF_KIND_TYPEDEF)
+    };
+    unsafe { libbpf_sys::btf__free(btf) };
+    Some(id > 0)
+}
+
+/// Options for [`Program::attach_raw_tracepoint_with_opts()`]. Maps to
+/// `struct bpf_raw_tracepoint_opts` in libbpf.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RawTracepointOpts {
+    /// Value returned by `bpf_get_attach_cookie()` to the program when the tracepoint fires.
+    /// Requires Linux 6.10.
+    pub cookie: u64,
+}
+
+impl RawTracepointOpts {
+    fn to_libbpf(self) -> libbpf_sys::bpf_raw_tracepoint_opts {
+        libbpf_sys::bpf_raw_tracepoint_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_raw_tracepoint_opts>() as libbpf_sys::size_t,
+            cookie: self.cookie,
+        }
+    }
+}
+
+bitflags! {
+    /// Flags for [`Program::attach_cgroup_with_opts()`]. Map to the `BPF_F_*` attach flags in
+    /// kernel uapi.
+    ///
+    /// Without flags, the program is the only one of its attach type in the cgroup, and
+    /// descendant cgroups cannot attach programs of their own.
+    #[derive(Default)]
+    pub struct CgroupAttachFlags: u32 {
+        /// Allow descendant cgroups to override the program with their own.
+        const ALLOW_OVERRIDE = libbpf_sys::BPF_F_ALLOW_OVERRIDE;
+        /// Allow multiple programs in the cgroup and its descendants, which all run.
+        const ALLOW_MULTI    = libbpf_sys::BPF_F_ALLOW_MULTI;
+        /// Atomically replace [`CgroupAttachOpts::replace_prog_fd`]. Requires `ALLOW_MULTI`.
+        const REPLACE        = libbpf_sys::BPF_F_REPLACE;
+    }
+}
+
+/// Options for [`Program::attach_cgroup_with_opts()`]. Maps to `struct bpf_prog_attach_opts`
+/// in libbpf.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CgroupAttachOpts<'fd> {
+    pub flags: CgroupAttachFlags,
+    /// The program to replace, if [`CgroupAttachFlags::REPLACE`] is set.
+    pub replace_prog_fd: Option<BorrowedFd<'fd>>,
+}
+
+/// Where to insert a program into the list of programs of a hook supporting multiple ordered
+/// programs, like tcx.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum AttachOrder<'fd> {
+    /// Run before all other programs.
+    First,
+    /// Run after all other programs.
+    #[default]
+    Last,
+    /// Run right before the given program or link.
+    Before(AttachAnchor<'fd>),
+    /// Run right after the given program or link.
+    After(AttachAnchor<'fd>),
+}
+
+impl AttachOrder<'_> {
+    /// Returns the `flags`, `relative_fd` and `relative_id` for libbpf
+    fn to_libbpf(self) -> (u32, u32, u32) {
+        let (flags, anchor) = match self {
+            AttachOrder::First => return (libbpf_sys::BPF_F_BEFORE, 0, 0),
+            // Appending is what the kernel does by default
+            AttachOrder::Last => return (0, 0, 0),
+            AttachOrder::Before(anchor) => (libbpf_sys::BPF_F_BEFORE, anchor),
+            AttachOrder::After(anchor) => (libbpf_sys::BPF_F_AFTER, anchor),
+        };
+
+        match anchor {
+            AttachAnchor::ProgFd(fd) => (flags, fd.as_raw_fd() as u32, 0),
+            AttachAnchor::ProgId(id) => (flags, 0, id),
+            AttachAnchor::LinkFd(fd) => (flags | libbpf_sys::BPF_F_LINK, fd.as_raw_fd() as u32, 0),
+            AttachAnchor::LinkId(id) => (flags | libbpf_sys::BPF_F_LINK, 0, id),
+        }
+    }
+}
+
+/// A program or link relative to which [`AttachOrder`] inserts a program.
+#[derive(Clone, Copy, Debug)]
+pub enum AttachAnchor<'fd> {
+    ProgFd(BorrowedFd<'fd>),
+    ProgId(u32),
+    LinkFd(BorrowedFd<'fd>),
+    LinkId(u32),
+}
+
+/// Options for [`Program::attach_tcx()`]. Maps to `struct bpf_tcx_opts` in libbpf.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TcxOpts<'fd> {
+    pub order: AttachOrder<'fd>,
+    /// Only attach if the list of programs of the hook is still at this revision, so that
+    /// `order` is not applied to a list changed by someone else in the meantime. Zero to always
+    /// attach.
+    pub expected_revision: u64,
+}
+
+impl TcxOpts<'_> {
+    fn to_libbpf(self) -> libbpf_sys::bpf_tcx_opts {
+        let (flags, relative_fd, relative_id) = self.order.to_libbpf();
+        libbpf_sys::bpf_tcx_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_tcx_opts>() as libbpf_sys::size_t,
+            flags,
+            relative_fd,
+            relative_id,
+            expected_revision: self.expected_revision,
+            ..Default::default()
+        }
+    }
+}
+
+/// Options for [`Program::attach_netkit()`]. Maps to `struct bpf_netkit_opts` in libbpf.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NetkitOpts<'fd> {
+    pub order: AttachOrder<'fd>,
+    /// See [`TcxOpts::expected_revision`].
+    pub expected_revision: u64,
+}
+
+impl NetkitOpts<'_> {
+    fn to_libbpf(self) -> libbpf_sys::bpf_netkit_opts {
+        let (flags, relative_fd, relative_id) = self.order.to_libbpf();
+        libbpf_sys::bpf_netkit_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_netkit_opts>() as libbpf_sys::size_t,
+            flags,
+            relative_fd,
+            relative_id,
+            expected_revision: self.expected_revision,
+            ..Default::default()
+        }
+    }
+}
+
+/// Options for [`Program::attach_usdt_with_opts()`]. Maps to `struct bpf_usdt_opts` in libbpf.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UsdtOpts {
+    /// Value returned by `bpf_usdt_cookie()` to the program when the probe fires.
+    pub cookie: u64,
+}
+
+impl UsdtOpts {
+    fn to_libbpf(self) -> libbpf_sys::bpf_usdt_opts {
+        libbpf_sys::bpf_usdt_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_usdt_opts>() as libbpf_sys::size_t,
+            usdt_cookie: self.cookie,
+        }
+    }
+}
+
+/// Options for [`Program::test_run()`]. Maps to `struct bpf_test_run_opts` in libbpf.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TestRunOpts<'a> {
+    /// Input data of the program, e.g. a packet for XDP and tc programs.
+    pub data_in: &'a [u8],
+    /// Size of the buffer for the output data, e.g. the packet as modified by the program.
+    /// Defaults to the size of `data_in`.
+    pub data_out_size: Option<usize>,
+    /// Context passed to the program, e.g. a `struct __sk_buff` for tc programs or the
+    /// arguments of `SEC("raw_tp")` and `SEC("syscall")` programs.
+    pub ctx_in: &'a [u8],
+    /// Size of the buffer for the context as modified by the program. Defaults to the size of
+    /// `ctx_in`.
+    pub ctx_out_size: Option<usize>,
+    /// Run the program this many times and report the average duration. Zero and one both run
+    /// it once.
+    pub repeat: u32,
+    pub flags: TestRunFlags,
+    /// Run the program on this CPU instead of the calling one. Only supported for
+    /// `SEC("raw_tp")` programs.
+    pub cpu: Option<u32>,
+    /// Number of frames sent per batch with [`TestRunFlags::XDP_LIVE_FRAMES`]. The kernel
+    /// picks a size if zero.
+    pub batch_size: u32,
+}
+
+bitflags! {
+    /// Flags for [`Program::test_run()`]. Map to the `BPF_F_TEST_*` flags in kernel uapi.
+    #[derive(Default)]
+    pub struct TestRunFlags: u32 {
+        /// Compute the checksum of the packet passed to a tc program as `CHECKSUM_COMPLETE`
+        /// would, instead of leaving it unset.
+        const SKB_CHECKSUM_COMPLETE = libbpf_sys::BPF_F_TEST_SKB_CHECKSUM_COMPLETE;
+        /// Instead of just returning the action of an XDP program, act on it by transmitting
+        /// or redirecting `data_in` as a real frame, [`TestRunOpts::repeat`] times. This makes
+        /// it possible to benchmark XDP programs and to generate traffic. Requires Linux 5.18.
+        ///
+        /// No output data or context is returned.
+        const XDP_LIVE_FRAMES       = libbpf_sys::BPF_F_TEST_XDP_LIVE_FRAMES;
+    }
+}
+
+/// Result of [`Program::test_run()`].
+#[derive(Clone, Debug)]
+pub struct TestRunOutput {
+    /// Value returned by the program, e.g. an XDP action, of its last run.
+    pub return_value: u32,
+    /// Output data of the last run.
+    pub data_out: Vec<u8>,
+    /// Context of the last run, as modified by the program.
+    pub ctx_out: Vec<u8>,
+    /// Average duration of a run.
+    pub duration: Duration,
+}
+
+/// Internal function to pass a buffer to libbpf, which expects a null pointer for no buffer.
+fn slice_ptr_or_null(buf: &mut [u8]) -> *mut c_void {
+    if buf.is_empty() {
+        ptr::null_mut()
+    } else {
+        buf.as_mut_ptr() as *mut c_void
+    }
+}
+
+/// Internal function to read the NUL terminated verifier log out of `log_buf`.
+fn log_buf_str(log_buf: &[u8]) -> Option<&str> {
+    if log_buf.is_empty() {
+        return None;
+    }
+
+    let len = log_buf
+        .iter()
+        .position(|b| *b == 0)
+        .unwrap_or(log_buf.len());
+    std::str::from_utf8(&log_buf[..len]).ok()
 }
@@ -9,9 +9,13 @@
 //!     println!("{}", prog.name);
 //! }
 //! ```
+//!
+//! Similarly, [`LinkInfoIter`] lists every bpf link, along with the program it runs and what
+//! it is attached to, such as a cgroup or a network interface.
 
 use core::ffi::c_void;
 use std::convert::TryFrom;
+use std::fs;
 use std::mem::size_of;
 use std::os::raw::c_char;
 use std::string::String;
@@ -85,7 +89,7 @@ macro_rules! gen_info_impl {
     };
 }
 
-fn name_arr_to_string(a: &[c_char], default: &str) -> String {
+pub(crate) fn name_arr_to_string(a: &[c_char], default: &str) -> String {
     let converted_arr: Vec<u8> = a
         .iter()
         .take_while(|x| **x != 0)
@@ -138,7 +142,7 @@ pub struct ProgramInfo {
 }
 
 impl ProgramInfo {
-    fn from_uapi(_fd: i32, s: libbpf_sys::bpf_prog_info) -> Option<Self> {
+    pub(crate) fn from_uapi(_fd: i32, s: libbpf_sys::bpf_prog_info) -> Option<Self> {
         let name = name_arr_to_string(&s.name, "(?)");
         let ty = match ProgramType::try_from(s.type_) {
             Ok(ty) => ty,
@@ -209,10 +213,14 @@ pub struct MapInfo {
     pub btf_id: u32,
     pub btf_key_type_id: u32,
     pub btf_value_type_id: u32,
+    pub btf_vmlinux_id: u32,
+    pub map_extra: u64,
+    /// Bytes of memory charged to the map, if the kernel reports it in `fdinfo`
+    pub memlock: Option<u64>,
 }
 
 impl MapInfo {
-    fn from_uapi(_fd: i32, s: libbpf_sys::bpf_map_info) -> Option<Self> {
+    pub(crate) fn from_uapi(fd: i32, s: libbpf_sys::bpf_map_info) -> Option<Self> {
         let name = name_arr_to_string(&s.name, "(?)");
         let ty = match MapType::try_from(s.type_) {
             Ok(ty) => ty,
@@ -234,10 +242,26 @@ impl MapInfo {
             btf_id: s.btf_id,
             btf_key_type_id: s.btf_key_type_id,
             btf_value_type_id: s.btf_value_type_id,
+            btf_vmlinux_id: s.btf_vmlinux_id,
+            map_extra: s.map_extra,
+            memlock: fdinfo_field(fd, "memlock"),
         })
     }
 }
 
+/// Read a numeric field from `/proc/self/fdinfo/<fd>`.
+fn fdinfo_field(fd: i32, field: &str) -> Option<u64> {
+    let fdinfo = fs::read_to_string(format!("/proc/self/fdinfo/{}", fd)).ok()?;
+    fdinfo.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        if key == field {
+            value.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
 gen_info_impl!(
     /// Iterator that returns [`MapInfo`]s.
     MapInfoIter,
@@ -279,6 +303,11 @@ pub struct RawTracepointLinkInfo {
 
 pub struct TracingLinkInfo {
     pub attach_type: ProgramAttachType,
+    /// Id of the program or BTF object the attach target belongs to, e.g. the program replaced
+    /// by an `freplace` program
+    pub target_obj_id: u32,
+    /// BTF type id of the attach target
+    pub target_btf_id: u32,
 }
 
 pub struct CgroupLinkInfo {
@@ -291,12 +320,68 @@ pub struct NetNsLinkInfo {
     pub attach_type: ProgramAttachType,
 }
 
+pub struct XdpLinkInfo {
+    pub ifindex: u32,
+}
+
+pub struct StructOpsLinkInfo {
+    pub map_id: u32,
+}
+
+pub struct NetfilterLinkInfo {
+    /// Protocol family, e.g. `NFPROTO_IPV4`
+    pub protocol_family: u32,
+    pub hooknum: u32,
+    pub priority: i32,
+    pub flags: u32,
+}
+
+pub struct KprobeMultiLinkInfo {
+    /// Number of functions the program is attached to
+    pub count: u32,
+    pub flags: u32,
+    /// Number of times the program was not run because of recursion, on kernels reporting it
+    pub missed: u64,
+}
+
+pub struct UprobeMultiLinkInfo {
+    /// Number of locations the program is attached to
+    pub count: u32,
+    pub flags: u32,
+    /// Process the probes are limited to, or zero if there is none
+    pub pid: u32,
+}
+
+pub struct TcxLinkInfo {
+    pub ifindex: u32,
+    pub attach_type: ProgramAttachType,
+}
+
+pub struct NetkitLinkInfo {
+    pub ifindex: u32,
+    pub attach_type: ProgramAttachType,
+}
+
+pub struct SockMapLinkInfo {
+    pub map_id: u32,
+    pub attach_type: ProgramAttachType,
+}
+
 pub enum LinkTypeInfo {
     RawTracepoint(RawTracepointLinkInfo),
     Tracing(TracingLinkInfo),
     Cgroup(CgroupLinkInfo),
     Iter,
     NetNs(NetNsLinkInfo),
+    Xdp(XdpLinkInfo),
+    PerfEvent,
+    KprobeMulti(KprobeMultiLinkInfo),
+    StructOps(StructOpsLinkInfo),
+    Netfilter(NetfilterLinkInfo),
+    Tcx(TcxLinkInfo),
+    UprobeMulti(UprobeMultiLinkInfo),
+    Netkit(NetkitLinkInfo),
+    SockMap(SockMapLinkInfo),
     Unknown,
 }
 
@@ -308,7 +393,7 @@ pub struct LinkInfo {
 }
 
 impl LinkInfo {
-    fn from_uapi(fd: i32, mut s: libbpf_sys::bpf_link_info) -> Option<Self> {
+    pub(crate) fn from_uapi(fd: i32, mut s: libbpf_sys::bpf_link_info) -> Option<Self> {
         let type_info = match s.type_ {
             libbpf_sys::BPF_LINK_TYPE_RAW_TRACEPOINT => {
                 let mut buf = [0; 256];
@@ -336,6 +421,8 @@ impl LinkInfo {
                     s.__bindgen_anon_1.tracing.attach_type
                 })
                 .unwrap_or(ProgramAttachType::Unknown),
+                target_obj_id: unsafe { s.__bindgen_anon_1.tracing.target_obj_id },
+                target_btf_id: unsafe { s.__bindgen_anon_1.tracing.target_btf_id },
             }),
             libbpf_sys::BPF_LINK_TYPE_CGROUP => LinkTypeInfo::Cgroup(CgroupLinkInfo {
                 cgroup_id: unsafe { s.__bindgen_anon_1.cgroup.cgroup_id },
@@ -352,6 +439,59 @@ impl LinkInfo {
                 })
                 .unwrap_or(ProgramAttachType::Unknown),
             }),
+            libbpf_sys::BPF_LINK_TYPE_XDP => LinkTypeInfo::Xdp(XdpLinkInfo {
+                ifindex: unsafe { s.__bindgen_anon_1.xdp.ifindex },
+            }),
+            libbpf_sys::BPF_LINK_TYPE_PERF_EVENT => LinkTypeInfo::PerfEvent,
+            libbpf_sys::BPF_LINK_TYPE_KPROBE_MULTI => {
+                let kprobe_multi = unsafe { s.__bindgen_anon_1.kprobe_multi };
+                LinkTypeInfo::KprobeMulti(KprobeMultiLinkInfo {
+                    count: kprobe_multi.count,
+                    flags: kprobe_multi.flags,
+                    missed: kprobe_multi.missed,
+                })
+            }
+            libbpf_sys::BPF_LINK_TYPE_STRUCT_OPS => LinkTypeInfo::StructOps(StructOpsLinkInfo {
+                map_id: unsafe { s.__bindgen_anon_1.struct_ops.map_id },
+            }),
+            libbpf_sys::BPF_LINK_TYPE_NETFILTER => {
+                let netfilter = unsafe { s.__bindgen_anon_1.netfilter };
+                LinkTypeInfo::Netfilter(NetfilterLinkInfo {
+                    protocol_family: netfilter.pf,
+                    hooknum: netfilter.hooknum,
+                    priority: netfilter.priority,
+                    flags: netfilter.flags,
+                })
+            }
+            libbpf_sys::BPF_LINK_TYPE_TCX => LinkTypeInfo::Tcx(TcxLinkInfo {
+                ifindex: unsafe { s.__bindgen_anon_1.tcx.ifindex },
+                attach_type: ProgramAttachType::try_from(unsafe {
+                    s.__bindgen_anon_1.tcx.attach_type
+                })
+                .unwrap_or(ProgramAttachType::Unknown),
+            }),
+            libbpf_sys::BPF_LINK_TYPE_UPROBE_MULTI => {
+                let uprobe_multi = unsafe { s.__bindgen_anon_1.uprobe_multi };
+                LinkTypeInfo::UprobeMulti(UprobeMultiLinkInfo {
+                    count: uprobe_multi.count,
+                    flags: uprobe_multi.flags,
+                    pid: uprobe_multi.pid,
+                })
+            }
+            libbpf_sys::BPF_LINK_TYPE_NETKIT => LinkTypeInfo::Netkit(NetkitLinkInfo {
+                ifindex: unsafe { s.__bindgen_anon_1.netkit.ifindex },
+                attach_type: ProgramAttachType::try_from(unsafe {
+                    s.__bindgen_anon_1.netkit.attach_type
+                })
+                .unwrap_or(ProgramAttachType::Unknown),
+            }),
+            libbpf_sys::BPF_LINK_TYPE_SOCKMAP => LinkTypeInfo::SockMap(SockMapLinkInfo {
+                map_id: unsafe { s.__bindgen_anon_1.sockmap.map_id },
+                attach_type: ProgramAttachType::try_from(unsafe {
+                    s.__bindgen_anon_1.sockmap.attach_type
+                })
+                .unwrap_or(ProgramAttachType::Unknown),
+            }),
             _ => LinkTypeInfo::Unknown,
         };
 
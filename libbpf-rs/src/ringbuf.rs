@@ -1,22 +1,38 @@
 use core::ffi::c_void;
 use std::boxed::Box;
+use std::cell::Cell;
+use std::ops::ControlFlow;
 use std::os::raw::c_ulong;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd};
 use std::ptr;
 use std::slice;
 use std::time::Duration;
 
+use nix::libc;
+
 use crate::*;
 
+// Workaround for `trait_alias`
+// (https://doc.rust-lang.org/unstable-book/language-features/trait-alias.html)
+// not being available yet. This is just a custom trait plus a blanket implementation.
+pub trait ControlRingBufferCb: FnMut(&[u8]) -> Result<ControlFlow<()>> {}
+impl<T> ControlRingBufferCb for T where T: FnMut(&[u8]) -> Result<ControlFlow<()>> {}
+
 struct RingBufferCallback {
-    cb: Box<dyn FnMut(&[u8]) -> i32>,
+    cb: Box<dyn ControlRingBufferCb>,
+    /// Why the callback stopped the ongoing poll, if it did
+    stopped: Cell<Option<Result<()>>>,
 }
 
 impl RingBufferCallback {
     fn new<F>(cb: F) -> Self
     where
-        F: FnMut(&[u8]) -> i32 + 'static,
+        F: ControlRingBufferCb + 'static,
     {
-        RingBufferCallback { cb: Box::new(cb) }
+        RingBufferCallback {
+            cb: Box::new(cb),
+            stopped: Cell::new(None),
+        }
     }
 }
 
@@ -41,19 +57,42 @@ impl RingBufferBuilder {
     /// manager. The callback should take one argument, a slice of raw bytes,
     /// and return an i32.
     ///
-    /// Non-zero return values in the callback will stop ring buffer consumption early.
+    /// Negative return values in the callback will stop ring buffer consumption
+    /// early, and are returned as [`Error::System`] with the sign flipped.
     ///
     /// The callback provides a raw byte slice. You may find libraries such as
     /// [`plain`](https://crates.io/crates/plain) helpful.
-    pub fn add<NewF>(&mut self, map: &Map, callback: NewF) -> Result<&mut Self>
+    pub fn add<NewF>(&mut self, map: &Map, mut callback: NewF) -> Result<&mut Self>
     where
         NewF: FnMut(&[u8]) -> i32 + 'static,
+    {
+        self.add_with_control(map, move |data| {
+            let ret = callback(data);
+            if ret < 0 {
+                Err(Error::System(-ret))
+            } else {
+                Ok(ControlFlow::Continue(()))
+            }
+        })
+    }
+
+    /// Like [`RingBufferBuilder::add()`], but the callback decides whether to
+    /// go on.
+    ///
+    /// Returning [`ControlFlow::Break`] makes the ongoing [`RingBuffer::poll()`]
+    /// or [`RingBuffer::consume()`] return `Ok(())` right away, e.g. to bound
+    /// the number of samples processed at once. Returning an error stops them
+    /// as well, and they return that error. The remaining samples are processed
+    /// by the next call.
+    pub fn add_with_control<NewF>(&mut self, map: &Map, callback: NewF) -> Result<&mut Self>
+    where
+        NewF: FnMut(&[u8]) -> Result<ControlFlow<()>> + 'static,
     {
         if map.map_type() != MapType::RingBuf {
             return Err(Error::InvalidInput("Must use a RingBuf map".into()));
         }
         self.fd_callbacks
-            .push((map.fd(), RingBufferCallback::new(callback)));
+            .push((map.fd().as_raw_fd(), RingBufferCallback::new(callback)));
         Ok(self)
     }
 
@@ -79,7 +118,7 @@ impl RingBufferBuilder {
                 // Handle errors
                 let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
                 if err != 0 {
-                    return Err(Error::System(err as i32));
+                    return Err(Error::System(-err as i32));
                 }
             } else {
                 // Add a ringbuf to the existing ringbuf manager
@@ -102,14 +141,22 @@ impl RingBufferBuilder {
             ));
         }
 
-        Ok(RingBuffer { ptr, _cbs: cbs })
+        Ok(RingBuffer { ptr, cbs })
     }
 
     unsafe extern "C" fn call_sample_cb(ctx: *mut c_void, data: *mut c_void, size: c_ulong) -> i32 {
         let callback_struct = ctx as *mut RingBufferCallback;
         let callback = (*callback_struct).cb.as_mut();
 
-        callback(slice::from_raw_parts(data as *const u8, size as usize))
+        let result = match callback(slice::from_raw_parts(data as *const u8, size as usize)) {
+            Ok(ControlFlow::Continue(())) => return 0,
+            Ok(ControlFlow::Break(())) => Ok(()),
+            Err(err) => Err(err),
+        };
+        (*callback_struct).stopped.set(Some(result));
+        // Any negative value makes libbpf stop, the actual result is picked up by
+        // `RingBuffer::result()`
+        -libc::ECANCELED
     }
 }
 
@@ -121,39 +168,79 @@ impl RingBufferBuilder {
 pub struct RingBuffer {
     ptr: *mut libbpf_sys::ring_buffer,
     #[allow(clippy::vec_box)]
-    _cbs: Vec<Box<RingBufferCallback>>,
+    cbs: Vec<Box<RingBufferCallback>>,
 }
 
 impl RingBuffer {
     /// Poll from all open ring buffers, calling the registered callback for
     /// each one. Polls continually until we either run out of events to consume
-    /// or `timeout` is reached.
+    /// or `timeout` is reached, or one of the callbacks stops it.
     pub fn poll(&self, timeout: Duration) -> Result<()> {
         assert!(!self.ptr.is_null());
 
         let ret = unsafe { libbpf_sys::ring_buffer__poll(self.ptr, timeout.as_millis() as i32) };
 
-        if ret < 0 {
-            Err(Error::System(-ret))
-        } else {
-            Ok(())
-        }
+        self.result(ret)
     }
 
     /// Greedily consume from all open ring buffers, calling the registered
     /// callback for each one. Consumes continually until we run out of events
-    /// to consume or one of the callbacks returns a non-zero integer.
+    /// to consume or one of the callbacks stops it.
+    ///
+    /// Unlike [`RingBuffer::poll()`], this does not wait for events, which makes
+    /// it the building block for driving ring buffers from an external wakeup
+    /// mechanism, e.g. after a BPF program signalled new data some other way.
     pub fn consume(&self) -> Result<()> {
         assert!(!self.ptr.is_null());
 
         let ret = unsafe { libbpf_sys::ring_buffer__consume(self.ptr) };
 
+        self.result(ret)
+    }
+
+    /// Replace the callbacks registered with the [`RingBufferBuilder`] by the
+    /// ones `new_cb` returns for the index of each ring buffer.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn set_callbacks<F>(&mut self, mut new_cb: F)
+    where
+        F: FnMut(usize) -> Box<dyn ControlRingBufferCb + Send>,
+    {
+        for (idx, callback) in self.cbs.iter_mut().enumerate() {
+            callback.cb = new_cb(idx);
+        }
+    }
+
+    /// Internal function to turn the return value of a libbpf poll or consume
+    /// into a result, taking into account why a callback stopped it.
+    fn result(&self, ret: i32) -> Result<()> {
+        if let Some(result) = self.cbs.iter().find_map(|cb| cb.stopped.take()) {
+            return result;
+        }
+
         if ret < 0 {
+            // Error code is returned negative, flip to positive to match errno
             Err(Error::System(-ret))
         } else {
             Ok(())
         }
     }
+
+    /// Returns the epoll file descriptor that all ring buffers are registered
+    /// with, to multiplex them into an existing event loop, e.g. alongside
+    /// sockets and timers.
+    ///
+    /// The file descriptor becomes readable when there is data in any of the
+    /// ring buffers, which [`RingBuffer::consume()`] then processes.
+    pub fn epoll_fd(&self) -> BorrowedFd<'_> {
+        // The fd is owned by the ring buffer manager and valid for as long as it exists
+        unsafe { BorrowedFd::borrow_raw(libbpf_sys::ring_buffer__epoll_fd(self.ptr)) }
+    }
+}
+
+impl AsFd for RingBuffer {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.epoll_fd()
+    }
 }
 
 impl Drop for RingBuffer {
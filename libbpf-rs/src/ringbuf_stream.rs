@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+use std::ops::ControlFlow;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use nix::libc;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+use crate::*;
+
+impl RingBuffer {
+    /// Turn the ring buffers into a [`Stream`] of the samples they receive, for
+    /// consuming them from async code without a blocking polling thread.
+    ///
+    /// The stream replaces the callbacks registered with the
+    /// [`RingBufferBuilder`]. It has to be created from within a tokio runtime
+    /// with IO enabled.
+    pub fn stream(mut self) -> Result<RingBufferStream> {
+        let samples = Arc::new(Mutex::new(VecDeque::new()));
+
+        self.set_callbacks(|ring| {
+            let samples = samples.clone();
+            Box::new(move |data: &[u8]| {
+                samples.lock().unwrap().push_back(RingBufferSample {
+                    ring,
+                    data: data.to_vec(),
+                });
+                Ok(ControlFlow::Continue(()))
+            })
+        });
+
+        let async_fd = AsyncFd::with_interest(self.epoll_fd().as_raw_fd(), Interest::READABLE)
+            .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)))?;
+
+        Ok(RingBufferStream {
+            async_fd,
+            rb: self,
+            samples,
+        })
+    }
+}
+
+/// A sample received by a [`RingBufferStream`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RingBufferSample {
+    /// Index of the ring buffer the sample was read from, in the order the maps
+    /// were added to the [`RingBufferBuilder`]
+    pub ring: usize,
+    pub data: Vec<u8>,
+}
+
+/// A [`RingBuffer`] consumed as an async stream, see [`RingBuffer::stream()`].
+pub struct RingBufferStream {
+    // Deregisters the epoll fd of `rb` from the runtime, so it has to be dropped first
+    async_fd: AsyncFd<RawFd>,
+    rb: RingBuffer,
+    samples: Arc<Mutex<VecDeque<RingBufferSample>>>,
+}
+
+// libbpf's ring buffer manager is not tied to the thread that created it, and all callbacks were
+// replaced by `Send` ones
+unsafe impl Send for RingBufferStream {}
+
+impl Stream for RingBufferStream {
+    type Item = Result<RingBufferSample>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(sample) = this.samples.lock().unwrap().pop_front() {
+                return Poll::Ready(Some(Ok(sample)));
+            }
+
+            let mut guard = match this.async_fd.poll_read_ready(cx) {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(e)) => {
+                    let err = Error::System(e.raw_os_error().unwrap_or(libc::EINVAL));
+                    return Poll::Ready(Some(Err(err)));
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+
+            if let Err(e) = this.rb.consume() {
+                return Poll::Ready(Some(Err(e)));
+            }
+            if this.samples.lock().unwrap().is_empty() {
+                // Drained, wait for the next wakeup
+                guard.clear_ready();
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs;
+
+use nix::libc;
+
+use crate::*;
+
+/// A symbol an address resolved to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    /// Offset of the address from the start of the symbol
+    pub offset: u64,
+}
+
+/// Resolves addresses of stack frames, e.g. as returned by [`Map::lookup_stack()`], to symbols.
+///
+/// Implement this to plug in a symbolizer for user space stacks, which needs knowledge about
+/// the memory mappings of the traced process. [`KernelSymbolizer`] handles kernel stacks.
+pub trait Symbolizer {
+    /// Returns the symbol containing `addr`, or `None` if it is unknown.
+    fn symbolize(&self, addr: u64) -> Option<Symbol>;
+
+    /// Symbolize every frame of `frames`, keeping their order.
+    fn symbolize_stack(&self, frames: &[u64]) -> Vec<Option<Symbol>> {
+        frames.iter().map(|addr| self.symbolize(*addr)).collect()
+    }
+}
+
+/// A [`Symbolizer`] for kernel addresses based on `/proc/kallsyms`.
+///
+/// Reading symbol addresses from `/proc/kallsyms` usually requires `CAP_SYSLOG`. Without it,
+/// the kernel reports every address as zero and nothing can be symbolized.
+pub struct KernelSymbolizer {
+    /// Symbol start addresses and names, sorted by address
+    syms: Vec<(u64, String)>,
+}
+
+impl KernelSymbolizer {
+    /// Load the symbols of the running kernel.
+    pub fn new() -> Result<Self> {
+        let kallsyms = fs::read_to_string("/proc/kallsyms")
+            .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)))?;
+        Ok(Self::from_kallsyms(&kallsyms))
+    }
+
+    /// Parse symbols in the format of `/proc/kallsyms`.
+    fn from_kallsyms(kallsyms: &str) -> Self {
+        let mut syms: Vec<(u64, String)> = kallsyms
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let addr = u64::from_str_radix(fields.next()?, 16).ok()?;
+                let _ty = fields.next()?;
+                let name = fields.next()?;
+                if addr == 0 {
+                    None
+                } else {
+                    Some((addr, name.to_string()))
+                }
+            })
+            .collect();
+        syms.sort_by_key(|(addr, _)| *addr);
+
+        KernelSymbolizer { syms }
+    }
+}
+
+impl Symbolizer for KernelSymbolizer {
+    fn symbolize(&self, addr: u64) -> Option<Symbol> {
+        // Find the last symbol starting at or before `addr`
+        let idx = match self.syms.binary_search_by_key(&addr, |(start, _)| *start) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+
+        let (start, name) = &self.syms[idx];
+        Some(Symbol {
+            name: name.clone(),
+            offset: addr - start,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kernel_symbolizer() {
+        let symbolizer = KernelSymbolizer::from_kallsyms(
+            "ffffffff81000200 T second\n\
+             ffffffff81000100 T first\n\
+             0000000000000000 A hidden\n\
+             ffffffff81000300 t third\t[mod]\n",
+        );
+
+        assert_eq!(symbolizer.symbolize(0xffffffff81000000), None);
+        assert_eq!(
+            symbolizer.symbolize(0xffffffff81000100),
+            Some(Symbol {
+                name: "first".to_string(),
+                offset: 0,
+            })
+        );
+        assert_eq!(
+            symbolizer.symbolize(0xffffffff81000250),
+            Some(Symbol {
+                name: "second".to_string(),
+                offset: 0x50,
+            })
+        );
+
+        let stack = symbolizer.symbolize_stack(&[0xffffffff81000301, 0x1]);
+        assert_eq!(stack[0].as_ref().map(|s| s.name.as_str()), Some("third"));
+        assert_eq!(stack[1], None);
+    }
+}
@@ -0,0 +1,257 @@
+use std::marker::PhantomData;
+use std::mem;
+use std::os::unix::io::{AsRawFd, BorrowedFd};
+
+use nix::libc;
+
+use crate::*;
+
+/// Where a [`TcHook`] attaches a program. Maps to `enum bpf_tc_attach_point` in libbpf.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcAttachPoint {
+    /// Ingress of the `clsact` qdisc of the interface.
+    Ingress = libbpf_sys::BPF_TC_INGRESS,
+    /// Egress of the `clsact` qdisc of the interface.
+    Egress = libbpf_sys::BPF_TC_EGRESS,
+    /// The qdisc given by [`TcHookBuilder::parent()`], which has to exist already.
+    Custom = libbpf_sys::BPF_TC_CUSTOM,
+}
+
+/// Attaches a `SEC("tc")` ([`ProgramType::SchedCls`]) program to the traffic control layer
+/// of a network interface, using netlink.
+///
+/// Unlike most attachments, the program is not detached when the hook is dropped. Call
+/// [`TcHook::detach()`] and [`TcHook::destroy()`] on teardown.
+///
+/// A hook refers to its program by file descriptor, so it borrows the program like the
+/// [`TcHookBuilder`] it was built with.
+#[derive(Clone, Copy, Debug)]
+pub struct TcHook<'fd> {
+    hook: libbpf_sys::bpf_tc_hook,
+    opts: libbpf_sys::bpf_tc_opts,
+    /// The program `opts.prog_fd` belongs to
+    _fd: PhantomData<BorrowedFd<'fd>>,
+}
+
+impl TcHook<'_> {
+    /// Create the `clsact` qdisc of the interface that the [`TcAttachPoint::Ingress`] and
+    /// [`TcAttachPoint::Egress`] hooks live in.
+    ///
+    /// It is not an error if the qdisc exists already, e.g. because it was created for the hook
+    /// in the other direction.
+    pub fn create(&mut self) -> Result<&mut Self> {
+        let ret = unsafe { libbpf_sys::bpf_tc_hook_create(&mut self.hook) };
+        if ret != 0 && ret != -libc::EEXIST {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret));
+        }
+
+        Ok(self)
+    }
+
+    /// Attach the program. On success, the handle and priority chosen by the kernel are
+    /// available through [`TcHook::handle()`] and [`TcHook::priority()`] if they were not set.
+    pub fn attach(&mut self) -> Result<&mut Self> {
+        // Set by an earlier attach, but libbpf only takes a program fd
+        self.opts.prog_id = 0;
+        let ret = unsafe { libbpf_sys::bpf_tc_attach(&self.hook, &mut self.opts) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret));
+        }
+
+        Ok(self)
+    }
+
+    /// Detach the program attached with the handle and priority of this hook.
+    pub fn detach(&mut self) -> Result<()> {
+        let opts = self.filter_opts();
+        let ret = unsafe { libbpf_sys::bpf_tc_detach(&self.hook, &opts) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the id of the program attached with the handle and priority of this hook.
+    pub fn query(&self) -> Result<u32> {
+        let mut opts = self.filter_opts();
+        let ret = unsafe { libbpf_sys::bpf_tc_query(&self.hook, &mut opts) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret));
+        }
+
+        Ok(opts.prog_id)
+    }
+
+    /// Remove the `clsact` qdisc of the interface, detaching all programs from both ingress
+    /// and egress, including ones not attached through this hook.
+    ///
+    /// This is not supported for [`TcAttachPoint::Custom`] hooks.
+    pub fn destroy(&mut self) -> Result<()> {
+        let mut hook = self.hook;
+        if hook.attach_point != TcAttachPoint::Custom as u32 {
+            // libbpf only removes the qdisc if asked for both directions at once
+            hook.attach_point = libbpf_sys::BPF_TC_INGRESS | libbpf_sys::BPF_TC_EGRESS;
+        }
+
+        let ret = unsafe { libbpf_sys::bpf_tc_hook_destroy(&mut hook) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret));
+        }
+
+        Ok(())
+    }
+
+    pub fn ifindex(&self) -> i32 {
+        self.hook.ifindex
+    }
+
+    pub fn handle(&self) -> u32 {
+        self.opts.handle
+    }
+
+    pub fn priority(&self) -> u32 {
+        self.opts.priority
+    }
+
+    /// Options identifying the filter of this hook, as libbpf expects them for detach and query
+    fn filter_opts(&self) -> libbpf_sys::bpf_tc_opts {
+        libbpf_sys::bpf_tc_opts {
+            prog_fd: 0,
+            prog_id: 0,
+            flags: 0,
+            ..self.opts
+        }
+    }
+}
+
+/// Builds [`TcHook`]s for a program, which share the interface, handle, priority and parent.
+///
+/// ```no_run
+/// # use libbpf_rs::{Program, Result, TcAttachPoint, TcHookBuilder};
+/// # fn attach(prog: &Program) -> Result<()> {
+/// let mut builder = TcHookBuilder::new(prog.fd());
+/// builder.ifindex(1).replace(true).handle(1).priority(1);
+///
+/// let mut ingress = builder.hook(TcAttachPoint::Ingress);
+/// ingress.create()?.attach()?;
+/// let mut egress = builder.hook(TcAttachPoint::Egress);
+/// egress.attach()?;
+///
+/// // ...
+///
+/// ingress.detach()?;
+/// egress.detach()?;
+/// ingress.destroy()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct TcHookBuilder<'fd> {
+    fd: BorrowedFd<'fd>,
+    ifindex: i32,
+    parent: (u32, u32),
+    replace: bool,
+    handle: u32,
+    priority: u32,
+}
+
+impl<'fd> TcHookBuilder<'fd> {
+    /// Start building hooks for the `SEC("tc")` program `fd`.
+    pub fn new(fd: BorrowedFd<'fd>) -> Self {
+        TcHookBuilder {
+            fd,
+            ifindex: 0,
+            parent: (0, 0),
+            replace: false,
+            handle: 0,
+            priority: 0,
+        }
+    }
+
+    /// Index of the network interface to attach to.
+    pub fn ifindex(&mut self, ifindex: i32) -> &mut Self {
+        self.ifindex = ifindex;
+        self
+    }
+
+    /// The qdisc `major:minor` to attach to. Only used for [`TcAttachPoint::Custom`] hooks.
+    pub fn parent(&mut self, major: u32, minor: u32) -> &mut Self {
+        self.parent = (major, minor);
+        self
+    }
+
+    /// Replace the program of an existing filter with the same handle and priority instead of
+    /// failing with `EEXIST`.
+    pub fn replace(&mut self, replace: bool) -> &mut Self {
+        self.replace = replace;
+        self
+    }
+
+    /// Handle of the filter. The kernel picks one if unset.
+    pub fn handle(&mut self, handle: u32) -> &mut Self {
+        self.handle = handle;
+        self
+    }
+
+    /// Priority of the filter, lower values run first. The kernel picks one if unset.
+    pub fn priority(&mut self, priority: u32) -> &mut Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Build a hook for `attach_point`.
+    pub fn hook(&self, attach_point: TcAttachPoint) -> TcHook<'fd> {
+        let (major, minor) = self.parent;
+        let parent = match attach_point {
+            TcAttachPoint::Custom => tc_handle(major, minor),
+            _ => 0,
+        };
+
+        TcHook {
+            hook: libbpf_sys::bpf_tc_hook {
+                sz: mem::size_of::<libbpf_sys::bpf_tc_hook>() as libbpf_sys::size_t,
+                ifindex: self.ifindex,
+                attach_point: attach_point as u32,
+                parent,
+                ..Default::default()
+            },
+            opts: libbpf_sys::bpf_tc_opts {
+                sz: mem::size_of::<libbpf_sys::bpf_tc_opts>() as libbpf_sys::size_t,
+                prog_fd: self.fd.as_raw_fd(),
+                flags: if self.replace {
+                    libbpf_sys::BPF_TC_F_REPLACE
+                } else {
+                    0
+                },
+                handle: self.handle,
+                priority: self.priority,
+                ..Default::default()
+            },
+            _fd: PhantomData,
+        }
+    }
+}
+
+/// Combine `major:minor` into a tc handle, like `TC_H_MAKE()` in kernel uapi.
+fn tc_handle(major: u32, minor: u32) -> u32 {
+    (major << 16) | (minor & 0xffff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tc_handle() {
+        assert_eq!(tc_handle(0, 0), 0);
+        assert_eq!(tc_handle(1, 0), 0x10000);
+        assert_eq!(tc_handle(0xffff, 0xfff2), 0xfffffff2);
+    }
+}
@@ -0,0 +1,116 @@
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ptr;
+
+use plain::Plain;
+
+use crate::*;
+
+/// Converts a value into the raw bytes the kernel expects.
+fn to_bytes<T: Plain>(val: &T) -> &[u8] {
+    // `Plain` types have no invalid bit patterns. Any padding bytes are passed to the kernel
+    // as-is, which is no different from userspace handing over a C struct.
+    unsafe { plain::as_bytes(val) }
+}
+
+/// Converts raw bytes returned by the kernel into a value.
+///
+/// `bytes` must be exactly `size_of::<T>()` long, which [`TypedMap::new()`] guarantees.
+fn from_bytes<T: Plain>(bytes: &[u8]) -> T {
+    assert_eq!(bytes.len(), size_of::<T>());
+    // The buffer is not necessarily aligned for `T`, so read it unaligned. `Plain` guarantees
+    // every bit pattern is a valid `T`.
+    unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) }
+}
+
+/// A [`Map`] with statically typed keys and values.
+///
+/// The key and value types must match [`Map::key_size()`] and [`Map::value_size()`], which is
+/// checked once upon construction. All operations then take and return `K` and `V` directly
+/// instead of raw bytes.
+///
+/// Both types must implement [`plain::Plain`], i.e. be valid for any bit pattern. This is usually
+/// the case for the `#[repr(C)]` types generated for a skeleton.
+///
+/// Per-cpu maps are not supported.
+pub struct TypedMap<'a, K: Plain, V: Plain> {
+    map: &'a mut Map,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+impl<'a, K: Plain, V: Plain> TypedMap<'a, K, V> {
+    pub fn new(map: &'a mut Map) -> Result<Self> {
+        if map.map_type().is_percpu() {
+            return Err(Error::InvalidInput(format!(
+                "TypedMap does not support per-cpu maps (type of the map is {})",
+                map.map_type(),
+            )));
+        }
+
+        if map.key_size() as usize != size_of::<K>() {
+            return Err(Error::InvalidInput(format!(
+                "key_size {} != size of key type {}",
+                map.key_size(),
+                size_of::<K>()
+            )));
+        }
+
+        if map.value_size() as usize != size_of::<V>() {
+            return Err(Error::InvalidInput(format!(
+                "value_size {} != size of value type {}",
+                map.value_size(),
+                size_of::<V>()
+            )));
+        }
+
+        Ok(TypedMap {
+            map,
+            _k: PhantomData,
+            _v: PhantomData,
+        })
+    }
+
+    /// Returns the underlying untyped [`Map`].
+    pub fn map(&self) -> &Map {
+        self.map
+    }
+
+    /// See [`Map::lookup()`].
+    pub fn lookup(&self, key: &K, flags: MapFlags) -> Result<Option<V>> {
+        Ok(self
+            .map
+            .lookup(to_bytes(key), flags)?
+            .map(|v| from_bytes(&v)))
+    }
+
+    /// See [`Map::update()`].
+    pub fn update(&mut self, key: &K, value: &V, flags: MapFlags) -> Result<()> {
+        self.map.update(to_bytes(key), to_bytes(value), flags)
+    }
+
+    /// See [`Map::delete()`].
+    pub fn delete(&mut self, key: &K) -> Result<()> {
+        self.map.delete(to_bytes(key))
+    }
+
+    /// See [`Map::lookup_and_delete()`].
+    pub fn lookup_and_delete(&mut self, key: &K) -> Result<Option<V>> {
+        Ok(self
+            .map
+            .lookup_and_delete(to_bytes(key))?
+            .map(|v| from_bytes(&v)))
+    }
+
+    /// See [`Map::keys()`].
+    pub fn keys(&self) -> impl Iterator<Item = K> + '_ {
+        self.map.keys().map(|k| from_bytes(&k))
+    }
+
+    /// See [`Map::iter()`].
+    pub fn iter(&self) -> impl Iterator<Item = Result<(K, V)>> + '_ {
+        self.map
+            .iter()
+            .map(|entry| entry.map(|(k, v)| (from_bytes(&k), from_bytes(&v))))
+    }
+}
@@ -0,0 +1,196 @@
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+use goblin::elf::Elf;
+use nix::libc;
+
+use crate::*;
+
+/// Type of the ELF notes describing USDT probes
+const NT_STAPSDT: u32 = 3;
+
+/// A USDT probe found in an ELF binary by [`usdt_probes()`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct UsdtProbe {
+    pub provider: String,
+    pub name: String,
+    /// Address of the probe, as recorded in the binary
+    pub addr: u64,
+    /// Address of the `.stapsdt.base` section, as recorded in the binary. Comparing it with the
+    /// actual address of the section reveals if the binary was prelinked.
+    pub base_addr: u64,
+    /// Address of the semaphore guarding the probe, or zero if there is none
+    pub semaphore_addr: u64,
+    pub args: Vec<UsdtArg>,
+}
+
+/// An argument of a [`UsdtProbe`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct UsdtArg {
+    /// Size of the argument in bytes
+    pub size: usize,
+    pub signed: bool,
+    /// Where the argument's value is to be found when the probe fires, in the assembler syntax
+    /// of the architecture, e.g. `%edi` or `-8(%rbp)` on x86
+    pub location: String,
+}
+
+/// List the USDT probes of the ELF binary at `binary_path`, e.g. to show the user what can be
+/// passed to [`Program::attach_usdt()`].
+pub fn usdt_probes<P: AsRef<Path>>(binary_path: P) -> Result<Vec<UsdtProbe>> {
+    let data = fs::read(binary_path.as_ref())
+        .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)))?;
+    let elf = Elf::parse(&data)
+        .map_err(|e| Error::InvalidInput(format!("failed to parse ELF binary: {}", e)))?;
+
+    let notes = match elf.iter_note_sections(&data, Some(".note.stapsdt")) {
+        Some(notes) => notes,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut probes = Vec::new();
+    for note in notes {
+        let note =
+            note.map_err(|e| Error::InvalidInput(format!("failed to parse ELF note: {}", e)))?;
+        if note.n_type != NT_STAPSDT || note.name != "stapsdt" {
+            continue;
+        }
+        probes.push(UsdtProbe::from_note_desc(
+            note.desc,
+            elf.is_64,
+            elf.little_endian,
+        )?);
+    }
+
+    Ok(probes)
+}
+
+impl UsdtProbe {
+    /// Parse the descriptor of a `stapsdt` note.
+    ///
+    /// It consists of the probe, base and semaphore addresses, followed by the NUL terminated
+    /// provider, name and argument strings.
+    fn from_note_desc(desc: &[u8], is_64: bool, little_endian: bool) -> Result<Self> {
+        let invalid = || Error::InvalidInput("malformed stapsdt note".to_string());
+
+        let word_size = if is_64 { 8 } else { 4 };
+        if desc.len() < 3 * word_size {
+            return Err(invalid());
+        }
+        let word = |idx: usize| -> u64 {
+            let bytes = &desc[idx * word_size..(idx + 1) * word_size];
+            match (is_64, little_endian) {
+                (true, true) => u64::from_le_bytes(bytes.try_into().unwrap()),
+                (true, false) => u64::from_be_bytes(bytes.try_into().unwrap()),
+                (false, true) => u32::from_le_bytes(bytes.try_into().unwrap()) as u64,
+                (false, false) => u32::from_be_bytes(bytes.try_into().unwrap()) as u64,
+            }
+        };
+
+        let mut strings = desc[3 * word_size..]
+            .split(|b| *b == 0)
+            .map(|s| String::from_utf8_lossy(s).into_owned());
+        let provider = strings.next().ok_or_else(invalid)?;
+        let name = strings.next().ok_or_else(invalid)?;
+        let args = strings.next().unwrap_or_default();
+
+        Ok(UsdtProbe {
+            provider,
+            name,
+            addr: word(0),
+            base_addr: word(1),
+            semaphore_addr: word(2),
+            args: parse_usdt_args(&args)?,
+        })
+    }
+}
+
+/// Parse the argument string of a probe, e.g. `-4@%edi 8@-8(%rbp)`.
+fn parse_usdt_args(args: &str) -> Result<Vec<UsdtArg>> {
+    // Arguments are separated by spaces, but locations may contain spaces inside brackets, like
+    // `8@[sp, 16]` on arm64
+    let mut specs = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (idx, c) in args.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ' ' | '\t' if depth == 0 => {
+                specs.push(&args[start..idx]);
+                start = idx + 1;
+            }
+            _ => (),
+        }
+    }
+    specs.push(&args[start..]);
+
+    specs
+        .into_iter()
+        .filter(|spec| !spec.is_empty())
+        .map(|spec| {
+            let invalid = || Error::InvalidInput(format!("malformed USDT argument '{}'", spec));
+            let (size, location) = spec.split_once('@').ok_or_else(invalid)?;
+            let size: isize = size.parse().map_err(|_| invalid())?;
+            Ok(UsdtArg {
+                size: size.unsigned_abs(),
+                signed: size < 0,
+                location: location.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usdt_probe_from_note_desc() {
+        let mut desc = Vec::new();
+        desc.extend_from_slice(&0x1234u64.to_le_bytes());
+        desc.extend_from_slice(&0x2000u64.to_le_bytes());
+        desc.extend_from_slice(&0u64.to_le_bytes());
+        desc.extend_from_slice(b"libc\0setjmp\0-4@%edi 8@[sp, 16]  4@$5\0");
+
+        let probe = UsdtProbe::from_note_desc(&desc, true, true).unwrap();
+        assert_eq!(probe.provider, "libc");
+        assert_eq!(probe.name, "setjmp");
+        assert_eq!(probe.addr, 0x1234);
+        assert_eq!(probe.base_addr, 0x2000);
+        assert_eq!(probe.semaphore_addr, 0);
+        assert_eq!(
+            probe.args,
+            vec![
+                UsdtArg {
+                    size: 4,
+                    signed: true,
+                    location: "%edi".to_string(),
+                },
+                UsdtArg {
+                    size: 8,
+                    signed: false,
+                    location: "[sp, 16]".to_string(),
+                },
+                UsdtArg {
+                    size: 4,
+                    signed: false,
+                    location: "$5".to_string(),
+                },
+            ]
+        );
+
+        let mut desc = Vec::new();
+        desc.extend_from_slice(&0x10u32.to_be_bytes());
+        desc.extend_from_slice(&0x20u32.to_be_bytes());
+        desc.extend_from_slice(&0x30u32.to_be_bytes());
+        desc.extend_from_slice(b"prov\0noargs\0\0");
+        let probe = UsdtProbe::from_note_desc(&desc, false, false).unwrap();
+        assert_eq!(probe.semaphore_addr, 0x30);
+        assert!(probe.args.is_empty());
+
+        assert!(UsdtProbe::from_note_desc(&desc[..8], false, false).is_err());
+        assert!(parse_usdt_args("%edi").is_err());
+    }
+}
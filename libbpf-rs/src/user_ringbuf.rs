@@ -0,0 +1,152 @@
+use core::ffi::c_void;
+use std::convert::TryFrom;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::io::AsRawFd;
+use std::slice;
+use std::time::Duration;
+
+use nix::errno;
+
+use crate::*;
+
+/// The producer side of a [`MapType::UserRingBuf`] map.
+///
+/// This is the reverse of a [`RingBuffer`]: userspace reserves samples, fills them in and
+/// submits them, after which BPF programs consume them with `bpf_user_ringbuf_drain()`.
+///
+/// libbpf only supports a single producer per user ring buffer, which is why this type is
+/// neither `Send` nor `Sync`.
+pub struct UserRingBuffer {
+    ptr: *mut libbpf_sys::user_ring_buffer,
+}
+
+impl UserRingBuffer {
+    /// Create a producer for the user ring buffer `map`.
+    pub fn new(map: &Map) -> Result<Self> {
+        if map.map_type() != MapType::UserRingBuf {
+            return Err(Error::InvalidInput("Must use a UserRingBuf map".into()));
+        }
+
+        let opts = libbpf_sys::user_ring_buffer_opts {
+            sz: mem::size_of::<libbpf_sys::user_ring_buffer_opts>() as libbpf_sys::size_t,
+        };
+
+        let ptr = unsafe { libbpf_sys::user_ring_buffer__new(map.fd().as_raw_fd(), &opts) };
+        if ptr.is_null() {
+            return Err(Error::System(errno::errno()));
+        }
+
+        Ok(UserRingBuffer { ptr })
+    }
+
+    /// Reserve a sample of `size` bytes.
+    ///
+    /// Fails with `ENOSPC` if the buffer currently has no room for the sample. The sample is
+    /// zeroed, and is discarded again unless passed to [`UserRingBuffer::submit()`].
+    pub fn reserve(&self, size: usize) -> Result<UserRingBufferSample<'_>> {
+        let size = Self::sample_size(size)?;
+        let ptr = unsafe { libbpf_sys::user_ring_buffer__reserve(self.ptr, size) };
+        self.wrap_sample(ptr, size)
+    }
+
+    /// Reserve a sample of `size` bytes, waiting up to `timeout` for BPF programs to make room.
+    pub fn reserve_blocking(
+        &self,
+        size: usize,
+        timeout: Duration,
+    ) -> Result<UserRingBufferSample<'_>> {
+        let size = Self::sample_size(size)?;
+        let ptr = unsafe {
+            libbpf_sys::user_ring_buffer__reserve_blocking(
+                self.ptr,
+                size,
+                timeout.as_millis() as i32,
+            )
+        };
+        self.wrap_sample(ptr, size)
+    }
+
+    /// Internal function to check that a sample of `size` bytes can be reserved at all.
+    fn sample_size(size: usize) -> Result<u32> {
+        u32::try_from(size).map_err(|_| {
+            Error::InvalidInput(format!("sample size {} does not fit into a u32", size))
+        })
+    }
+
+    /// Internal function to turn a reservation of `size` bytes returned by libbpf into a sample.
+    fn wrap_sample(&self, ptr: *mut c_void, size: u32) -> Result<UserRingBufferSample<'_>> {
+        if ptr.is_null() {
+            return Err(Error::System(errno::errno()));
+        }
+        let size = size as usize;
+
+        // Samples may contain stale data from earlier rounds through the buffer
+        let data = unsafe { slice::from_raw_parts_mut(ptr as *mut u8, size) };
+        for byte in data.iter_mut() {
+            *byte = 0;
+        }
+
+        Ok(UserRingBufferSample {
+            rb: self,
+            ptr,
+            size,
+        })
+    }
+
+    /// Make `sample` available to BPF programs.
+    pub fn submit(&self, sample: UserRingBufferSample<'_>) -> Result<()> {
+        if sample.rb.ptr != self.ptr {
+            return Err(Error::InvalidInput(
+                "sample was reserved from a different user ring buffer".into(),
+            ));
+        }
+
+        unsafe { libbpf_sys::user_ring_buffer__submit(self.ptr, sample.ptr) };
+        // Submitted, so don't discard it
+        mem::forget(sample);
+        Ok(())
+    }
+
+    /// Give up on `sample` without making it available to BPF programs.
+    ///
+    /// This is the same as dropping the sample.
+    pub fn discard(&self, sample: UserRingBufferSample<'_>) {
+        drop(sample)
+    }
+}
+
+impl Drop for UserRingBuffer {
+    fn drop(&mut self) {
+        unsafe { libbpf_sys::user_ring_buffer__free(self.ptr) };
+    }
+}
+
+/// A sample reserved in a [`UserRingBuffer`], accessible as a byte slice.
+///
+/// Dropping the sample without submitting it discards it.
+pub struct UserRingBufferSample<'a> {
+    rb: &'a UserRingBuffer,
+    ptr: *mut c_void,
+    size: usize,
+}
+
+impl Deref for UserRingBufferSample<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.size) }
+    }
+}
+
+impl DerefMut for UserRingBufferSample<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr as *mut u8, self.size) }
+    }
+}
+
+impl Drop for UserRingBufferSample<'_> {
+    fn drop(&mut self) {
+        unsafe { libbpf_sys::user_ring_buffer__discard(self.rb.ptr, self.ptr) };
+    }
+}
@@ -1,7 +1,10 @@
 use std::ffi::{CStr, CString};
+use std::fs;
 use std::os::raw::c_char;
 use std::path::Path;
 
+use nix::libc;
+
 use crate::*;
 
 pub fn str_to_cstring(s: &str) -> Result<CString> {
@@ -33,6 +36,25 @@ pub fn roundup(num: usize, r: usize) -> usize {
     ((num + (r - 1)) / r) * r
 }
 
+/// Interpret `bytes` as a native-endian unsigned integer of 1, 2, 4, or 8 bytes.
+///
+/// Returns `None` if `bytes` has any other length.
+pub fn ne_bytes_to_u64(bytes: &[u8]) -> Option<u64> {
+    let val = match bytes.len() {
+        1 => u64::from(bytes[0]),
+        2 => u64::from(u16::from_ne_bytes([bytes[0], bytes[1]])),
+        4 => u64::from(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        8 => {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(bytes);
+            u64::from_ne_bytes(buf)
+        }
+        _ => return None,
+    };
+
+    Some(val)
+}
+
 /// Get the number of CPUs in the system, e.g., to interact with per-cpu maps.
 pub fn num_possible_cpus() -> Result<usize> {
     let ret = unsafe { libbpf_sys::libbpf_num_possible_cpus() };
@@ -44,6 +66,29 @@ pub fn num_possible_cpus() -> Result<usize> {
     }
 }
 
+/// Get the ids of the CPUs that are currently online, e.g., to open a perf event on each of them.
+pub fn online_cpus() -> Result<Vec<usize>> {
+    let list = fs::read_to_string("/sys/devices/system/cpu/online")
+        .map_err(|e| Error::System(e.raw_os_error().unwrap_or(libc::EINVAL)))?;
+    parse_cpu_list(&list)
+}
+
+/// Parse a list of CPUs in the kernel's list format, e.g. `0-3,5,7-8`.
+fn parse_cpu_list(list: &str) -> Result<Vec<usize>> {
+    let invalid = || Error::Internal(format!("malformed cpu list '{}'", list.trim()));
+    let parse = |s: &str| s.parse::<usize>().map_err(|_| invalid());
+
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => cpus.extend(parse(start)?..=parse(end)?),
+            None => cpus.push(parse(range)?),
+        }
+    }
+
+    Ok(cpus)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -65,9 +110,36 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_ne_bytes_to_u64() {
+        assert_eq!(ne_bytes_to_u64(&[7]), Some(7));
+        assert_eq!(ne_bytes_to_u64(&513u16.to_ne_bytes()), Some(513));
+        assert_eq!(ne_bytes_to_u64(&70000u32.to_ne_bytes()), Some(70000));
+        assert_eq!(ne_bytes_to_u64(&u64::MAX.to_ne_bytes()), Some(u64::MAX));
+        assert_eq!(ne_bytes_to_u64(&[]), None);
+        assert_eq!(ne_bytes_to_u64(&[1, 2, 3]), None);
+    }
+
     #[test]
     fn test_num_possible_cpus() {
         let num = num_possible_cpus().unwrap();
         assert!(num > 0);
     }
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0\n").unwrap(), vec![0]);
+        assert_eq!(
+            parse_cpu_list("0-3,5,7-8\n").unwrap(),
+            vec![0, 1, 2, 3, 5, 7, 8]
+        );
+        assert!(parse_cpu_list("0-a").is_err());
+    }
+
+    #[test]
+    fn test_online_cpus() {
+        let cpus = online_cpus().unwrap();
+        assert!(!cpus.is_empty());
+        assert!(cpus.len() <= num_possible_cpus().unwrap());
+    }
 }
@@ -1,15 +1,30 @@
 use std::collections::HashSet;
+use std::convert::TryFrom;
 use std::fs;
 use std::io::Read;
+use std::net::UdpSocket;
+use std::ops::ControlFlow;
+use std::os::unix::io::{AsFd, AsRawFd};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::channel;
+use std::sync::Arc;
 use std::time::Duration;
 
 use nix::errno;
 use plain::Plain;
 use scopeguard::defer;
 
-use libbpf_rs::{num_possible_cpus, Iter, MapFlags, Object, ObjectBuilder};
+use libbpf_rs::query::{LinkInfoIter, LinkTypeInfo, MapInfoIter};
+use libbpf_rs::{
+    lpm_trie_key, num_possible_cpus, usdt_probes, AttachAnchor, AttachOrder, CgroupAttachFlags,
+    CgroupAttachOpts, Iter, IterOpts, KprobeMultiOpts, KprobeOpts, Link, MapBatchCursor,
+    MapBatchOpts, MapCreateOpts, MapFlags, MapHandle, MapType, NetfilterFlags, NetkitOpts, Object,
+    ObjectBuilder, PerfBufferBuilder, PerfSample, ProbeAttachMode, Program, ProgramAttachType,
+    ProgramHandle, ProgramType, RawTracepointOpts, TcAttachPoint, TcHookBuilder, TcxOpts,
+    TestRunFlags, TestRunOpts, TracepointOpts, TypedMap, TypedPerfBuffer, UprobeMultiOpts,
+    UprobeOpts, UsdtOpts, UserRingBuffer, XdpFlags,
+};
 
 fn get_test_object_path(filename: &str) -> PathBuf {
     let mut path = PathBuf::new();
@@ -213,6 +228,37 @@ fn test_object_percpu_lookup_update() {
     assert_eq!(res, res2);
 }
 
+#[test]
+fn test_object_percpu_sum() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("percpu_map.bpf.o");
+    let map = obj.map_mut("percpu_map").expect("failed to find map");
+    let key = 0u32.to_ne_bytes();
+
+    let ncpu = num_possible_cpus().unwrap();
+    let vals: Vec<Vec<u8>> = (0..ncpu)
+        .map(|i| (i as u32 + 1).to_ne_bytes().to_vec())
+        .collect();
+    map.update_percpu(&key, &vals, MapFlags::ANY)
+        .expect("failed to update map");
+
+    let sum = map
+        .lookup_percpu_sum(&key, MapFlags::ANY)
+        .expect("failed to lookup")
+        .expect("failed to find value for key");
+    assert_eq!(sum, (ncpu * (ncpu + 1) / 2) as u64);
+
+    let cpus_seen = map
+        .lookup_percpu_fold(&key, MapFlags::ANY, Vec::new(), |mut seen, val| {
+            seen.push(val.to_vec());
+            seen
+        })
+        .expect("failed to lookup")
+        .expect("failed to find value for key");
+    assert_eq!(cpus_seen, vals);
+}
+
 #[test]
 fn test_object_map_empty_lookup() {
     bump_rlimit_mlock();
@@ -251,6 +297,80 @@ fn test_object_map_mutation() {
         .is_none());
 }
 
+#[test]
+fn test_object_map_lookup_and_delete() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+
+    start
+        .update(&[1, 2, 3, 4], &[1, 2, 3, 4, 5, 6, 7, 8], MapFlags::empty())
+        .expect("failed to write");
+
+    let val = start
+        .lookup_and_delete(&[1, 2, 3, 4])
+        .expect("failed to lookup and delete")
+        .expect("failed to find key");
+    assert_eq!(val, &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+    // The element is gone, so a second pop finds nothing
+    assert!(start
+        .lookup_and_delete(&[1, 2, 3, 4])
+        .expect("failed to lookup and delete")
+        .is_none());
+    assert!(start
+        .lookup(&[1, 2, 3, 4], MapFlags::empty())
+        .expect("failed to read map")
+        .is_none());
+}
+
+#[test]
+fn test_object_percpu_invalid_lookup_and_delete() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("percpu_map.bpf.o");
+    let map = obj.map_mut("percpu_map").expect("failed to find map");
+
+    assert!(map.lookup_and_delete(&0u32.to_ne_bytes()).is_err());
+}
+
+#[test]
+fn test_object_typed_map() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+
+    // Value type does not match the map's value size
+    assert!(TypedMap::<u32, u32>::new(start).is_err());
+
+    let mut start = TypedMap::<u32, u64>::new(start).expect("failed to create typed map");
+
+    start
+        .update(&1, &42, MapFlags::empty())
+        .expect("failed to write");
+    assert_eq!(
+        start
+            .lookup(&1, MapFlags::empty())
+            .expect("failed to read map"),
+        Some(42)
+    );
+    assert_eq!(start.keys().collect::<Vec<_>>(), vec![1]);
+    assert_eq!(
+        start
+            .lookup_and_delete(&1)
+            .expect("failed to lookup and delete"),
+        Some(42)
+    );
+    assert_eq!(
+        start
+            .lookup(&1, MapFlags::empty())
+            .expect("failed to read map"),
+        None
+    );
+}
+
 #[test]
 fn test_object_map_lookup_flags() {
     bump_rlimit_mlock();
@@ -313,104 +433,1774 @@ fn test_object_map_key_iter_empty() {
 }
 
 #[test]
-fn test_object_map_pin() {
+fn test_object_map_batch() {
     bump_rlimit_mlock();
 
     let mut obj = get_test_object("runqslower.bpf.o");
-    let map = obj.map_mut("start").expect("failed to find map");
+    let start = obj.map_mut("start").expect("failed to find map");
 
-    let path = "/sys/fs/bpf/mymap";
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    for i in 0..10u32 {
+        keys.extend_from_slice(&i.to_ne_bytes());
+        values.extend_from_slice(&u64::from(i).to_ne_bytes());
+    }
 
-    // Unpinning a unpinned map should be an error
-    assert!(map.unpin(path).is_err());
-    assert!(!Path::new(path).exists());
+    // Mismatched number of keys and values should be rejected
+    assert!(start
+        .update_batch(&keys, &values[8..], &MapBatchOpts::default())
+        .is_err());
 
-    // Pin and unpin should be successful
-    map.pin(path).expect("failed to pin map");
-    assert!(Path::new(path).exists());
-    map.unpin(path).expect("failed to unpin map");
-    assert!(!Path::new(path).exists());
+    start
+        .update_batch(&keys, &values, &MapBatchOpts::default())
+        .expect("failed to batch update");
+
+    // Read back in chunks smaller than the number of entries to exercise the cursor
+    let mut cursor = MapBatchCursor::new();
+    let mut found = HashSet::new();
+    while !cursor.is_done() {
+        for (key, value) in start
+            .lookup_batch(&mut cursor, 3, &MapBatchOpts::default())
+            .expect("failed to batch lookup")
+        {
+            let mut k: u32 = 0;
+            plain::copy_from_bytes(&mut k, &key).expect("Wrong key size");
+            assert_eq!(value, u64::from(k).to_ne_bytes());
+            found.insert(k);
+        }
+    }
+    assert_eq!(found.len(), 10);
+
+    start
+        .delete_batch(&keys, &MapBatchOpts::default())
+        .expect("failed to batch delete");
+    assert_eq!(start.keys().count(), 0);
 }
 
 #[test]
-fn test_object_programs() {
+fn test_object_percpu_map_batch() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("percpu_map.bpf.o");
+    let map = obj.map_mut("percpu_map").expect("failed to find map");
+    let keys = 0u32.to_ne_bytes();
+
+    let ncpu = num_possible_cpus().expect("failed to get number of cpus");
+    let vals: Vec<Vec<u8>> = (0..ncpu)
+        .map(|i| (i as u32 + 1).to_ne_bytes().to_vec())
+        .collect();
+
+    // The non per-cpu variants should be rejected
+    assert!(map
+        .update_batch(&keys, &vals[0], &MapBatchOpts::default())
+        .is_err());
+    assert!(map
+        .lookup_batch(&mut MapBatchCursor::new(), 1, &MapBatchOpts::default())
+        .is_err());
+
+    map.update_batch_percpu(&keys, std::slice::from_ref(&vals), &MapBatchOpts::default())
+        .expect("failed to batch update");
+
+    let mut cursor = MapBatchCursor::new();
+    let mut found = Vec::new();
+    while !cursor.is_done() {
+        found.extend(
+            map.lookup_batch_percpu(&mut cursor, 1, &MapBatchOpts::default())
+                .expect("failed to batch lookup"),
+        );
+    }
+    assert_eq!(found, vec![(keys.to_vec(), vals)]);
+}
+
+#[test]
+fn test_object_map_iter() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+
+    let key1 = vec![1, 2, 3, 4];
+    let key2 = vec![1, 2, 3, 5];
+    let val1 = vec![1, 2, 3, 4, 5, 6, 7, 8];
+    let val2 = vec![8, 7, 6, 5, 4, 3, 2, 1];
+
+    start
+        .update(&key1, &val1, MapFlags::empty())
+        .expect("failed to write");
+    start
+        .update(&key2, &val2, MapFlags::empty())
+        .expect("failed to write");
+
+    let mut entries = start
+        .iter()
+        .collect::<libbpf_rs::Result<Vec<_>>>()
+        .expect("failed to iterate map");
+    entries.sort();
+    assert_eq!(entries, vec![(key1, val1), (key2, val2)]);
+
+    let start = TypedMap::<u32, u64>::new(start).expect("failed to create typed map");
+    assert_eq!(start.iter().count(), 2);
+}
+
+#[test]
+fn test_object_map_freeze() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+
+    start
+        .update(&[1, 2, 3, 4], &[1, 2, 3, 4, 5, 6, 7, 8], MapFlags::empty())
+        .expect("failed to write");
+    start.freeze().expect("failed to freeze map");
+
+    // Lookups still work but any modification is rejected
+    assert!(start
+        .lookup(&[1, 2, 3, 4], MapFlags::empty())
+        .expect("failed to read map")
+        .is_some());
+    assert!(start
+        .update(&[1, 2, 3, 5], &[1, 2, 3, 4, 5, 6, 7, 8], MapFlags::empty())
+        .is_err());
+    assert!(start.delete(&[1, 2, 3, 4]).is_err());
+}
+
+#[test]
+fn test_object_map_mmap() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+
+    // Only mmapable array maps can be mapped
+    let start = obj.map("start").expect("failed to find map");
+    assert!(start.mmap().is_err());
+
+    // libbpf creates .rodata as an mmapable array and freezes it after load
+    let rodata = obj
+        .maps_iter_mut()
+        .find(|m| m.name().ends_with(".rodata"))
+        .expect("failed to find .rodata map");
+    {
+        let mmap = rodata.mmap().expect("failed to mmap map");
+        // min_us, targ_pid, targ_tgid were all left at their defaults
+        assert!(mmap.len() >= 16);
+        assert!(mmap.iter().all(|b| *b == 0));
+    }
+    assert!(rodata.mmap_mut().is_err());
+}
+
+#[test]
+fn test_object_map_queue_ops_wrong_type() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+
+    assert!(start
+        .push(&[1, 2, 3, 4, 5, 6, 7, 8], MapFlags::ANY)
+        .is_err());
+    assert!(start.pop().is_err());
+    assert!(start.peek().is_err());
+    assert!(start.contains(&[1, 2, 3, 4]).is_err());
+}
+
+#[test]
+fn test_object_map_lookup_inner_wrong_type() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map("start").expect("failed to find map");
+
+    assert!(start.lookup_inner(&[0, 0, 0, 0], MapFlags::ANY).is_err());
+}
+
+#[test]
+fn test_object_map_set_max_entries() {
+    let obj_path = get_test_object_path("runqslower.bpf.o");
+    let mut builder = ObjectBuilder::default();
+    builder.debug(true);
+    let mut open_obj = builder.open_file(obj_path).expect("failed to open object");
+
+    let start = open_obj.map_mut("start").expect("failed to find map");
+    assert_ne!(start.max_entries(), 128);
+    start
+        .set_max_entries(128)
+        .expect("failed to set max_entries");
+    assert_eq!(start.max_entries(), 128);
+}
+
+#[test]
+fn test_object_map_flags_numa_node() {
+    let obj_path = get_test_object_path("runqslower.bpf.o");
+    let mut builder = ObjectBuilder::default();
+    builder.debug(true);
+    let mut open_obj = builder.open_file(obj_path).expect("failed to open object");
+
+    let start = open_obj.map_mut("start").expect("failed to find map");
+    let flags = start.map_flags() | libbpf_rs::libbpf_sys::BPF_F_NUMA_NODE;
+    start.set_map_flags(flags).expect("failed to set map flags");
+    assert_eq!(start.map_flags(), flags);
+    start.set_numa_node(0).expect("failed to set numa node");
+    assert_eq!(start.numa_node(), 0);
+}
+
+#[test]
+fn test_object_map_extra() {
+    let obj_path = get_test_object_path("runqslower.bpf.o");
+    let mut builder = ObjectBuilder::default();
+    builder.debug(true);
+    let mut open_obj = builder.open_file(obj_path).expect("failed to open object");
+
+    let start = open_obj.map_mut("start").expect("failed to find map");
+    assert_eq!(start.map_extra(), 0);
+    start.set_map_extra(3).expect("failed to set map_extra");
+    assert_eq!(start.map_extra(), 3);
+}
+
+#[test]
+fn test_map_handle_create() {
     bump_rlimit_mlock();
 
-    let obj = get_test_object("runqslower.bpf.o");
-    obj.prog("handle__sched_wakeup")
-        .expect("failed to find program");
-    obj.prog("handle__sched_wakeup_new")
-        .expect("failed to find program");
-    obj.prog("handle__sched_switch")
+    let mut map = MapHandle::create(
+        MapType::Hash,
+        "test_create",
+        4,
+        8,
+        16,
+        &MapCreateOpts::default(),
+    )
+    .expect("failed to create map");
+    assert_eq!(map.name(), "test_create");
+    assert_eq!(map.map_type(), MapType::Hash);
+    assert_eq!(map.key_size(), 4);
+    assert_eq!(map.value_size(), 8);
+
+    let key = 1u32.to_ne_bytes();
+    let val = 2u64.to_ne_bytes();
+    map.update(&key, &val, MapFlags::empty())
+        .expect("failed to write");
+    assert_eq!(
+        map.lookup(&key, MapFlags::empty())
+            .expect("failed to read map")
+            .expect("failed to find key"),
+        val
+    );
+
+    assert!(MapHandle::create(
+        MapType::Unknown,
+        "test_create",
+        4,
+        8,
+        16,
+        &MapCreateOpts::default()
+    )
+    .is_err());
+}
+
+#[test]
+fn test_map_handle_update_sock() {
+    bump_rlimit_mlock();
+
+    let mut map = MapHandle::create(
+        MapType::Sockmap,
+        "test_sockmap",
+        4,
+        4,
+        1,
+        &MapCreateOpts::default(),
+    )
+    .expect("failed to create map");
+
+    let sock = UdpSocket::bind("127.0.0.1:0").expect("failed to bind socket");
+    let key = 0u32.to_ne_bytes();
+    map.update_sock(&key, &sock, MapFlags::ANY)
+        .expect("failed to insert socket");
+    map.delete(&key).expect("failed to delete socket");
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+    assert!(start.update_sock(&key, &sock, MapFlags::ANY).is_err());
+}
+
+#[test]
+fn test_map_handle_set_prog() {
+    bump_rlimit_mlock();
+
+    let mut map = MapHandle::create(
+        MapType::ProgArray,
+        "test_progarray",
+        4,
+        4,
+        2,
+        &MapCreateOpts::default(),
+    )
+    .expect("failed to create map");
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog("handle__sched_wakeup")
+        .expect("failed to find program");
+    map.set_prog(1, prog).expect("failed to set program");
+    assert!(map.set_prog(2, prog).is_err());
+    map.delete(&1u32.to_ne_bytes())
+        .expect("failed to clear slot");
+
+    let mut hash = MapHandle::create(
+        MapType::Hash,
+        "test_hash",
+        4,
+        4,
+        2,
+        &MapCreateOpts::default(),
+    )
+    .expect("failed to create map");
+    assert!(hash.set_prog(0, prog).is_err());
+}
+
+#[test]
+fn test_map_handle_set_cgroup() {
+    bump_rlimit_mlock();
+
+    let mut map = MapHandle::create(
+        MapType::CgroupArray,
+        "test_cgrouparr",
+        4,
+        4,
+        1,
+        &MapCreateOpts::default(),
+    )
+    .expect("failed to create map");
+
+    map.set_cgroup_path(0, "/sys/fs/cgroup")
+        .expect("failed to set cgroup");
+    let cgroup = fs::File::open("/sys/fs/cgroup").expect("failed to open cgroup");
+    map.set_cgroup(0, &cgroup).expect("failed to set cgroup");
+    assert!(map.set_cgroup(1, &cgroup).is_err());
+    assert!(map.set_cgroup_path(0, "/does/not/exist").is_err());
+}
+
+#[test]
+fn test_map_handle_update_devmap_cpumap() {
+    bump_rlimit_mlock();
+
+    let mut devmap = MapHandle::create(
+        MapType::Devmap,
+        "test_devmap",
+        4,
+        8,
+        1,
+        &MapCreateOpts::default(),
+    )
+    .expect("failed to create map");
+    // Interface index 1 is the loopback device
+    devmap
+        .update_devmap(0, 1, None, MapFlags::ANY)
+        .expect("failed to update devmap");
+    assert!(devmap.update_cpumap(0, 192, None, MapFlags::ANY).is_err());
+
+    let mut cpumap = MapHandle::create(
+        MapType::Cpumap,
+        "test_cpumap",
+        4,
+        4,
+        1,
+        &MapCreateOpts::default(),
+    )
+    .expect("failed to create map");
+    cpumap
+        .update_cpumap(0, 192, None, MapFlags::ANY)
+        .expect("failed to update cpumap");
+    assert!(cpumap.update_devmap(0, 1, None, MapFlags::ANY).is_err());
+}
+
+#[test]
+fn test_map_handle_lpm_trie() {
+    bump_rlimit_mlock();
+
+    let mut map = MapHandle::create(
+        MapType::LpmTrie,
+        "test_lpm",
+        8,
+        4,
+        16,
+        &MapCreateOpts {
+            map_flags: libbpf_rs::libbpf_sys::BPF_F_NO_PREALLOC,
+            ..Default::default()
+        },
+    )
+    .expect("failed to create map");
+
+    let net = "10.0.0.0".parse().unwrap();
+    let subnet = "10.1.0.0".parse().unwrap();
+    map.update(
+        &lpm_trie_key(net, 8).unwrap(),
+        &1u32.to_ne_bytes(),
+        MapFlags::ANY,
+    )
+    .expect("failed to write");
+    map.update(
+        &lpm_trie_key(subnet, 16).unwrap(),
+        &2u32.to_ne_bytes(),
+        MapFlags::ANY,
+    )
+    .expect("failed to write");
+
+    let lookup = |addr: &str| {
+        map.lookup_lpm(addr.parse().unwrap(), MapFlags::ANY)
+            .expect("failed to read map")
+    };
+    assert_eq!(lookup("10.2.3.4"), Some(1u32.to_ne_bytes().to_vec()));
+    assert_eq!(lookup("10.1.3.4"), Some(2u32.to_ne_bytes().to_vec()));
+    assert_eq!(lookup("192.168.0.1"), None);
+}
+
+#[test]
+fn test_object_map_clear() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+
+    // Clearing an empty map is a no-op
+    start.clear().expect("failed to clear map");
+
+    for key in 0u32..3 {
+        start
+            .update(&key.to_ne_bytes(), &[0; 8], MapFlags::empty())
+            .expect("failed to write");
+    }
+    start.clear().expect("failed to clear map");
+    assert_eq!(start.keys().count(), 0);
+}
+
+#[test]
+fn test_map_handle_lookup_stack() {
+    bump_rlimit_mlock();
+
+    let map = MapHandle::create(
+        MapType::StackTrace,
+        "test_stacks",
+        4,
+        8 * 127,
+        16,
+        &MapCreateOpts::default(),
+    )
+    .expect("failed to create map");
+    assert_eq!(map.lookup_stack(0).expect("failed to read map"), None);
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map("start").expect("failed to find map");
+    assert!(start.lookup_stack(0).is_err());
+}
+
+#[test]
+fn test_object_map_dump_pretty() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+
+    assert_eq!(start.dump_pretty().expect("failed to dump map"), "");
+
+    start
+        .update(
+            &1u32.to_ne_bytes(),
+            &4242u64.to_ne_bytes(),
+            MapFlags::empty(),
+        )
+        .expect("failed to write");
+    let dump = start.dump_pretty().expect("failed to dump map");
+    assert!(dump.starts_with("key: "));
+    assert!(dump.contains("value: "));
+    assert!(dump.contains("4242"));
+}
+
+#[test]
+fn test_object_map_locked_no_spin_lock() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+
+    // The values of `start` do not contain a `struct bpf_spin_lock`
+    let key = 1u32.to_ne_bytes();
+    let val = 2u64.to_ne_bytes();
+    assert!(start.update_locked(&key, &val, MapFlags::ANY).is_err());
+    start
+        .update(&key, &val, MapFlags::ANY)
+        .expect("failed to write");
+    assert!(start.lookup_locked(&key).is_err());
+}
+
+#[test]
+fn test_map_handle_arena() {
+    bump_rlimit_mlock();
+
+    let mut map = MapHandle::create(
+        MapType::Arena,
+        "test_arena",
+        0,
+        0,
+        2,
+        &MapCreateOpts {
+            map_flags: libbpf_rs::libbpf_sys::BPF_F_MMAPABLE,
+            ..Default::default()
+        },
+    )
+    .expect("failed to create map");
+
+    let mut arena = map.arena().expect("failed to map arena");
+    assert!(arena.len() >= 2 * 4096);
+    arena[..4].copy_from_slice(&[1, 2, 3, 4]);
+    assert_eq!(&arena[..4], &[1, 2, 3, 4]);
+
+    let addr = arena.offset_to_addr(16).expect("offset outside of arena");
+    assert_eq!(arena.addr_to_offset(addr), Some(16));
+}
+
+#[test]
+fn test_map_handle_arena_fixed_addr() {
+    bump_rlimit_mlock();
+
+    // Pick an address that is unlikely to be used by anything else
+    let map_extra = 1u64 << 40;
+    let mut map = MapHandle::create(
+        MapType::Arena,
+        "test_arena",
+        0,
+        0,
+        2,
+        &MapCreateOpts {
+            map_flags: libbpf_rs::libbpf_sys::BPF_F_MMAPABLE,
+            map_extra,
+            ..Default::default()
+        },
+    )
+    .expect("failed to create map");
+    let mut clone = map.try_clone().expect("failed to clone handle");
+
+    let mut arena = map.arena().expect("failed to map arena");
+    assert_eq!(arena.base_addr(), map_extra);
+    arena[..4].copy_from_slice(&[1, 2, 3, 4]);
+
+    // The region is taken already and must not be replaced
+    match clone.arena() {
+        Err(libbpf_rs::Error::System(errno)) => assert_eq!(errno, libc::EEXIST),
+        _ => panic!("arena mapped over an existing mapping"),
+    }
+    assert_eq!(&arena[..4], &[1, 2, 3, 4]);
+}
+
+#[test]
+fn test_map_handle_try_clone() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map("start").expect("failed to find map");
+
+    let mut handle = MapHandle::try_from(start).expect("failed to create handle");
+    assert_ne!(handle.fd().as_raw_fd(), start.fd().as_raw_fd());
+    assert_eq!(handle.name(), "start");
+    let clone = handle.try_clone().expect("failed to clone handle");
+    assert_ne!(clone.fd().as_raw_fd(), handle.fd().as_raw_fd());
+    drop(obj);
+
+    let key = 1u32.to_ne_bytes();
+    let val = 2u64.to_ne_bytes();
+    handle
+        .update(&key, &val, MapFlags::empty())
+        .expect("failed to write");
+    assert_eq!(
+        clone
+            .lookup(&key, MapFlags::empty())
+            .expect("failed to read map")
+            .expect("failed to find key"),
+        val
+    );
+
+    // Clones can be moved to and used from other threads
+    let mut clone = handle.try_clone().expect("failed to clone handle");
+    let key2 = 2u32.to_ne_bytes();
+    std::thread::spawn(move || {
+        clone
+            .update(&key2, &val, MapFlags::empty())
+            .expect("failed to write")
+    })
+    .join()
+    .expect("thread panicked");
+    assert!(handle
+        .lookup(&key2, MapFlags::empty())
+        .expect("failed to read map")
+        .is_some());
+}
+
+#[test]
+fn test_object_map_attach_struct_ops_wrong_type() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map("start").expect("failed to find map");
+    assert!(start.attach_struct_ops().is_err());
+}
+
+#[test]
+fn test_object_map_pin() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let map = obj.map_mut("start").expect("failed to find map");
+
+    let path = "/sys/fs/bpf/mymap";
+
+    // Unpinning a unpinned map should be an error
+    assert!(map.unpin(path).is_err());
+    assert!(!Path::new(path).exists());
+
+    // Pin and unpin should be successful
+    map.pin(path).expect("failed to pin map");
+    assert!(Path::new(path).exists());
+    map.unpin(path).expect("failed to unpin map");
+    assert!(!Path::new(path).exists());
+}
+
+#[test]
+fn test_object_map_pin_path() {
+    bump_rlimit_mlock();
+
+    let path = "/sys/fs/bpf/mymap_pin_path";
+
+    let obj_path = get_test_object_path("runqslower.bpf.o");
+    let mut builder = ObjectBuilder::default();
+    builder.debug(true);
+    builder
+        .pin_root_path("/sys/fs/bpf")
+        .expect("failed to set pin root path");
+    let mut open_obj = builder.open_file(obj_path).expect("failed to open object");
+    open_obj
+        .map_mut("start")
+        .expect("failed to find map")
+        .set_pin_path(path)
+        .expect("failed to set pin path");
+
+    let mut obj = open_obj.load().expect("failed to load object");
+    let map = obj.map_mut("start").expect("failed to find map");
+    assert!(map.is_pinned());
+    assert_eq!(map.pin_path(), Some(PathBuf::from(path)));
+    assert!(Path::new(path).exists());
+
+    map.unpin(path).expect("failed to unpin map");
+    assert!(!Path::new(path).exists());
+}
+
+#[test]
+fn test_map_handle_from_pinned_path() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let map = obj.map_mut("start").expect("failed to find map");
+
+    let path = "/sys/fs/bpf/mymap_from_pinned_path";
+    assert!(MapHandle::from_pinned_path(path).is_err());
+    map.pin(path).expect("failed to pin map");
+
+    let key = 1u32.to_ne_bytes();
+    let val = 2u64.to_ne_bytes();
+    map.update(&key, &val, MapFlags::empty())
+        .expect("failed to write");
+
+    let handle = MapHandle::from_pinned_path(path).expect("failed to open pinned map");
+    assert_eq!(handle.name(), "start");
+    assert_eq!(handle.map_type(), MapType::Hash);
+    assert_eq!(handle.key_size(), map.key_size());
+    assert_eq!(handle.value_size(), map.value_size());
+    assert_eq!(
+        handle
+            .lookup(&key, MapFlags::empty())
+            .expect("failed to read map")
+            .expect("failed to find key"),
+        val
+    );
+
+    map.unpin(path).expect("failed to unpin map");
+}
+
+#[test]
+fn test_map_handle_from_map_id() {
+    bump_rlimit_mlock();
+
+    let _obj = get_test_object("runqslower.bpf.o");
+
+    let info = MapInfoIter::default()
+        .find(|info| info.name == "start")
+        .expect("failed to find map info");
+    let handle = MapHandle::from_map_id(info.id).expect("failed to open map by id");
+    assert_eq!(handle.name(), "start");
+    assert_eq!(handle.key_size(), info.key_size);
+    assert_eq!(handle.value_size(), info.value_size);
+
+    assert!(MapHandle::from_map_id(u32::MAX).is_err());
+}
+
+#[test]
+fn test_object_map_info() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let map = obj.map("start").expect("failed to find map");
+
+    let info = map.info().expect("failed to get map info");
+    assert_eq!(info.name, "start");
+    assert_eq!(info.ty, MapType::Hash);
+    assert_eq!(info.key_size, map.key_size());
+    assert_eq!(info.value_size, map.value_size());
+    assert!(info.max_entries > 0);
+    assert!(info.id > 0);
+
+    let handle = MapHandle::from_map_id(info.id).expect("failed to open map by id");
+    assert_eq!(handle.info().expect("failed to get map info").id, info.id);
+}
+
+#[test]
+fn test_object_programs() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    obj.prog("handle__sched_wakeup")
+        .expect("failed to find program");
+    obj.prog("handle__sched_wakeup_new")
+        .expect("failed to find program");
+    obj.prog("handle__sched_switch")
+        .expect("failed to find program");
+    assert!(obj.prog("asdf").is_none());
+}
+
+#[test]
+fn test_object_programs_iter_mut() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    assert!(obj.progs_iter().count() == 3);
+}
+
+#[test]
+fn test_object_program_pin() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let path = "/sys/fs/bpf/myprog";
+
+    // Unpinning a unpinned prog should be an error
+    assert!(prog.unpin(path).is_err());
+    assert!(!Path::new(path).exists());
+
+    // Pin should be successful
+    prog.pin(path).expect("failed to pin prog");
+    assert!(Path::new(path).exists());
+
+    // Backup cleanup method in case test errors
+    defer! {
+        let _ = fs::remove_file(path);
+    }
+
+    // Unpin should be successful
+    prog.unpin(path).expect("failed to unpin prog");
+    assert!(!Path::new(path).exists());
+}
+
+#[test]
+fn test_program_handle_from_pinned_path() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let path = "/sys/fs/bpf/myprog_from_pinned_path";
+    assert!(ProgramHandle::from_pinned_path(path).is_err());
+    prog.pin(path).expect("failed to pin prog");
+
+    // Backup cleanup method in case test errors
+    defer! {
+        let _ = fs::remove_file(path);
+    }
+
+    let handle = ProgramHandle::from_pinned_path(path).expect("failed to open pinned prog");
+    // The kernel truncates names to 15 bytes
+    assert_eq!(handle.name(), "handle__sched_w");
+    assert!(matches!(handle.prog_type(), ProgramType::Tracing));
+
+    let info = handle.info().expect("failed to query prog info");
+    assert_eq!(info.name, "handle__sched_w");
+    let by_id = ProgramHandle::from_prog_id(info.id).expect("failed to open prog by id");
+    assert_eq!(by_id.name(), handle.name());
+    assert_eq!(
+        by_id.info().expect("failed to query prog info").tag,
+        info.tag
+    );
+
+    let path2 = "/sys/fs/bpf/myprog_from_pinned_path2";
+    handle.pin(path2).expect("failed to pin prog handle");
+    assert!(Path::new(path2).exists());
+    handle.unpin(path2).expect("failed to unpin prog handle");
+    assert!(!Path::new(path2).exists());
+
+    prog.unpin(path).expect("failed to unpin prog");
+}
+
+#[test]
+fn test_object_set_attach_target() {
+    let obj_path = get_test_object_path("runqslower.bpf.o");
+    let mut open_obj = ObjectBuilder::default()
+        .open_file(obj_path)
+        .expect("failed to open object");
+    let prog = open_obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    assert!(matches!(
+        prog.set_attach_target(None, Some("bad\0name")),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    // Retarget the tp_btf program from sched_wakeup
+    prog.set_attach_target(None, Some("sched_switch"))
+        .expect("failed to set attach target");
+    assert!(prog
+        .set_attach_target(None, Some("libbpf_rs_no_such_function"))
+        .is_err());
+}
+
+#[test]
+fn test_object_set_prog_and_attach_type() {
+    let obj_path = get_test_object_path("runqslower.bpf.o");
+    let mut open_obj = ObjectBuilder::default()
+        .open_file(obj_path)
+        .expect("failed to open object");
+    let prog = open_obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    assert!(matches!(prog.prog_type(), ProgramType::Tracing));
+    assert!(matches!(prog.attach_type(), ProgramAttachType::TraceRawTp));
+
+    prog.set_prog_type(ProgramType::CgroupSkb);
+    prog.set_attach_type(ProgramAttachType::CgroupInetEgress);
+    assert!(matches!(prog.prog_type(), ProgramType::CgroupSkb));
+    assert!(matches!(
+        prog.attach_type(),
+        ProgramAttachType::CgroupInetEgress
+    ));
+}
+
+#[test]
+fn test_object_program_insns() {
+    bump_rlimit_mlock();
+
+    let obj_path = get_test_object_path("runqslower.bpf.o");
+    let mut open_obj = ObjectBuilder::default()
+        .open_file(obj_path)
+        .expect("failed to open object");
+    let prog = open_obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let insns = prog.insns();
+    assert_eq!(insns.len(), prog.insn_cnt());
+    // BPF_JMP | BPF_EXIT
+    assert_eq!(insns.last().expect("no instructions").code, 0x95);
+
+    let obj = open_obj.load().expect("failed to load object");
+    let prog = obj
+        .prog("handle__sched_wakeup")
+        .expect("failed to find program");
+    let xlated = prog
+        .xlated_insns()
+        .expect("failed to get xlated instructions");
+    assert!(!xlated.is_empty());
+}
+
+#[test]
+fn test_object_set_autoload() {
+    bump_rlimit_mlock();
+
+    let obj_path = get_test_object_path("runqslower.bpf.o");
+    let mut open_obj = ObjectBuilder::default()
+        .open_file(obj_path)
+        .expect("failed to open object");
+    let prog = open_obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    assert!(prog.autoload());
+    prog.set_autoload(false)
+        .expect("failed to disable autoload");
+    assert!(!prog.autoload());
+
+    let obj = open_obj.load().expect("failed to load object");
+    assert!(obj.prog("handle__sched_wakeup").is_none());
+    assert!(obj.prog("handle__sched_switch").is_some());
+}
+
+#[test]
+fn test_object_log_buf() {
+    bump_rlimit_mlock();
+
+    let obj_path = get_test_object_path("runqslower.bpf.o");
+    let mut open_obj = ObjectBuilder::default()
+        .open_file(obj_path)
+        .expect("failed to open object");
+    let prog = open_obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    assert_eq!(prog.log_level(), 0);
+    prog.set_log_level(1 | 4).expect("failed to set log level");
+    assert_eq!(prog.log_level(), 1 | 4);
+    prog.set_log_buf_size(1 << 20)
+        .expect("failed to set log buffer");
+    assert_eq!(prog.log_buf(), Some(""));
+
+    let obj = open_obj.load().expect("failed to load object");
+    let log = obj
+        .prog("handle__sched_wakeup")
+        .expect("failed to find program")
+        .log_buf()
+        .expect("no verifier log");
+    // Statistics are logged at level 4
+    assert!(log.contains("processed"));
+    assert_eq!(
+        obj.prog("handle__sched_switch")
+            .expect("failed to find program")
+            .log_buf(),
+        None
+    );
+}
+
+#[test]
+fn test_object_load_or_recover() {
+    bump_rlimit_mlock();
+
+    let obj_path = get_test_object_path("runqslower.bpf.o");
+    let mut open_obj = ObjectBuilder::default()
+        .open_file(obj_path)
+        .expect("failed to open object");
+    let prog = open_obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+    // Too small for the log, so loading fails
+    prog.set_log_level(2).expect("failed to set log level");
+    prog.set_log_buf_size(64).expect("failed to set log buffer");
+
+    let (err, open_obj) = match open_obj.load_or_recover() {
+        Ok(_) => panic!("loading succeeded"),
+        Err(res) => res,
+    };
+    assert!(matches!(err, libbpf_rs::Error::System(_)));
+    let open_obj = open_obj.expect("no object to recover");
+    let log = open_obj
+        .prog("handle__sched_wakeup")
+        .expect("failed to find program")
+        .log_buf()
+        .expect("no verifier log");
+    assert!(!log.is_empty());
+}
+
+#[test]
+fn test_object_attach_cgroup_with_opts() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let other = obj
+        .prog("handle__sched_switch")
+        .expect("failed to find program")
+        .fd()
+        .try_clone_to_owned()
+        .expect("failed to dup fd");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+    let cgroup = fs::File::open("/sys/fs/cgroup").expect("failed to open cgroup");
+
+    let opts = CgroupAttachOpts {
+        flags: CgroupAttachFlags::ALLOW_MULTI | CgroupAttachFlags::REPLACE,
+        replace_prog_fd: None,
+    };
+    assert!(matches!(
+        prog.attach_cgroup_with_opts(cgroup.as_fd(), &opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    let opts = CgroupAttachOpts {
+        flags: CgroupAttachFlags::ALLOW_MULTI,
+        replace_prog_fd: Some(other.as_fd()),
+    };
+    assert!(matches!(
+        prog.attach_cgroup_with_opts(cgroup.as_fd(), &opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+
+    // Not a cgroup program
+    let opts = CgroupAttachOpts {
+        flags: CgroupAttachFlags::ALLOW_MULTI,
+        ..Default::default()
+    };
+    assert!(prog.attach_cgroup_with_opts(cgroup.as_fd(), &opts).is_err());
+    assert!(prog.attach_cgroup(cgroup.as_fd()).is_err());
+    assert!(prog.detach_cgroup(cgroup.as_fd()).is_err());
+}
+
+#[test]
+fn test_object_attach_lsm_cgroup_wrong_type() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let cgroup = fs::File::open("/sys/fs/cgroup").expect("failed to open cgroup");
+    assert!(matches!(
+        prog.attach_lsm_cgroup(cgroup.as_fd()),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_object_attach_cgroup_sysctl_sockopt_wrong_type() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let cgroup = fs::File::open("/sys/fs/cgroup").expect("failed to open cgroup");
+    assert!(matches!(
+        prog.attach_cgroup_sysctl(cgroup.as_fd()),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    assert!(matches!(
+        prog.attach_cgroup_getsockopt(cgroup.as_fd()),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    assert!(matches!(
+        prog.attach_cgroup_setsockopt(cgroup.as_fd()),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    assert!(matches!(
+        prog.attach_sockops(cgroup.as_fd()),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_object_attach_netns_wrong_type() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let netns = fs::File::open("/proc/self/ns/net").expect("failed to open netns");
+    assert!(matches!(
+        prog.attach_netns(netns.as_fd()),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_object_flow_dissector() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let netns = fs::File::open("/proc/self/ns/net").expect("failed to open netns");
+    assert!(matches!(
+        prog.attach_flow_dissector(netns.as_fd()),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    // Not attached
+    assert!(prog.detach_flow_dissector(netns.as_fd()).is_err());
+    assert_eq!(
+        Program::query_flow_dissector(netns.as_fd()).expect("failed to query flow dissector"),
+        None
+    );
+}
+
+#[test]
+fn test_object_attach_sockmap_wrong_type() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map("start").expect("failed to find map");
+    let prog = obj
+        .prog("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    // Not a sockmap
+    assert!(matches!(
+        prog.attach_sockmap_with_type(start, ProgramAttachType::SkMsgVerdict),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    assert!(matches!(
+        prog.detach_sockmap(start, ProgramAttachType::SkSkbStreamParser),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_object_attach_perf_event_per_cpu() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    // Not a perf event program
+    assert!(prog
+        .attach_perf_event_per_cpu(
+            libbpf_rs::libbpf_sys::PERF_TYPE_SOFTWARE,
+            libbpf_rs::libbpf_sys::PERF_COUNT_SW_CPU_CLOCK as u64,
+            PerfSample::Frequency(99),
+        )
+        .is_err());
+}
+
+#[test]
+fn test_object_attach_netfilter_wrong_type() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    // NFPROTO_IPV4, NF_INET_LOCAL_IN
+    assert!(prog
+        .attach_netfilter(2, 1, 0, NetfilterFlags::IP_DEFRAG)
+        .is_err());
+}
+
+#[test]
+fn test_object_test_run_unsupported() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    // tp_btf programs cannot be test run
+    let opts = TestRunOpts {
+        data_in: &[0; 64],
+        repeat: 10,
+        ..Default::default()
+    };
+    assert!(prog.test_run(&opts).is_err());
+
+    let opts = TestRunOpts {
+        ctx_in: &[0; 8],
+        ctx_out_size: Some(8),
+        flags: TestRunFlags::SKB_CHECKSUM_COMPLETE,
+        cpu: Some(0),
+        ..Default::default()
+    };
+    assert!(prog.test_run(&opts).is_err());
+
+    let opts = TestRunOpts {
+        data_in: &[0; 64],
+        data_out_size: Some(64),
+        flags: TestRunFlags::XDP_LIVE_FRAMES,
+        ..Default::default()
+    };
+    assert!(matches!(
+        prog.test_run(&opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_object_attach_freplace_wrong_type() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let target = obj
+        .prog("handle__sched_switch")
+        .expect("failed to find program")
+        .fd()
+        .try_clone_to_owned()
+        .expect("failed to dup fd");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    assert!(matches!(
+        prog.attach_freplace(target.as_fd(), "func"),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_object_attach_xdp_with_flags() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    assert!(matches!(
+        prog.attach_xdp_with_flags(1, XdpFlags::REPLACE),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    // Not an XDP program
+    assert!(prog
+        .attach_xdp_with_flags(1, XdpFlags::SKB_MODE | XdpFlags::UPDATE_IF_NOEXIST)
+        .is_err());
+}
+
+#[test]
+fn test_tc_hook() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    // Loopback
+    let mut builder = TcHookBuilder::new(prog.fd());
+    builder.ifindex(1).handle(1).priority(1);
+    let mut ingress = builder.hook(TcAttachPoint::Ingress);
+    assert_eq!(ingress.ifindex(), 1);
+    assert_eq!(ingress.handle(), 1);
+    assert_eq!(ingress.priority(), 1);
+
+    ingress.create().expect("failed to create hook");
+    let mut cleanup = ingress;
+    defer! {
+        let _ = cleanup.destroy();
+    }
+    // Creating the qdisc twice is fine
+    ingress.create().expect("failed to create hook again");
+
+    // Not a tc program
+    assert!(ingress.attach().is_err());
+    assert!(ingress.query().is_err());
+    assert!(ingress.detach().is_err());
+}
+
+#[test]
+fn test_object_attach_tcx() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let anchor = obj
+        .prog("handle__sched_switch")
+        .expect("failed to find program")
+        .fd()
+        .try_clone_to_owned()
+        .expect("failed to dup fd");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let opts = TcxOpts {
+        order: AttachOrder::Before(AttachAnchor::ProgFd(anchor.as_fd())),
+        ..Default::default()
+    };
+    // Not a tcx program
+    assert!(prog.attach_tcx(1, &opts).is_err());
+}
+
+#[test]
+fn test_object_attach_netkit_wrong_type() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    assert!(matches!(
+        prog.attach_netkit(1, &NetkitOpts::default()),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_object_kprobe_with_opts() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let opts = KprobeOpts {
+        offset: 0,
+        retprobe: false,
+        cookie: 42,
+        ..Default::default()
+    };
+    assert!(matches!(
+        prog.attach_kprobe_with_opts("bad\0name", &opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    // No such kernel function
+    assert!(prog
+        .attach_kprobe_with_opts("libbpf_rs_no_such_function", &opts)
+        .is_err());
+    assert!(prog.attach_kretprobe("libbpf_rs_no_such_function").is_err());
+
+    // maxactive only applies to kretprobes
+    let maxactive_opts = KprobeOpts {
+        maxactive: 64,
+        ..Default::default()
+    };
+    assert!(matches!(
+        prog.attach_kprobe_with_opts("libbpf_rs_no_such_function", &maxactive_opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    let maxactive_opts = KprobeOpts {
+        retprobe: true,
+        ..maxactive_opts
+    };
+    assert!(matches!(
+        prog.attach_kprobe_with_opts("bad\0name", &maxactive_opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    assert!(prog
+        .attach_kprobe_with_opts("libbpf_rs_no_such_function", &maxactive_opts)
+        .is_err());
+    assert!(prog
+        .attach_ksyscall(false, "libbpf_rs_no_such_syscall")
+        .is_err());
+}
+
+#[test]
+fn test_object_kprobe_multi() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let opts = KprobeMultiOpts::default();
+    assert!(matches!(
+        prog.attach_kprobe_multi(&opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    let opts = KprobeMultiOpts {
+        symbols: vec!["first".to_string(), "second".to_string()],
+        cookies: vec![1],
+        ..Default::default()
+    };
+    assert!(matches!(
+        prog.attach_kprobe_multi(&opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    // Nothing matches
+    assert!(prog
+        .attach_kprobe_multi_pattern(false, "libbpf_rs_no_such_function_*")
+        .is_err());
+}
+
+#[test]
+fn test_object_raw_tracepoint_with_opts() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let opts = RawTracepointOpts { cookie: 42 };
+    assert!(matches!(
+        prog.attach_raw_tracepoint_with_opts("libbpf_rs_no_such_tracepoint", &opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    assert!(matches!(
+        prog.attach_raw_tracepoint("libbpf_rs_no_such_tracepoint"),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_object_tracepoint_with_opts() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("ringbuf.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sys_enter_getpid")
+        .expect("failed to find program");
+
+    let opts = TracepointOpts {
+        cookie: 42,
+        ..Default::default()
+    };
+    let _link = prog
+        .attach_tracepoint_with_opts("syscalls", "sys_enter_getpid", &opts)
+        .expect("failed to attach prog");
+    assert!(prog
+        .attach_tracepoint_with_opts("syscalls", "libbpf_rs_no_such_tracepoint", &opts)
+        .is_err());
+}
+
+#[test]
+fn test_object_tracepoint_attach_mode() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("ringbuf.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sys_enter_getpid")
+        .expect("failed to find program");
+
+    let opts = TracepointOpts {
+        attach_mode: ProbeAttachMode::Link,
+        ..Default::default()
+    };
+    let link = prog
+        .attach_tracepoint_with_opts("syscalls", "sys_enter_getpid", &opts)
+        .expect("failed to attach prog");
+    assert!(matches!(
+        link.info().expect("failed to query link info").info,
+        LinkTypeInfo::PerfEvent
+    ));
+
+    let opts = TracepointOpts {
+        attach_mode: ProbeAttachMode::Perf,
+        ..Default::default()
+    };
+    let link = prog
+        .attach_tracepoint_with_opts("syscalls", "sys_enter_getpid", &opts)
+        .expect("failed to attach prog");
+    // Attached with the ioctl, so there is no BPF link to query
+    assert!(link.info().is_err());
+    assert!(matches!(
+        prog.attach_tracepoint_with_opts("sys\0calls", "sys_enter_getpid", &opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    assert!(prog
+        .attach_tracepoint_with_opts("syscalls", "libbpf_rs_no_such_tracepoint", &opts)
+        .is_err());
+}
+
+#[test]
+fn test_object_uprobe_symbol() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let binary = std::env::current_exe().expect("failed to find test binary");
+    // The offset of a symbol that does not exist cannot be resolved
+    assert!(prog
+        .attach_uprobe_symbol(false, -1, &binary, "libbpf_rs_no_such_symbol", None)
+        .is_err());
+    assert!(prog
+        .attach_uprobe_symbol(false, -1, &binary, "main", Some("NO_SUCH_VERSION"))
+        .is_err());
+
+    let opts = UprobeOpts {
+        cookie: 42,
+        func_name: Some("bad\0name".to_string()),
+        ..Default::default()
+    };
+    assert!(matches!(
+        prog.attach_uprobe_with_opts(-1, &binary, 0, &opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    assert!(prog
+        .attach_uretprobe(-1, "/libbpf_rs/no/such/binary", 0)
+        .is_err());
+}
+
+#[test]
+fn test_object_uprobe_multi() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let binary = std::env::current_exe().expect("failed to find test binary");
+    let opts = UprobeMultiOpts {
+        symbols: vec!["main".to_string()],
+        offsets: vec![0],
+        ..Default::default()
+    };
+    assert!(matches!(
+        prog.attach_uprobe_multi(-1, &binary, &opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    let opts = UprobeMultiOpts {
+        offsets: vec![0, 1],
+        cookies: vec![1, 2, 3],
+        ..Default::default()
+    };
+    assert!(matches!(
+        prog.attach_uprobe_multi(-1, &binary, &opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    // Nothing matches
+    assert!(prog
+        .attach_uprobe_multi_pattern(-1, &binary, false, "libbpf_rs_no_such_symbol_*")
+        .is_err());
+}
+
+#[test]
+fn test_object_usdt() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    // The test binary has no USDT probes
+    let binary = std::env::current_exe().expect("failed to find test binary");
+    assert!(prog
+        .attach_usdt(-1, &binary, "libbpf_rs", "no_such_probe")
+        .is_err());
+    let opts = UsdtOpts { cookie: 42 };
+    assert!(matches!(
+        prog.attach_usdt_with_opts(-1, &binary, "bad\0provider", "probe", &opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_usdt_probes() {
+    let binary = std::env::current_exe().expect("failed to find test binary");
+    let probes = usdt_probes(&binary).expect("failed to list probes");
+    assert!(probes.is_empty());
+
+    assert!(usdt_probes("/libbpf_rs/no/such/binary").is_err());
+}
+
+#[test]
+fn test_object_link_pin() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+    let mut link = prog.attach().expect("failed to attach prog");
+
+    let path = "/sys/fs/bpf/mylink";
+
+    // Unpinning a unpinned prog should be an error
+    assert!(link.unpin().is_err());
+    assert!(!Path::new(path).exists());
+    assert_eq!(link.pin_path(), None);
+
+    // Pin should be successful
+    link.pin(path).expect("failed to pin prog");
+    assert!(Path::new(path).exists());
+    assert_eq!(link.pin_path(), Some(PathBuf::from(path)));
+
+    // Backup cleanup method in case test errors
+    defer! {
+        let _ = fs::remove_file(path);
+    }
+
+    // Unpin should be successful
+    link.unpin().expect("failed to unpin prog");
+    assert!(!Path::new(path).exists());
+    assert_eq!(link.pin_path(), None);
+}
+
+#[test]
+fn test_object_link_open() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let path = "/sys/fs/bpf/mylink_open";
+    assert!(Link::open(path).is_err());
+
+    let mut link = prog.attach().expect("failed to attach prog");
+    link.pin(path).expect("failed to pin link");
+
+    // Backup cleanup method in case test errors
+    defer! {
+        let _ = fs::remove_file(path);
+    }
+
+    // The program stays attached through the pin
+    drop(link);
+    let mut link = Link::open(path).expect("failed to open pinned link");
+    assert_eq!(link.pin_path(), Some(PathBuf::from(path)));
+
+    link.unpin().expect("failed to unpin link");
+    assert!(!Path::new(path).exists());
+}
+
+#[test]
+fn test_object_link_update() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("taskiter.bpf.o");
+    let prog = obj.prog_mut("dump_pid").expect("failed to find program");
+    let mut link = prog.attach().expect("failed to attach prog");
+    let handle = ProgramHandle::try_from(&*prog).expect("failed to create prog handle");
+    link.update(&handle).expect("failed to update link");
+    link.update(&*prog).expect("failed to update link");
+
+    // Tracing links cannot be updated
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let other = ProgramHandle::try_from(
+        obj.prog("handle__sched_wakeup_new")
+            .expect("failed to find program"),
+    )
+    .expect("failed to create prog handle");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
         .expect("failed to find program");
-    assert!(obj.prog("asdf").is_none());
+    let mut link = prog.attach().expect("failed to attach prog");
+    assert!(link.update(&other).is_err());
 }
 
 #[test]
-fn test_object_programs_iter_mut() {
+fn test_object_link_detach() {
     bump_rlimit_mlock();
 
-    let obj = get_test_object("runqslower.bpf.o");
-    assert!(obj.progs_iter().count() == 3);
+    let mut obj = get_test_object("taskiter.bpf.o");
+    let prog = obj.prog_mut("dump_pid").expect("failed to find program");
+    let link = prog.attach().expect("failed to attach prog");
+
+    // Iterators are not attached to anything that they could be detached from
+    assert!(matches!(link.detach(), Err(libbpf_rs::Error::System(_))));
 }
 
 #[test]
-fn test_object_program_pin() {
+fn test_object_link_info() {
     bump_rlimit_mlock();
 
     let mut obj = get_test_object("runqslower.bpf.o");
     let prog = obj
         .prog_mut("handle__sched_wakeup")
         .expect("failed to find program");
+    let prog_id = ProgramHandle::try_from(&*prog)
+        .expect("failed to create prog handle")
+        .info()
+        .expect("failed to query prog info")
+        .id;
+    let link = prog.attach().expect("failed to attach prog");
+
+    let info = link.info().expect("failed to query link info");
+    assert_eq!(info.prog_id, prog_id);
+    match info.info {
+        LinkTypeInfo::Tracing(tracing) => {
+            assert!(matches!(tracing.attach_type, ProgramAttachType::TraceRawTp))
+        }
+        _ => panic!("unexpected link type"),
+    }
 
-    let path = "/sys/fs/bpf/myprog";
+    let mut obj = get_test_object("taskiter.bpf.o");
+    let prog = obj.prog_mut("dump_pid").expect("failed to find program");
+    let link = prog.attach().expect("failed to attach prog");
+    assert!(matches!(
+        link.info().expect("failed to query link info").info,
+        LinkTypeInfo::Iter
+    ));
+}
 
-    // Unpinning a unpinned prog should be an error
-    assert!(prog.unpin(path).is_err());
-    assert!(!Path::new(path).exists());
+#[test]
+fn test_link_info_iter() {
+    bump_rlimit_mlock();
 
-    // Pin should be successful
-    prog.pin(path).expect("failed to pin prog");
-    assert!(Path::new(path).exists());
+    let mut obj = get_test_object("taskiter.bpf.o");
+    let prog = obj.prog_mut("dump_pid").expect("failed to find program");
+    let link = prog.attach().expect("failed to attach prog");
+    let info = link.info().expect("failed to query link info");
+
+    let found = LinkInfoIter::default()
+        .find(|link| link.id == info.id)
+        .expect("failed to find link");
+    assert_eq!(found.prog_id, info.prog_id);
+    assert!(matches!(found.info, LinkTypeInfo::Iter));
+}
 
-    // Backup cleanup method in case test errors
-    defer! {
-        let _ = fs::remove_file(path);
-    }
+#[test]
+fn test_link_from_id() {
+    bump_rlimit_mlock();
 
-    // Unpin should be successful
-    prog.unpin(path).expect("failed to unpin prog");
-    assert!(!Path::new(path).exists());
+    let mut obj = get_test_object("taskiter.bpf.o");
+    let prog = obj.prog_mut("dump_pid").expect("failed to find program");
+    let link = prog.attach().expect("failed to attach prog");
+    let info = link.info().expect("failed to query link info");
+
+    let by_id = Link::from_id(info.id).expect("failed to open link by id");
+    let by_id_info = by_id.info().expect("failed to query link info");
+    assert_eq!(by_id_info.id, info.id);
+    assert_eq!(by_id_info.prog_id, info.prog_id);
+
+    assert!(Link::from_id(u32::MAX).is_err());
 }
 
 #[test]
-fn test_object_link_pin() {
+fn test_link_into_fd() {
     bump_rlimit_mlock();
 
-    let mut obj = get_test_object("runqslower.bpf.o");
-    let prog = obj
-        .prog_mut("handle__sched_wakeup")
-        .expect("failed to find program");
-    let mut link = prog.attach().expect("failed to attach prog");
+    let mut obj = get_test_object("taskiter.bpf.o");
+    let prog = obj.prog_mut("dump_pid").expect("failed to find program");
+    let link = prog.attach().expect("failed to attach prog");
+    let id = link.info().expect("failed to query link info").id;
 
-    let path = "/sys/fs/bpf/mylink";
+    let _fd = link.into_fd().expect("failed to turn link into fd");
+    // The link lives on through the fd
+    assert!(Link::from_id(id).is_ok());
+}
 
-    // Unpinning a unpinned prog should be an error
-    assert!(link.unpin().is_err());
-    assert!(!Path::new(path).exists());
+#[test]
+fn test_object_reuse_fd() {
+    bump_rlimit_mlock();
 
-    // Pin should be successful
-    link.pin(path).expect("failed to pin prog");
-    assert!(Path::new(path).exists());
+    let mut first = get_test_object("runqslower.bpf.o");
 
-    // Backup cleanup method in case test errors
-    defer! {
-        let _ = fs::remove_file(path);
-    }
+    let obj_path = get_test_object_path("runqslower.bpf.o");
+    let mut builder = ObjectBuilder::default();
+    builder.debug(true);
+    let mut open_obj = builder.open_file(obj_path).expect("failed to open object");
+    let start = open_obj.map_mut("start").expect("failed to find map");
+    start
+        .reuse_fd(first.map("start").expect("failed to find map").fd())
+        .expect("failed to reuse map");
+    let mut second = open_obj.load().expect("failed to load object");
+
+    let key = 1u32.to_ne_bytes();
+    let val = 2u64.to_ne_bytes();
+    second
+        .map_mut("start")
+        .expect("failed to find map")
+        .update(&key, &val, MapFlags::empty())
+        .expect("failed to write");
 
-    // Unpin should be successful
-    link.unpin().expect("failed to unpin prog");
-    assert!(!Path::new(path).exists());
+    let found_val = first
+        .map_mut("start")
+        .expect("failed to find map")
+        .lookup(&key, MapFlags::empty())
+        .expect("failed to read map")
+        .expect("failed to find key");
+    assert_eq!(found_val, val);
 }
 
 #[test]
@@ -463,6 +2253,278 @@ fn test_object_reuse_pined_map() {
     assert!(!Path::new(path).exists());
 }
 
+#[test]
+fn test_object_perf_buffer_epoll_fd() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let map = obj.map("events").expect("failed to find map");
+    let perf = PerfBufferBuilder::new(map)
+        .sample_cb(|_cpu, _data| ())
+        .build()
+        .expect("failed to build perf buffer");
+
+    assert!(perf.epoll_fd().as_raw_fd() >= 0);
+    // Nothing was written, so nothing is ready
+    perf.poll(Duration::from_secs(0))
+        .expect("failed to poll perf buffer");
+    assert!(perf.stats().is_empty());
+}
+
+#[test]
+fn test_object_perf_buffer_consume() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let map = obj.map("events").expect("failed to find map");
+    let perf = PerfBufferBuilder::new(map)
+        .sample_cb(|_cpu, _data| panic!("unexpected sample"))
+        .build()
+        .expect("failed to build perf buffer");
+
+    // Returns right away even though nothing was written
+    perf.consume().expect("failed to consume perf buffer");
+}
+
+#[test]
+fn test_object_perf_buffer_wakeup() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let map = obj.map("events").expect("failed to find map");
+
+    let mut builder = PerfBufferBuilder::new(map);
+    builder.pages(8).wakeup_events(16);
+    let _perf = builder.build().expect("failed to build perf buffer");
+
+    let mut builder = PerfBufferBuilder::new(map);
+    builder.wakeup_watermark(4096);
+    let _perf = builder.build().expect("failed to build perf buffer");
+
+    let mut builder = PerfBufferBuilder::new(map);
+    builder.pages(3);
+    assert!(matches!(
+        builder.build(),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_object_perf_buffer_control() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let map = obj.map("events").expect("failed to find map");
+    let perf = PerfBufferBuilder::new(map)
+        .sample_cb_with_control(|_cpu, _data| ControlFlow::Break(()))
+        .build()
+        .expect("failed to build perf buffer");
+
+    perf.poll(Duration::from_millis(1))
+        .expect("failed to poll perf buffer");
+    perf.consume().expect("failed to consume perf buffer");
+}
+
+#[test]
+fn test_object_perf_buffer_run() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let map = obj.map("events").expect("failed to find map");
+    let perf = PerfBufferBuilder::new(map)
+        .build()
+        .expect("failed to build perf buffer");
+
+    let stop = Arc::new(AtomicBool::new(true));
+    perf.run(&stop).expect("failed to run perf buffer");
+
+    stop.store(false, Ordering::Relaxed);
+    let stop_clone = stop.clone();
+    let stopper = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(200));
+        stop_clone.store(true, Ordering::Relaxed);
+    });
+    perf.run(&stop).expect("failed to run perf buffer");
+    stopper.join().expect("failed to join thread");
+}
+
+#[test]
+fn test_object_perf_buffer_cpus() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let map = obj.map("events").expect("failed to find map");
+
+    let mut builder = PerfBufferBuilder::new(map);
+    builder.cpus(&[0]);
+    let perf = builder.build().expect("failed to build perf buffer");
+    perf.consume().expect("failed to consume perf buffer");
+
+    let mut builder = PerfBufferBuilder::new(map);
+    builder.cpus(&[]);
+    assert!(matches!(
+        builder.build(),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_object_perf_buffer_sample_limit() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let map = obj.map("events").expect("failed to find map");
+
+    let mut builder = PerfBufferBuilder::new(map);
+    builder.max_samples_per_poll(16);
+    let perf = builder.build().expect("failed to build perf buffer");
+    perf.poll(Duration::from_millis(1))
+        .expect("failed to poll perf buffer");
+    perf.consume().expect("failed to consume perf buffer");
+
+    let mut builder = PerfBufferBuilder::new(map);
+    builder.max_samples_per_poll(0);
+    assert!(matches!(
+        builder.build(),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_object_perf_buffer_send() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let map = obj.map("events").expect("failed to find map");
+    let (sender, _receiver) = channel();
+    let perf = PerfBufferBuilder::new(map)
+        .build_send(
+            move |cpu, data: &[u8]| {
+                sender.send((cpu, data.to_vec())).unwrap();
+                ControlFlow::Continue(())
+            },
+            |_cpu, _count| (),
+        )
+        .expect("failed to build perf buffer");
+
+    std::thread::spawn(move || {
+        perf.poll(Duration::from_millis(1))
+            .expect("failed to poll perf buffer")
+    })
+    .join()
+    .expect("failed to join thread");
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_object_perf_buffer_stream() {
+    use futures_core::Stream;
+    use std::pin::Pin;
+    use std::task::Poll;
+
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let map = obj.map("events").expect("failed to find map");
+    let perf = PerfBufferBuilder::new(map)
+        .build()
+        .expect("failed to build perf buffer");
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .expect("failed to build runtime");
+    rt.block_on(async {
+        let mut stream = perf.stream().expect("failed to create stream");
+        // Nothing was written, so there is nothing to read yet
+        let next =
+            std::future::poll_fn(|cx| Poll::Ready(Pin::new(&mut stream).poll_next(cx))).await;
+        assert!(next.is_pending());
+    });
+}
+
+#[test]
+fn test_object_perf_buffer_from_map_handle() {
+    bump_rlimit_mlock();
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let handle = MapHandle::try_from(obj.map("events").expect("failed to find map"))
+        .expect("failed to create map handle");
+    drop(obj);
+
+    let perf = PerfBufferBuilder::from_map_handle(handle)
+        .build()
+        .expect("failed to build perf buffer");
+    perf.consume().expect("failed to consume perf buffer");
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let handle = MapHandle::try_from(obj.map("start").expect("failed to find map"))
+        .expect("failed to create map handle");
+    assert!(matches!(
+        PerfBufferBuilder::from_map_handle(handle).build(),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_object_typed_perf_buffer() {
+    bump_rlimit_mlock();
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct Event {
+        pid: u32,
+        delta_us: u64,
+    }
+    unsafe impl Plain for Event {}
+
+    let obj = get_test_object("runqslower.bpf.o");
+    let map = obj.map("events").expect("failed to find map");
+    let perf = TypedPerfBuffer::new(PerfBufferBuilder::new(map), |_cpu, _event: &Event| ())
+        .expect("failed to build perf buffer");
+
+    perf.consume().expect("failed to consume perf buffer");
+    assert_eq!(perf.short_samples(), 0);
+}
+
+#[test]
+fn test_user_ringbuf() {
+    bump_rlimit_mlock();
+
+    let map = MapHandle::create(
+        MapType::UserRingBuf,
+        "test_user_rb",
+        0,
+        0,
+        4096,
+        &MapCreateOpts::default(),
+    )
+    .expect("failed to create map");
+
+    let rb = UserRingBuffer::new(&map).expect("failed to create user ring buffer");
+    let mut sample = rb.reserve(8).expect("failed to reserve sample");
+    assert_eq!(&*sample, &[0; 8]);
+    sample.copy_from_slice(&42u64.to_ne_bytes());
+    rb.submit(sample).expect("failed to submit sample");
+
+    let sample = rb.reserve(16).expect("failed to reserve sample");
+    rb.discard(sample);
+
+    // Nothing drains the buffer, so it eventually fills up
+    assert!(rb.reserve(8192).is_err());
+
+    // Sizes libbpf cannot represent are rejected rather than truncated
+    #[cfg(target_pointer_width = "64")]
+    assert!(matches!(
+        rb.reserve((1 << 32) + 8),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+
+    let mut obj = get_test_object("ringbuf.bpf.o");
+    let ringbuf1 = obj.map_mut("ringbuf1").expect("failed to find map");
+    assert!(UserRingBuffer::new(ringbuf1).is_err());
+}
+
 #[test]
 fn test_object_ringbuf() {
     bump_rlimit_mlock();
@@ -613,6 +2675,166 @@ fn test_object_ringbuf_closure() {
     assert_eq!(v2, 2);
 }
 
+#[test]
+fn test_object_ringbuf_control() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("ringbuf.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sys_enter_getpid")
+        .expect("failed to find program");
+    let _link = prog.attach().expect("failed to attach prog");
+
+    let map1 = obj.map("ringbuf1").expect("Failed to get ringbuf1 map");
+    let map2 = obj.map("ringbuf2").expect("Failed to get ringbuf2 map");
+
+    // Stopping at the first sample leaves the second ring buffer untouched
+    let (sender, receiver) = channel();
+    let mut builder = libbpf_rs::RingBufferBuilder::new();
+    builder
+        .add_with_control(map1, |_data| Ok(ControlFlow::Break(())))
+        .expect("Failed to add ringbuf");
+    builder
+        .add_with_control(map2, move |data| {
+            let mut value: i32 = 0;
+            plain::copy_from_bytes(&mut value, data).expect("Wrong size");
+            sender.send(value).expect("Failed to send value");
+            Ok(ControlFlow::Continue(()))
+        })
+        .expect("Failed to add ringbuf");
+    let mgr = builder.build().expect("Failed to build");
+
+    // Call getpid to ensure the BPF program runs
+    unsafe { libc::getpid() };
+
+    mgr.consume().expect("Failed to consume ringbuf");
+    assert!(receiver.try_recv().is_err());
+    drop(mgr);
+
+    // Errors of the callback are passed on
+    let mut builder = libbpf_rs::RingBufferBuilder::new();
+    builder
+        .add_with_control(map2, |_data| {
+            Err(libbpf_rs::Error::InvalidInput("stop".to_string()))
+        })
+        .expect("Failed to add ringbuf");
+    let mgr = builder.build().expect("Failed to build");
+
+    assert!(matches!(
+        mgr.consume(),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_object_ringbuf_stream() {
+    use futures_core::Stream;
+    use std::pin::Pin;
+
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("ringbuf.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sys_enter_getpid")
+        .expect("failed to find program");
+    let _link = prog.attach().expect("failed to attach prog");
+
+    let mut builder = libbpf_rs::RingBufferBuilder::new();
+    let map1 = obj.map("ringbuf1").expect("Failed to get ringbuf1 map");
+    builder.add(map1, |_data| 0).expect("Failed to add ringbuf");
+    let map2 = obj.map("ringbuf2").expect("Failed to get ringbuf2 map");
+    builder.add(map2, |_data| 0).expect("Failed to add ringbuf");
+    let mgr = builder.build().expect("Failed to build");
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .expect("failed to build runtime");
+    rt.block_on(async {
+        let mut stream = mgr.stream().expect("failed to create stream");
+
+        // Call getpid to ensure the BPF program runs
+        unsafe { libc::getpid() };
+
+        let sample = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx))
+            .await
+            .expect("stream ended")
+            .expect("failed to read sample");
+        let mut value: i32 = 0;
+        plain::copy_from_bytes(&mut value, &sample.data).expect("Wrong size");
+        assert_eq!(value, sample.ring as i32 + 1);
+    });
+}
+
+#[test]
+fn test_object_ringbuf_epoll_fd() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("ringbuf.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sys_enter_getpid")
+        .expect("failed to find program");
+    let _link = prog.attach().expect("failed to attach prog");
+
+    let (sender, receiver) = channel();
+    let mut builder = libbpf_rs::RingBufferBuilder::new();
+    let map = obj.map("ringbuf1").expect("Failed to get ringbuf1 map");
+    builder
+        .add(map, move |data: &[u8]| -> i32 {
+            sender.send(data.len()).expect("Failed to send value");
+            0
+        })
+        .expect("Failed to add ringbuf");
+    let mgr = builder.build().expect("Failed to build");
+
+    let epoll_fd = mgr.epoll_fd().as_raw_fd();
+    assert!(epoll_fd >= 0);
+
+    // Call getpid to ensure the BPF program runs
+    unsafe { libc::getpid() };
+
+    // Wait on the epoll fd ourselves, like an external event loop would
+    let mut event = libc::epoll_event { events: 0, u64: 0 };
+    let ret = unsafe { libc::epoll_wait(epoll_fd, &mut event, 1, 100) };
+    assert_eq!(ret, 1);
+
+    mgr.consume().expect("Failed to consume ringbuf");
+    assert!(receiver.try_recv().is_ok());
+}
+
+#[test]
+fn test_object_task_iter_process() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("taskiter.bpf.o");
+    let prog = obj.prog_mut("dump_pid").expect("Failed to find program");
+    let pid = std::process::id();
+    let link = prog
+        .attach_iter(&IterOpts::Process(pid))
+        .expect("Failed to attach prog");
+    let mut iter = Iter::new(&link).expect("Failed to create iterator");
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct IndexPidPair {
+        i: u32,
+        pid: i32,
+    }
+
+    unsafe impl Plain for IndexPidPair {}
+
+    let mut buf = Vec::new();
+    iter.read_to_end(&mut buf)
+        .expect("Failed to read from iterator");
+    let items: &[IndexPidPair] =
+        plain::slice_from_bytes(buf.as_slice()).expect("Input slice cannot satisfy length");
+
+    // Only the threads of this process are visited
+    assert!(!items.is_empty());
+    assert!(items.iter().all(|item| item.pid == pid as i32));
+}
+
 #[test]
 fn test_object_task_iter() {
     bump_rlimit_mlock();